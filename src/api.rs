@@ -0,0 +1,1232 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use chrono::Utc;
+use clap::ValueEnum;
+use futures_util::{StreamExt, stream};
+use html_escape::decode_html_entities;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    config::CategoryWeights,
+    db::{Database, Fetch, StoreSummary, Torrent, release_type_name},
+    error::{ErrorAction, RedmanError, Result},
+    library::{most_played_artists, normalize},
+    metrics::{self, Counter},
+    selection::preferred_torrent,
+    shutdown,
+    sources::{Sources, resolve_releases},
+    tracker::TrackerClient,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Type {
+    Collage,
+    Artist,
+    /// Recommendations of the `[listenbrainz]` user; takes no id
+    Listenbrainz,
+    /// A Spotify playlist, added with `--spotify-playlist`
+    Spotify,
+    /// The wanted list of the `[lidarr]` server; takes no id
+    Lidarr,
+    /// The wantlist of the `[discogs]` user; takes no id
+    Discogs,
+    /// Uploads from the `[rss]` feed, pooled by `redman rss`; takes no id
+    Rss,
+    /// Uploads announced on IRC, pooled by `redman irc`; takes no id
+    Irc,
+}
+
+impl Type {
+    /// Value stored in the `type` column of the `fetches` table.
+    pub fn code(&self) -> u32 {
+        match self {
+            Type::Artist => 0,
+            Type::Collage => 1,
+            Type::Listenbrainz => 2,
+            Type::Spotify => 3,
+            Type::Lidarr => 4,
+            Type::Discogs => 5,
+            Type::Rss => 6,
+            Type::Irc => 7,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(Type::Artist),
+            1 => Some(Type::Collage),
+            2 => Some(Type::Listenbrainz),
+            3 => Some(Type::Spotify),
+            4 => Some(Type::Lidarr),
+            5 => Some(Type::Discogs),
+            6 => Some(Type::Rss),
+            7 => Some(Type::Irc),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Collage => write!(f, "collage"),
+            Type::Artist => write!(f, "artist"),
+            Type::Listenbrainz => write!(f, "listenbrainz"),
+            Type::Spotify => write!(f, "spotify"),
+            Type::Lidarr => write!(f, "lidarr"),
+            Type::Discogs => write!(f, "discogs"),
+            Type::Rss => write!(f, "rss"),
+            Type::Irc => write!(f, "irc"),
+        }
+    }
+}
+
+/// Kinds of collages on Gazelle trackers.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollageCategory {
+    Personal,
+    Theme,
+    GenreIntroductions,
+    Discography,
+    Label,
+    StaffPicks,
+    Charts,
+    Artists,
+    Awards,
+    Series,
+}
+
+impl CollageCategory {
+    /// Number of the category on the site.
+    pub fn code(&self) -> u32 {
+        match self {
+            CollageCategory::Personal => 0,
+            CollageCategory::Theme => 1,
+            CollageCategory::GenreIntroductions => 2,
+            CollageCategory::Discography => 3,
+            CollageCategory::Label => 4,
+            CollageCategory::StaffPicks => 5,
+            CollageCategory::Charts => 6,
+            CollageCategory::Artists => 7,
+            CollageCategory::Awards => 8,
+            CollageCategory::Series => 9,
+        }
+    }
+}
+
+impl std::fmt::Display for CollageCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            CollageCategory::Personal => "Personal",
+            CollageCategory::Theme => "Theme",
+            CollageCategory::GenreIntroductions => "Genre Introductions",
+            CollageCategory::Discography => "Discography",
+            CollageCategory::Label => "Label",
+            CollageCategory::StaffPicks => "Staff picks",
+            CollageCategory::Charts => "Charts",
+            CollageCategory::Artists => "Artists",
+            CollageCategory::Awards => "Awards",
+            CollageCategory::Series => "Series",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A page of collages as answered by `action=collages`.
+#[derive(Debug, Deserialize)]
+pub struct CollagePage {
+    #[serde(default)]
+    pub pages: u32,
+    #[serde(deserialize_with = "skip_invalid")]
+    pub results: Vec<CollageSummary>,
+}
+
+/// The collages the user subscribed to, as answered by `action=subscriptions`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct CollageSubscriptions {
+    #[serde(alias = "bookmarks", deserialize_with = "skip_invalid")]
+    pub(crate) collages: Vec<CollageSummary>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CollageSummary {
+    pub id: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CollageData {
+    pub id: u32,
+    pub name: String,
+    #[serde(rename = "collageCategoryName")]
+    pub collage_category_name: String,
+    #[serde(rename = "torrentgroups", deserialize_with = "skip_invalid")]
+    pub torrent_groups: Vec<TorrentGroupCollage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArtistData {
+    pub id: u32,
+    pub name: String,
+    #[serde(alias = "torrentgroup", deserialize_with = "skip_invalid")]
+    pub torrent_groups: Vec<TorrentGroupArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+pub enum GroupData {
+    CollageData(CollageData),
+    ArtistData(ArtistData),
+    #[serde(skip)]
+    Resolved(ResolvedData),
+}
+
+/// Releases suggested by a service besides the tracker, matched to torrents
+/// by search.
+#[derive(Debug)]
+pub struct ResolvedData {
+    pub ftype: Type,
+    pub id: u32,
+    pub name: String,
+    /// What the service looks the releases up by, if not the config
+    pub source: Option<String>,
+    pub torrents: Vec<Torrent>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TorrentGroupCollage {
+    #[serde(default, deserialize_with = "optional_number")]
+    id: Option<u32>,
+    name: String,
+    #[serde(default, deserialize_with = "optional_number")]
+    year: Option<u32>,
+    #[serde(alias = "releaseType", deserialize_with = "number_or_string")]
+    release_type: u32,
+    #[serde(rename = "musicInfo")]
+    music_info: MusicInfo,
+    #[serde(rename = "wikiImage", default)]
+    wiki_image: Option<String>,
+    #[serde(deserialize_with = "skip_invalid")]
+    torrents: Vec<TorrentApi>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TorrentGroupArtist {
+    #[serde(alias = "groupId", default, deserialize_with = "optional_number")]
+    id: Option<u32>,
+    #[serde(alias = "groupName")]
+    name: String,
+    #[serde(alias = "groupYear", default, deserialize_with = "optional_number")]
+    year: Option<u32>,
+    #[serde(alias = "releaseType")]
+    release_type: u32,
+    #[serde(rename = "wikiImage", default)]
+    wiki_image: Option<String>,
+    #[serde(alias = "torrent", deserialize_with = "skip_invalid")]
+    torrents: Vec<TorrentApi>,
+    /// Everyone credited on the group, under the name used there
+    #[serde(rename = "extendedArtists", default, deserialize_with = "credits")]
+    credits: Vec<Credit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Credit {
+    #[serde(default, deserialize_with = "optional_number")]
+    id: Option<u32>,
+    name: String,
+}
+
+impl ArtistData {
+    /// Other names the artist is credited under on its groups, such as "AFX"
+    /// for Aphex Twin.
+    pub fn aliases(&self) -> Vec<String> {
+        self.torrent_groups
+            .iter()
+            .flat_map(|g| &g.credits)
+            .filter(|c| c.id == Some(self.id) && normalize(&c.name) != normalize(&self.name))
+            .map(|c| decode_html_entities(&c.name).to_string())
+            .unique_by(|name| normalize(name))
+            .collect()
+    }
+}
+
+/// A torrent and its group as answered by `action=torrent`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct TorrentDetails {
+    group: TorrentDetailsGroup,
+    torrent: TorrentApi,
+}
+
+#[derive(Debug, Deserialize)]
+struct TorrentDetailsGroup {
+    #[serde(default, deserialize_with = "optional_number")]
+    id: Option<u32>,
+    name: String,
+    #[serde(default, deserialize_with = "optional_number")]
+    year: Option<u32>,
+    #[serde(rename = "releaseType", deserialize_with = "number_or_string")]
+    release_type: u32,
+    #[serde(rename = "musicInfo")]
+    music_info: MusicInfo,
+    #[serde(rename = "wikiImage", default)]
+    wiki_image: Option<String>,
+}
+
+/// A group and all its torrents as answered by `action=torrentgroup`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct GroupDetails {
+    group: TorrentDetailsGroup,
+    #[serde(deserialize_with = "skip_invalid")]
+    torrents: Vec<TorrentApi>,
+}
+
+/// Groups matching an `action=browse` search.
+#[derive(Debug, Deserialize)]
+pub(crate) struct SearchResults {
+    #[serde(deserialize_with = "skip_invalid")]
+    results: Vec<SearchGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchGroup {
+    #[serde(rename = "groupId", default, deserialize_with = "optional_number")]
+    id: Option<u32>,
+    #[serde(rename = "groupName")]
+    name: String,
+    artist: String,
+    #[serde(rename = "groupYear", default, deserialize_with = "optional_number")]
+    year: Option<u32>,
+    #[serde(rename = "releaseType", deserialize_with = "release_type")]
+    release_type: u32,
+    #[serde(default)]
+    cover: Option<String>,
+    #[serde(deserialize_with = "skip_invalid")]
+    torrents: Vec<TorrentApi>,
+}
+
+impl SearchResults {
+    pub(crate) fn into_torrents(self) -> Vec<Torrent> {
+        self.results
+            .iter()
+            .flat_map(|g| {
+                g.torrents.iter().map(|t| Torrent {
+                    id: t.torrent_id,
+                    album_name: decode_html_entities(&g.name).to_string(),
+                    artist_names: decode_html_entities(&g.artist).to_string(),
+                    group_id: g.id,
+                    year: g.year,
+                    release_type: g.release_type,
+                    media: t.media.clone(),
+                    format: t.format.clone(),
+                    encoding: t.encoding.clone(),
+                    file_count: t.file_count,
+                    weight: 0,
+                    size: t.size,
+                    created_at: Utc::now().naive_utc(),
+                    cover_url: cover_url(&g.cover),
+                })
+            })
+            .collect()
+    }
+}
+
+impl TorrentDetailsGroup {
+    fn torrent(&self, t: &TorrentApi) -> Torrent {
+        let artist_names = self
+            .music_info
+            .artists
+            .iter()
+            .map(|a| decode_html_entities(&a.name).to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Torrent {
+            id: t.torrent_id,
+            album_name: decode_html_entities(&self.name).to_string(),
+            artist_names,
+            group_id: self.id,
+            year: self.year,
+            release_type: self.release_type,
+            media: t.media.clone(),
+            format: t.format.clone(),
+            encoding: t.encoding.clone(),
+            file_count: t.file_count,
+            weight: 0,
+            size: t.size,
+            created_at: Utc::now().naive_utc(),
+            cover_url: cover_url(&self.wiki_image),
+        }
+    }
+}
+
+impl TorrentDetails {
+    pub(crate) fn into_torrent(self) -> Torrent {
+        self.group.torrent(&self.torrent)
+    }
+}
+
+impl GroupDetails {
+    pub(crate) fn into_torrents(self) -> Vec<Torrent> {
+        self.torrents
+            .iter()
+            .map(|t| self.group.torrent(t))
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MusicInfo {
+    artists: Vec<Artist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TorrentApi {
+    #[serde(
+        alias = "torrentid",
+        alias = "torrentId",
+        alias = "id",
+        deserialize_with = "number_or_string"
+    )]
+    torrent_id: u32,
+    media: String,
+    format: String,
+    encoding: String,
+    #[serde(rename = "fileCount")]
+    file_count: u32,
+    size: u64,
+}
+
+/// Numeric fields that Redacted sends as strings and Orpheus as numbers.
+fn number_or_string<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(u32),
+        String(String),
+    }
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => Ok(n),
+        Value::String(s) => s.trim().parse().map_err(serde::de::Error::custom),
+    }
+}
+
+/// Release years and group ids, where a missing, empty or zero value means it
+/// is unknown.
+fn optional_number<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(u32),
+        String(String),
+    }
+    let number = match Option::<Value>::deserialize(deserializer)? {
+        None => None,
+        Some(Value::Number(n)) => Some(n),
+        Some(Value::String(s)) if s.trim().is_empty() => None,
+        Some(Value::String(s)) => Some(s.trim().parse().map_err(serde::de::Error::custom)?),
+    };
+    Ok(number.filter(|&n| n != 0))
+}
+
+/// Credits by role, as in `{"1": [{"id": 1, "name": "…"}], "2": null}`. Groups
+/// without any have `false` or nothing at all.
+fn credits<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<Credit>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Roles(HashMap<String, Option<Vec<Credit>>>),
+        Other(serde::de::IgnoredAny),
+    }
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Roles(roles) => roles.into_values().flatten().flatten().collect(),
+        Value::Other(_) => Vec::new(),
+    })
+}
+
+/// Release types that search results give by name, e.g. "Album", and other
+/// answers by code. Unknown names count as "Unknown".
+fn release_type<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(u32),
+        Text(String),
+    }
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(n) => n,
+        Value::Text(s) => s.parse().unwrap_or_else(|_| {
+            (1..=21)
+                .find(|&code| release_type_name(code) == Some(s.as_str()))
+                .unwrap_or(21)
+        }),
+    })
+}
+
+/// Deserialize a list entry by entry, skipping the ones that do not parse with a
+/// warning so one odd group or torrent does not fail the whole response.
+fn skip_invalid<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let values = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    let total = values.len();
+    let items = values
+        .into_iter()
+        .filter_map(|value| {
+            serde_json::from_value(value.clone())
+                .inspect_err(|e| {
+                    warn!(
+                        "Skipping malformed entry: {}: {}",
+                        e,
+                        snippet(&value.to_string())
+                    )
+                })
+                .ok()
+        })
+        .collect::<Vec<T>>();
+    if items.len() < total {
+        warn!(
+            skipped = total - items.len(),
+            total, "Some entries could not be read"
+        );
+    }
+    Ok(items)
+}
+
+/// Default `User-Agent` sent with every tracker request.
+pub const USER_AGENT: &str = concat!("redman/", env!("CARGO_PKG_VERSION"));
+
+/// Tracker page of a torrent. Without the group id the tracker redirects from
+/// the torrent id alone.
+pub fn permalink(base_url: &str, group_id: Option<u32>, torrent_id: u32) -> String {
+    match group_id {
+        Some(group_id) => format!(
+            "{}torrents.php?id={}&torrentid={}",
+            base_url, group_id, torrent_id
+        ),
+        None => format!("{}torrents.php?torrentid={}", base_url, torrent_id),
+    }
+}
+
+/// What a tracker page link points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permalink {
+    Torrent(u32),
+    Group(u32),
+}
+
+impl Permalink {
+    /// Read a `torrents.php` link on the tracker at `base_url`, as made by
+    /// [`permalink`] or copied from the browser. A torrent id wins over the
+    /// group id.
+    pub fn parse(base_url: &str, link: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            RedmanError::InvalidInput(format!(
+                "Not a torrent or group link: {} ({})",
+                link, reason
+            ))
+        };
+        let url = tracker_url(base_url, link, invalid)?;
+        if !url.path().ends_with("/torrents.php") {
+            return Err(invalid("expected a torrents.php page"));
+        }
+        let query = |key: &str| query_id(&url, key, invalid);
+        match (query("torrentid")?, query("id")?) {
+            (Some(id), _) => Ok(Permalink::Torrent(id)),
+            (None, Some(id)) => Ok(Permalink::Group(id)),
+            (None, None) => Err(invalid("it has no id")),
+        }
+    }
+}
+
+/// A collage, artist or group page link, pasted to `fetch` in place of an id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageLink {
+    Collage(u32),
+    Artist(u32),
+    Group(u32),
+}
+
+impl PageLink {
+    /// Read a `collages.php`, `artist.php` or `torrents.php` link on the
+    /// tracker at `base_url`.
+    pub fn parse(base_url: &str, link: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            RedmanError::InvalidInput(format!(
+                "Not a collage, artist or group link: {} ({})",
+                link, reason
+            ))
+        };
+        let url = tracker_url(base_url, link, invalid)?;
+        let page = url.path().rsplit('/').next().unwrap_or_default();
+        let id = query_id(&url, "id", invalid)?.ok_or_else(|| invalid("it has no id"));
+        match page {
+            "collages.php" => Ok(PageLink::Collage(id?)),
+            "artist.php" => Ok(PageLink::Artist(id?)),
+            "torrents.php" => Ok(PageLink::Group(id?)),
+            _ => Err(invalid(
+                "expected a collages.php, artist.php or torrents.php page",
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for PageLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PageLink::Collage(id) => write!(f, "collage {}", id),
+            PageLink::Artist(id) => write!(f, "artist {}", id),
+            PageLink::Group(id) => write!(f, "group {}", id),
+        }
+    }
+}
+
+/// `link` as a URL, if it is on the tracker at `base_url`.
+fn tracker_url(base_url: &str, link: &str, invalid: impl Fn(&str) -> RedmanError) -> Result<Url> {
+    let url = Url::parse(link).map_err(|e| invalid(&e.to_string()))?;
+    let base = Url::parse(base_url).map_err(|e| invalid(&e.to_string()))?;
+    if url.host_str() != base.host_str() {
+        return Err(RedmanError::InvalidInput(format!(
+            "{} is not on the tracker at {}; pick another with --tracker or --base-url",
+            link, base_url
+        )));
+    }
+    Ok(url)
+}
+
+/// The number in query parameter `key` of `url`, if there is one.
+fn query_id(url: &Url, key: &str, invalid: impl Fn(&str) -> RedmanError) -> Result<Option<u32>> {
+    url.query_pairs()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| {
+            v.parse::<u32>()
+                .map_err(|_| invalid("the id is not a number"))
+        })
+        .transpose()
+}
+
+/// The id of the `ftype` fetch `link` points at. For a group that is its
+/// first artist, looked up by name.
+pub async fn resolve_page_link(
+    api: &impl TrackerClient,
+    ftype: Type,
+    link: PageLink,
+) -> Result<u32> {
+    match (ftype, link) {
+        (Type::Collage, PageLink::Collage(id)) | (Type::Artist, PageLink::Artist(id)) => Ok(id),
+        (Type::Artist, PageLink::Group(group)) => {
+            let torrents = api.fetch_torrent_group(group).await?;
+            let Some(names) = torrents.first().map(|t| &t.artist_names) else {
+                return Err(RedmanError::InvalidInput(format!(
+                    "Group {} has no torrents to tell its artist by",
+                    group
+                )));
+            };
+            let name = names.split(", ").next().unwrap_or(names);
+            let artist = api.fetch_artist_by_name(name).await?;
+            info!(
+                group,
+                "Artist of the group is {} ({})", artist.name, artist.id
+            );
+            Ok(artist.id)
+        }
+        (ftype, link) => Err(RedmanError::InvalidInput(format!(
+            "Cannot fetch {} as {}",
+            link, ftype
+        ))),
+    }
+}
+
+/// The torrent `link` points at; for a group, the one [`preferred_torrent`]
+/// picks among all of its torrents.
+pub async fn resolve_permalink(api: &impl TrackerClient, link: Permalink) -> Result<u32> {
+    match link {
+        Permalink::Torrent(id) => Ok(id),
+        Permalink::Group(id) => {
+            let torrents = api.fetch_torrent_group(id).await?;
+            let t = preferred_torrent(&torrents).ok_or_else(|| {
+                RedmanError::InvalidInput(format!(
+                    "Group {} has no CD or WEB MP3 torrent in V0 or 320",
+                    id
+                ))
+            })?;
+            info!(
+                group = id,
+                id = t.id,
+                "Picked {} {} {}",
+                t.media,
+                t.format,
+                t.encoding
+            );
+            Ok(t.id)
+        }
+    }
+}
+
+/// Base URL used when neither `--base-url` nor a tracker profile gives one.
+pub const DEFAULT_BASE_URL: &str = "https://redacted.sh/";
+
+/// Weight of a fetch when neither `--weight` nor `[category_weights]` gives one.
+pub const DEFAULT_WEIGHT: u32 = 10;
+
+const SNIPPET_LEN: usize = 200;
+pub const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+pub const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApiStatus {
+    pub(crate) status: String,
+    pub(crate) error: Option<String>,
+}
+
+pub(crate) fn content_type(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// Collapse whitespace and truncate a response body for use in error messages.
+pub(crate) fn snippet(body: &str) -> String {
+    let collapsed = body.split_whitespace().join(" ");
+    if collapsed.chars().count() > SNIPPET_LEN {
+        format!(
+            "{}…",
+            collapsed.chars().take(SNIPPET_LEN).collect::<String>()
+        )
+    } else {
+        collapsed
+    }
+}
+
+/// A tracker response body read to completion.
+pub(crate) struct RawResponse {
+    pub(crate) status: reqwest::StatusCode,
+    pub(crate) content_type: String,
+    pub(crate) body: String,
+}
+
+/// Deserialize a tracker response, reporting HTML error pages and Cloudflare
+/// challenges with a body snippet instead of an opaque serde error.
+pub(crate) fn parse_json<T: DeserializeOwned>(raw: &RawResponse) -> Result<T> {
+    let RawResponse {
+        status,
+        content_type,
+        body,
+    } = raw;
+    if let Some(e) = RedmanError::from_status(*status) {
+        return Err(e);
+    }
+    if !content_type.contains("json") {
+        return Err(RedmanError::ApiError(format!(
+            "Tracker returned a non-JSON response ({}, {}): {}",
+            status,
+            content_type,
+            snippet(body)
+        )));
+    }
+    if let Ok(s) = serde_json::from_str::<ApiStatus>(body)
+        && s.status != "success"
+    {
+        return Err(RedmanError::from_message(
+            s.error.as_deref().unwrap_or(&s.status),
+        ));
+    }
+    serde_json::from_str(body).map_err(|e| {
+        RedmanError::ApiError(format!(
+            "Could not parse tracker response ({}): {}: {}",
+            status,
+            e,
+            snippet(body)
+        ))
+    })
+}
+
+/// Fetch the collage or artist `id`, or the releases `sources` suggest for
+/// the other types. Spotify fetches read the playlist `source`.
+pub async fn fetch_data(
+    api: &impl TrackerClient,
+    id: u32,
+    ftype: Type,
+    source: Option<&str>,
+    sources: &Sources,
+) -> Result<GroupData> {
+    Ok(match ftype {
+        Type::Artist => {
+            let artist = api.fetch_artist(id).await?;
+            if artist.id != id {
+                info!("Artist {} redirects to {} ({})", id, artist.name, artist.id);
+            }
+            GroupData::ArtistData(artist)
+        }
+        Type::Collage => GroupData::CollageData(api.fetch_collage(id).await?),
+        Type::Listenbrainz => {
+            let Some(listenbrainz) = &sources.listenbrainz else {
+                return Err(RedmanError::ServiceError(
+                    "Set user in [listenbrainz] to fetch recommendations".to_string(),
+                ));
+            };
+            let releases = listenbrainz.recommended_releases().await?;
+            GroupData::Resolved(ResolvedData {
+                ftype,
+                id: 0,
+                name: listenbrainz.user.clone(),
+                source: None,
+                torrents: resolve_releases(api, &releases).await?,
+            })
+        }
+        Type::Spotify => {
+            let Some(spotify) = &sources.spotify else {
+                return Err(RedmanError::ServiceError(
+                    "Set client_id and client_secret in [spotify] to read playlists".to_string(),
+                ));
+            };
+            let Some(playlist) = source else {
+                return Err(RedmanError::ServiceError(
+                    "Pass the playlist with --spotify-playlist".to_string(),
+                ));
+            };
+            let (name, albums) = spotify.playlist_albums(playlist).await?;
+            GroupData::Resolved(ResolvedData {
+                ftype,
+                id,
+                name,
+                source: Some(playlist.to_string()),
+                torrents: resolve_releases(api, &albums).await?,
+            })
+        }
+        Type::Lidarr => {
+            let Some(lidarr) = &sources.lidarr else {
+                return Err(RedmanError::ServiceError(
+                    "Set url and api_key in [lidarr] to read its wanted list".to_string(),
+                ));
+            };
+            let albums = lidarr.wanted_albums().await?;
+            GroupData::Resolved(ResolvedData {
+                ftype,
+                id: 0,
+                name: lidarr.url.clone(),
+                source: None,
+                torrents: resolve_releases(api, &albums).await?,
+            })
+        }
+        Type::Discogs => {
+            let Some(discogs) = &sources.discogs else {
+                return Err(RedmanError::ServiceError(
+                    "Set user in [discogs] to read the wantlist".to_string(),
+                ));
+            };
+            let releases = discogs.wanted_releases().await?;
+            GroupData::Resolved(ResolvedData {
+                ftype,
+                id: 0,
+                name: discogs.user.clone(),
+                source: None,
+                torrents: resolve_releases(api, &releases).await?,
+            })
+        }
+        Type::Rss | Type::Irc => {
+            return Err(RedmanError::ServiceError(format!(
+                "{} uploads are pooled by `redman {}` as they appear",
+                ftype, ftype
+            )));
+        }
+    })
+}
+
+/// Fetch the discographies of the `top` most played artists in the Plex
+/// database at `plex_db`. The most played artist gets `max_weight`, the others
+/// a share of it by play count, at least 1.
+pub async fn fetch_plex_artists(
+    api: &impl TrackerClient,
+    db: &Database,
+    plex_db: &str,
+    top: usize,
+    max_weight: u32,
+) -> Result<StoreSummary> {
+    let artists = most_played_artists(plex_db, top)?;
+    let most_plays = artists.first().map_or(1, |(_, plays)| *plays);
+    let mut summary = StoreSummary::default();
+    for (name, plays) in artists {
+        if shutdown::requested() {
+            warn!("Fetch interrupted");
+            break;
+        }
+        let weight = (u64::from(max_weight) * plays).div_ceil(most_plays).max(1) as u32;
+        info!(artist = %name, plays, weight, "Fetching artist");
+        match api.fetch_artist_by_name(&name).await {
+            Ok(data) => summary += db.store_data(&GroupData::ArtistData(data), weight)?,
+            Err(e) if e.action() == ErrorAction::Skip => {
+                warn!("Skipping artist {}: {}", name, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(summary)
+}
+
+/// Fetch up to `limit` collages of `category` that are not stored yet, the
+/// biggest first, each with `weight` or else the weight of the category.
+pub async fn fetch_category(
+    api: &impl TrackerClient,
+    db: &Database,
+    category: CollageCategory,
+    limit: usize,
+    weight: Option<u32>,
+    weights: &CategoryWeights,
+) -> Result<StoreSummary> {
+    let stored = stored_collages(db)?;
+    let mut collages = Vec::new();
+    for page in 1.. {
+        let results = api.browse_collages(category, page).await?;
+        collages.extend(
+            results
+                .results
+                .into_iter()
+                .filter(|c| !stored.contains(&c.id)),
+        );
+        if collages.len() >= limit || page >= results.pages {
+            break;
+        }
+    }
+    collages.truncate(limit);
+    info!(%category, collages = collages.len(), "Found collages to fetch");
+    fetch_collages(api, db, &collages, weight, weights).await
+}
+
+/// Fetch the collages the user subscribed to on the tracker that are not
+/// stored yet, each with `weight` or else the weight of its category, so they
+/// are synced from then on.
+pub async fn sync_subscriptions(
+    api: &impl TrackerClient,
+    db: &Database,
+    weight: Option<u32>,
+    weights: &CategoryWeights,
+) -> Result<StoreSummary> {
+    let stored = stored_collages(db)?;
+    let collages: Vec<CollageSummary> = api
+        .subscribed_collages()
+        .await?
+        .into_iter()
+        .filter(|c| !stored.contains(&c.id))
+        .collect();
+    info!(collages = collages.len(), "New subscribed collages");
+    fetch_collages(api, db, &collages, weight, weights).await
+}
+
+fn stored_collages(db: &Database) -> Result<HashSet<u32>> {
+    Ok(db
+        .get_fetches()?
+        .into_iter()
+        .filter(|f| matches!(f.ftype, Type::Collage))
+        .map(|f| f.id)
+        .collect())
+}
+
+/// Send `request` again after [`RATE_LIMIT_BACKOFF`] while the tracker rate
+/// limits it, up to [`MAX_RATE_LIMIT_RETRIES`] times.
+pub async fn retry_rate_limited<T, F: Future<Output = Result<T>>>(
+    mut request: impl FnMut() -> F,
+) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        match request().await {
+            Err(e) if e.action() == ErrorAction::Retry && retries < MAX_RATE_LIMIT_RETRIES => {
+                retries += 1;
+                warn!(
+                    "Rate limited, retrying in {}",
+                    humantime::format_duration(RATE_LIMIT_BACKOFF)
+                );
+                metrics::add(Counter::RateLimitWaits, 1);
+                tokio::time::sleep(RATE_LIMIT_BACKOFF).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Fetch and store `collages`, up to [`TrackerClient::jobs`] at a time.
+async fn fetch_collages(
+    api: &impl TrackerClient,
+    db: &Database,
+    collages: &[CollageSummary],
+    weight: Option<u32>,
+    weights: &CategoryWeights,
+) -> Result<StoreSummary> {
+    let mut summary = StoreSummary::default();
+    let mut results = stream::iter(collages)
+        .map(|c| async move {
+            if shutdown::requested() {
+                return None;
+            }
+            info!(
+                "Fetching collage {} ({})",
+                c.id,
+                decode_html_entities(&c.name)
+            );
+            Some(retry_rate_limited(|| api.fetch_collage(c.id)).await)
+        })
+        .buffered(api.jobs());
+    for c in collages {
+        let Some(Some(result)) = results.next().await else {
+            warn!("Fetch interrupted");
+            break;
+        };
+        match result {
+            Ok(data) => {
+                let data = GroupData::CollageData(data);
+                summary += db.store_data(&data, weights.weight(weight, &data))?;
+            }
+            Err(e) if e.action() == ErrorAction::Skip => {
+                warn!("Skipping collage {}: {}", c.id, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(summary)
+}
+
+/// Re-fetch every recorded fetch with its stored weight, up to
+/// [`TrackerClient::jobs`] at a time.
+pub async fn sync_fetches(
+    api: &impl TrackerClient,
+    db: &Database,
+    sources: &Sources,
+) -> Result<StoreSummary> {
+    let mut summary = StoreSummary::default();
+    let fetches: Vec<Fetch> = db
+        .get_fetches()?
+        .into_iter()
+        // Polled on their own schedule, and only ever for new uploads
+        .filter(|f| !matches!(f.ftype, Type::Rss | Type::Irc))
+        .collect();
+    // Up to `jobs` fetches are in flight, stored one by one in their order
+    let mut results = stream::iter(&fetches)
+        .map(|f| async move {
+            if shutdown::requested() {
+                return None;
+            }
+            info!("Syncing {} {} ({})", f.ftype, f.id, f.name);
+            let result =
+                retry_rate_limited(|| fetch_data(api, f.id, f.ftype, f.source.as_deref(), sources))
+                    .await;
+            tokio::time::sleep(Duration::from_millis(150)).await; // Do not spam redacted API
+            Some(result)
+        })
+        .buffered(api.jobs());
+    for f in &fetches {
+        let Some(Some(result)) = results.next().await else {
+            warn!("Sync interrupted");
+            break;
+        };
+        match result {
+            Ok(data) => {
+                summary += db.store_data(&data, f.weight)?;
+                db.drop_alias_fetch(&data, f.id)?;
+            }
+            Err(e) if e.action() == ErrorAction::Skip => {
+                warn!("Skipping {} {}: {}", f.ftype, f.id, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(summary)
+}
+
+/// The group's cover, if the tracker has one. Empty strings mean no image.
+fn cover_url(wiki_image: &Option<String>) -> Option<String> {
+    wiki_image
+        .as_deref()
+        .filter(|url| !url.trim().is_empty())
+        .map(str::to_string)
+}
+
+pub(crate) fn transform_groups(groups: &GroupData, weight: u32) -> Vec<Vec<Torrent>> {
+    match groups {
+        GroupData::ArtistData(artist) => artist
+            .torrent_groups
+            .iter()
+            .map(|g| {
+                g.torrents
+                    .iter()
+                    .map(|t| {
+                        let artist_name = decode_html_entities(&artist.name);
+                        let album_name = decode_html_entities(&g.name);
+                        Torrent {
+                            id: t.torrent_id,
+                            album_name: album_name.to_string(),
+                            artist_names: artist_name.to_string(),
+                            group_id: g.id,
+                            year: g.year,
+                            release_type: g.release_type,
+                            media: t.media.clone(),
+                            format: t.format.clone(),
+                            encoding: t.encoding.clone(),
+                            file_count: t.file_count,
+                            weight,
+                            size: t.size,
+                            created_at: Utc::now().naive_utc(),
+                            cover_url: cover_url(&g.wiki_image),
+                        }
+                    })
+                    .collect()
+            })
+            .collect(),
+        GroupData::CollageData(collage) => collage
+            .torrent_groups
+            .iter()
+            .map(|g| {
+                let artist_names = g
+                    .music_info
+                    .artists
+                    .iter()
+                    .map(|a| a.name.clone())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                g.torrents
+                    .iter()
+                    .map(|t| Torrent {
+                        id: t.torrent_id,
+                        album_name: g.name.clone(),
+                        artist_names: artist_names.clone(),
+                        group_id: g.id,
+                        year: g.year,
+                        release_type: g.release_type,
+                        media: t.media.clone(),
+                        format: t.format.clone(),
+                        encoding: t.encoding.clone(),
+                        file_count: t.file_count,
+                        weight,
+                        size: t.size,
+                        created_at: Utc::now().naive_utc(),
+                        cover_url: cover_url(&g.wiki_image),
+                    })
+                    .collect()
+            })
+            .collect(),
+        GroupData::Resolved(resolved) => {
+            // In order of first appearance, so the groups are stored in the
+            // order the service lists them
+            let mut groups: Vec<Vec<Torrent>> = Vec::new();
+            let mut positions: HashMap<u32, usize> = HashMap::new();
+            for t in &resolved.torrents {
+                let t = Torrent {
+                    weight,
+                    ..t.clone()
+                };
+                match t.group_id {
+                    Some(group_id) => match positions.get(&group_id) {
+                        Some(&i) => groups[i].push(t),
+                        None => {
+                            positions.insert(group_id, groups.len());
+                            groups.push(vec![t]);
+                        }
+                    },
+                    // Torrents of unknown groups are groups of their own
+                    None => groups.push(vec![t]),
+                }
+            }
+            groups
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    const BASE: &str = "https://tracker.example/";
+
+    #[test]
+    fn parses_permalinks() {
+        let parse = |link: &str| Permalink::parse(BASE, link);
+        assert_eq!(
+            parse(&permalink(BASE, Some(1), 2)).unwrap(),
+            Permalink::Torrent(2)
+        );
+        assert_eq!(
+            parse(&permalink(BASE, None, 2)).unwrap(),
+            Permalink::Torrent(2)
+        );
+        assert_eq!(
+            parse("https://tracker.example/torrents.php?id=1#comments").unwrap(),
+            Permalink::Group(1)
+        );
+        assert!(parse("https://tracker.example/torrents.php?action=browse").is_err());
+        assert!(parse("https://tracker.example/torrents.php?id=one").is_err());
+        assert!(parse("https://tracker.example/artist.php?id=1").is_err());
+        assert!(parse("https://other.example/torrents.php?id=1").is_err());
+        assert!(parse("torrents.php?id=1").is_err());
+    }
+
+    #[test]
+    fn parses_page_links() {
+        let parse = |link: &str| PageLink::parse(BASE, link);
+        assert_eq!(
+            parse("https://tracker.example/collages.php?id=7").unwrap(),
+            PageLink::Collage(7)
+        );
+        assert_eq!(
+            parse("https://tracker.example/artist.php?id=3&page=2").unwrap(),
+            PageLink::Artist(3)
+        );
+        assert_eq!(
+            parse("https://tracker.example/torrents.php?id=1&torrentid=2").unwrap(),
+            PageLink::Group(1)
+        );
+        assert_eq!(PageLink::Collage(7).to_string(), "collage 7");
+        assert!(parse("https://tracker.example/collages.php").is_err());
+        assert!(parse("https://tracker.example/collages.php?id=-1").is_err());
+        assert!(parse("https://tracker.example/user.php?id=1").is_err());
+        assert!(parse("https://other.example/artist.php?id=1").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_rate_limited_requests() {
+        let calls = Cell::new(0);
+        let result = retry_rate_limited(|| async {
+            calls.set(calls.get() + 1);
+            match calls.get() {
+                1 | 2 => Err(RedmanError::RateLimited),
+                n => Ok(n),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        calls.set(0);
+        let result: Result<()> = retry_rate_limited(|| async {
+            calls.set(calls.get() + 1);
+            Err(RedmanError::RateLimited)
+        })
+        .await;
+        assert!(matches!(result, Err(RedmanError::RateLimited)));
+        assert_eq!(calls.get(), MAX_RATE_LIMIT_RETRIES + 1);
+
+        calls.set(0);
+        let result: Result<()> = retry_rate_limited(|| async {
+            calls.set(calls.get() + 1);
+            Err(RedmanError::BadApiKey)
+        })
+        .await;
+        assert!(matches!(result, Err(RedmanError::BadApiKey)));
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -0,0 +1,79 @@
+#[cfg(feature = "keyring")]
+use crate::error::{RedmanError, Result};
+
+/// Keyring service the API keys are stored under.
+#[cfg(feature = "keyring")]
+const SERVICE: &str = "redman";
+
+/// Environment variable holding the API key of `tracker`: `API_KEY`, or
+/// `API_KEY_<TRACKER>` for a tracker profile, e.g. `API_KEY_OPS`.
+pub fn env_var(tracker: Option<&str>) -> String {
+    match tracker {
+        Some(name) => format!(
+            "API_KEY_{}",
+            name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                })
+                .collect::<String>()
+        ),
+        None => "API_KEY".to_string(),
+    }
+}
+
+/// The API key of `tracker` (`None` without `--tracker`): the environment
+/// variable from [`env_var`] (also read from `.env`) wins, then the OS keyring
+/// when built with the `keyring` feature. A profile never falls back to the
+/// global key, so one tracker's key is not sent to another.
+pub fn api_key(tracker: Option<&str>) -> Option<String> {
+    if let Ok(key) = std::env::var(env_var(tracker)) {
+        return Some(key);
+    }
+    #[cfg(feature = "keyring")]
+    match entry(tracker).and_then(|e| e.get_password()) {
+        Ok(key) => return Some(key),
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => tracing::debug!("Could not read the API key from the keyring: {}", e),
+    }
+    None
+}
+
+/// Store the key of `tracker` in the OS keyring, replacing any previous key.
+#[cfg(feature = "keyring")]
+pub fn store(tracker: Option<&str>, key: &str) -> Result<()> {
+    entry(tracker)
+        .and_then(|e| e.set_password(key))
+        .map_err(|error| RedmanError::KeyringError {
+            context: "Could not store the API key in the OS keyring",
+            error,
+        })
+}
+
+/// Remove the stored key of `tracker`. Returns whether there was one.
+#[cfg(feature = "keyring")]
+pub fn delete(tracker: Option<&str>) -> Result<bool> {
+    match entry(tracker).and_then(|e| e.delete_credential()) {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(error) => Err(RedmanError::KeyringError {
+            context: "Could not remove the API key from the OS keyring",
+            error,
+        }),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn entry(tracker: Option<&str>) -> keyring::Result<keyring::Entry> {
+    let user = match tracker {
+        Some(name) => format!("api_key:{}", name),
+        None => "api_key".to_string(),
+    };
+    keyring::Entry::new(SERVICE, &user)
+}
+
+/// Whether this build can use the OS keyring.
+pub const fn keyring_enabled() -> bool {
+    cfg!(feature = "keyring")
+}
@@ -0,0 +1,55 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde::Deserialize;
+
+use crate::error::{RedmanError, Result};
+
+/// Import of finished downloads into a beets library, configured in
+/// `[beets]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Beets {
+    /// beet executable
+    pub command: String,
+    /// Arguments passed to `beet import` before the directory. Beets cannot
+    /// ask questions here, so the default is quiet mode, which skips albums
+    /// it is unsure about.
+    pub args: Vec<String>,
+}
+
+impl Default for Beets {
+    fn default() -> Self {
+        Beets {
+            command: "beet".to_string(),
+            args: vec!["-q".to_string()],
+        }
+    }
+}
+
+impl Beets {
+    /// Run `beet import` on the downloaded files at `path`.
+    pub fn import(&self, path: &Path) -> Result<()> {
+        let output = Command::new(&self.command)
+            .arg("import")
+            .args(&self.args)
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| {
+                RedmanError::ClientError(format!("{}: Could not run: {}", self.command, e))
+            })?;
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(RedmanError::ClientError(format!(
+            "{}: Could not import {} ({}): {}",
+            self.command,
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, path::Path, process::Command, time::Duration};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::error::{RedmanError, Result};
+
+/// Torrent client reached through the `transmission-remote` executable.
+#[derive(Debug, Clone)]
+pub struct Transmission {
+    remote: String,
+    url: String,
+    auth: String,
+    paused: bool,
+    priority: Priority,
+    label: Option<String>,
+}
+
+/// Bandwidth priority of added torrents.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Default for Transmission {
+    fn default() -> Self {
+        Self::new(
+            "transmission-remote",
+            "localhost:9091",
+            "transmission:transmission",
+        )
+    }
+}
+
+impl Transmission {
+    /// `remote` is the executable, `url` the RPC host and port and `auth`
+    /// the credentials as "USER:PASSWORD".
+    pub fn new(remote: &str, url: &str, auth: &str) -> Self {
+        Self {
+            remote: remote.to_string(),
+            url: url.to_string(),
+            auth: auth.to_string(),
+            paused: false,
+            priority: Priority::default(),
+            label: None,
+        }
+    }
+
+    /// Add torrents stopped instead of starting them.
+    pub fn with_paused(mut self, paused: bool) -> Self {
+        self.paused = paused;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Label added torrents, to tell them apart from ones added by hand.
+    /// Needs Transmission 3.0 or newer.
+    pub fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Add the torrent file at `path`, saving into `download_dir`, and start
+    /// it unless paused.
+    pub fn add(&self, path: &Path, download_dir: &str) -> Result<()> {
+        let mut command = Command::new(&self.remote);
+        command
+            .arg(&self.url)
+            .args(["-n", &self.auth])
+            .arg("-a")
+            .arg(path)
+            .args(["--download-dir", download_dir])
+            .arg(if self.paused { "--stop" } else { "-s" });
+        match self.priority {
+            Priority::Low => command.arg("--bandwidth-low"),
+            Priority::Normal => &mut command,
+            Priority::High => command.arg("--bandwidth-high"),
+        };
+        if let Some(label) = &self.label {
+            command.args(["--labels", label]);
+        }
+        let output = command.output();
+        let error = match output {
+            Ok(output) if output.status.success() => return Ok(()),
+            Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            Err(e) => e.to_string(),
+        };
+        Err(RedmanError::ClientError(format!(
+            "{}: Could not add {} to transmission: {}",
+            self.remote,
+            path.display(),
+            error
+        )))
+    }
+
+    /// Remove the torrent with info hash `hash`, and its files if
+    /// `delete_data` is set.
+    pub fn remove(&self, hash: &str, delete_data: bool) -> Result<()> {
+        let output = Command::new(&self.remote)
+            .arg(&self.url)
+            .args(["-n", &self.auth])
+            .args(["-t", hash])
+            .arg(if delete_data {
+                "--remove-and-delete"
+            } else {
+                "--remove"
+            })
+            .output();
+        let error = match output {
+            Ok(output) if output.status.success() => return Ok(()),
+            Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            Err(e) => e.to_string(),
+        };
+        Err(RedmanError::ClientError(format!(
+            "{}: Could not remove {}: {}",
+            self.remote, hash, error
+        )))
+    }
+
+    /// The state of the torrent with info hash `hash`, or `None` when the
+    /// client does not have it (any more).
+    pub fn status(&self, hash: &str) -> Result<Option<ClientTorrent>> {
+        let output = Command::new(&self.remote)
+            .arg(&self.url)
+            .args(["-n", &self.auth])
+            .args(["-t", hash, "-i"])
+            .output();
+        let output = match output {
+            Ok(output) if output.status.success() => output,
+            Ok(output) => {
+                return Err(RedmanError::ClientError(format!(
+                    "{}: Could not get the status of {}: {}",
+                    self.remote,
+                    hash,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            Err(e) => {
+                return Err(RedmanError::ClientError(format!(
+                    "{}: Could not get the status of {}: {}",
+                    self.remote, hash, e
+                )));
+            }
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let fields: HashMap<&str, &str> = stdout
+            .lines()
+            .filter_map(|line| line.trim().split_once(": "))
+            .collect();
+        let Some(name) = fields.get("Name") else {
+            return Ok(None);
+        };
+        Ok(Some(ClientTorrent {
+            name: name.to_string(),
+            location: fields.get("Location").unwrap_or(&"").to_string(),
+            percent_done: fields
+                .get("Percent Done")
+                .and_then(|p| p.trim_end_matches('%').parse().ok())
+                .unwrap_or(0.0),
+            // "None" until something was downloaded
+            ratio: fields.get("Ratio").and_then(|r| r.parse().ok()),
+            // e.g. "2 days (172800 seconds)"
+            seeding_time: fields
+                .get("Seeding Time")
+                .and_then(|t| t.split_once('(')?.1.split_whitespace().next()?.parse().ok())
+                .map(Duration::from_secs),
+        }))
+    }
+}
+
+/// A torrent as the client sees it.
+#[derive(Debug, Clone)]
+pub struct ClientTorrent {
+    /// File or directory name of the download
+    pub name: String,
+    /// Directory the download is saved in
+    pub location: String,
+    pub percent_done: f64,
+    /// Uploaded divided by downloaded
+    pub ratio: Option<f64>,
+    /// How long the client has been seeding it
+    pub seeding_time: Option<Duration>,
+}
+
+impl ClientTorrent {
+    pub fn is_complete(&self) -> bool {
+        self.percent_done >= 100.0
+    }
+
+    /// Where the downloaded files are.
+    pub fn path(&self) -> std::path::PathBuf {
+        Path::new(&self.location).join(&self.name)
+    }
+}
+
+/// The info hash identifying a torrent file in clients: the hex SHA-1 of its
+/// bencoded `info` dictionary. `None` if the file is not a valid torrent.
+pub(crate) fn info_hash(torrent_file: &[u8]) -> Option<String> {
+    if torrent_file.first() != Some(&b'd') {
+        return None;
+    }
+    let mut pos = 1;
+    while torrent_file.get(pos) != Some(&b'e') {
+        let key_end = skip_value(torrent_file, pos)?;
+        let key = &torrent_file[pos..key_end];
+        let value_end = skip_value(torrent_file, key_end)?;
+        if key.ends_with(b":info") {
+            let hash = Sha1::digest(&torrent_file[key_end..value_end]);
+            return Some(hash.iter().map(|b| format!("{:02x}", b)).collect());
+        }
+        pos = value_end;
+    }
+    None
+}
+
+/// The position after the bencoded value starting at `pos`.
+fn skip_value(data: &[u8], pos: usize) -> Option<usize> {
+    match data.get(pos)? {
+        b'i' => Some(pos + data[pos..].iter().position(|&b| b == b'e')? + 1),
+        b'l' | b'd' => {
+            let mut pos = pos + 1;
+            while *data.get(pos)? != b'e' {
+                pos = skip_value(data, pos)?;
+            }
+            Some(pos + 1)
+        }
+        b'0'..=b'9' => {
+            let colon = pos + data[pos..].iter().position(|&b| b == b':')?;
+            let len: usize = std::str::from_utf8(&data[pos..colon]).ok()?.parse().ok()?;
+            let end = colon + 1 + len;
+            (end <= data.len()).then_some(end)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &[u8] = b"d6:lengthi42e4:name5:a.mp312:piece lengthi16384ee";
+
+    #[test]
+    fn hashes_the_info_dictionary() {
+        let mut file = b"d8:announce14:http://a/b/c/d13:creation datei1700000000e4:info".to_vec();
+        file.extend_from_slice(INFO);
+        file.extend_from_slice(b"4:listl1:xi3eee");
+        assert_eq!(
+            info_hash(&file).as_deref(),
+            Some("b9976cc0c68ae000a62bbd24675cf7853bde4d9a")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_torrents() {
+        assert_eq!(info_hash(b""), None);
+        assert_eq!(info_hash(b"<html>"), None);
+        assert_eq!(info_hash(b"d8:announce3:urle"), None);
+        assert_eq!(info_hash(b"d4:infod4:name"), None);
+        assert_eq!(info_hash(b"d8:announce99:short4:infodee"), None);
+    }
+
+    #[test]
+    fn skips_bencoded_values() {
+        assert_eq!(skip_value(b"i-42eX", 0), Some(5));
+        assert_eq!(skip_value(b"4:spamX", 0), Some(6));
+        assert_eq!(skip_value(b"0:X", 0), Some(2));
+        assert_eq!(skip_value(b"l4:spami1eeX", 0), Some(11));
+        assert_eq!(skip_value(b"d1:ad1:bli1eeeeX", 0), Some(15));
+        assert_eq!(skip_value(b"X1:a", 1), Some(4));
+        assert_eq!(skip_value(b"5:spam", 0), None);
+        assert_eq!(skip_value(b"i42", 0), None);
+        assert_eq!(skip_value(b"l1:a", 0), None);
+        assert_eq!(skip_value(b"x", 0), None);
+        assert_eq!(skip_value(b"", 0), None);
+    }
+}
@@ -0,0 +1,597 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::Local;
+use croner::Cron;
+use html_escape::decode_html_entities;
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    api::{DEFAULT_WEIGHT, GroupData},
+    beets::Beets,
+    clients::{Priority, Transmission},
+    downloads::CheckOptions,
+    error::{RedmanError, Result},
+    formula::{Formula, deserialize_formula},
+    hooks::Hooks,
+    irc::IrcConfig,
+    lastfm::LastFm,
+    notify::{Notifier, Run},
+    plex::PlexServer,
+    rss::{FeedAction, RssConfig},
+    rules::Rule,
+    selection::{Strategy, UnknownYear},
+    sources::Sources,
+    tracker::{Compat, DEFAULT_RATE_LIMIT, DEFAULT_RATE_WINDOW},
+    watch::WatchOptions,
+};
+
+/// Settings read from the TOML file given with `--config`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub daemon: DaemonConfig,
+    pub watch: Option<WatchConfig>,
+    /// Tracker profiles selected with `--tracker`, by name
+    pub trackers: HashMap<String, TrackerProfile>,
+    /// Channels that get a summary after every watch and sync run
+    pub notify: Vec<Notifier>,
+    /// Commands run when torrents are added and completed
+    pub hooks: Hooks,
+    /// Import completed downloads into beets
+    pub beets: Option<Beets>,
+    /// Server to rescan once downloads completed
+    pub plex: Option<PlexServer>,
+    pub cleanup: CleanupConfig,
+    /// Keep the collages subscribed to on the tracker as fetches
+    pub subscriptions: Option<Subscriptions>,
+    /// Weights of collage fetches by category when `fetch` is given none
+    pub category_weights: CategoryWeights,
+    /// Raise the weight of torrents by artists and albums played most
+    pub lastfm: Option<LastFm>,
+    /// Services suggesting releases for `fetch`
+    #[serde(flatten)]
+    pub sources: Sources,
+    /// Pool or download new uploads matching a filter
+    pub rss: Option<RssConfig>,
+    /// Pool or download announced uploads matching a filter
+    pub irc: Option<IrcConfig>,
+    /// What to do with uploads of `[rss]` and `[irc]`, first match first
+    pub rules: Vec<Rule>,
+}
+
+/// A tracker to talk to, selected with `--tracker <NAME>`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrackerProfile {
+    pub base_url: String,
+    /// Takes precedence over `API_KEY_<NAME>` and the keyring
+    pub api_key: Option<String>,
+    /// Requests allowed in every `rate_window`; 0 disables the limit while
+    /// `jobs` is 1, and means the default of 10 in 10s above
+    #[serde(default = "default_rate_limit")]
+    pub rate_limit: usize,
+    #[serde(default = "default_rate_window", with = "humantime_serde")]
+    pub rate_window: Duration,
+    /// API dialect of the site: "redacted" or "orpheus"
+    #[serde(default)]
+    pub compat: Compat,
+    /// Collages and artists fetched at once by `sync` and `fetch`
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+    /// Pool database, defaulting to `$XDG_DATA_HOME/redman/<NAME>.db` so
+    /// torrent ids of different trackers never mix
+    pub pool: Option<PathBuf>,
+}
+
+/// When `cleanup` may remove a torrent from the client, on top of the
+/// tracker's minimum seed time.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CleanupConfig {
+    pub min_ratio: Option<f64>,
+    /// e.g. "30d"
+    #[serde(with = "humantime_serde")]
+    pub min_seed_time: Option<Duration>,
+    /// Delete the downloaded files too; off by default as they are usually
+    /// the files Plex plays
+    pub delete_data: bool,
+}
+
+/// Collages subscribed to on the tracker, fetched by `sync` once they show up.
+#[derive(Debug, Deserialize)]
+pub struct Subscriptions {
+    /// Weight of the fetches made for new subscriptions [default: that of
+    /// the collage category]
+    pub weight: Option<u32>,
+}
+
+/// Weights of collages by category name, e.g. `Personal = 5` or
+/// `"Staff picks" = 15`.
+#[derive(Debug, Default, Deserialize)]
+pub struct CategoryWeights(HashMap<String, u32>);
+
+impl CategoryWeights {
+    /// Weight to store `data` with: `given` if any, else the weight of the
+    /// collage's category, else [`DEFAULT_WEIGHT`].
+    pub fn weight(&self, given: Option<u32>, data: &GroupData) -> u32 {
+        given
+            .or_else(|| match data {
+                GroupData::CollageData(c) => {
+                    let category = category_key(&decode_html_entities(&c.collage_category_name));
+                    self.0
+                        .iter()
+                        .find(|(name, _)| category_key(name) == category)
+                        .map(|(_, weight)| *weight)
+                }
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_WEIGHT)
+    }
+}
+
+/// Category names compared regardless of case, and of `-` or `_` in place of
+/// spaces as on the command line.
+fn category_key(name: &str) -> String {
+    name.to_lowercase().replace(['-', '_'], " ")
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// How often to re-fetch all stored collages and artists, e.g. "24h"
+    #[serde(deserialize_with = "deserialize_interval")]
+    pub sync_interval: Option<Duration>,
+    /// How often to add new torrents for download, e.g. "6h"
+    #[serde(deserialize_with = "deserialize_interval")]
+    pub watch_interval: Option<Duration>,
+    /// How often to ask the torrent client which downloads finished, e.g. "10m"
+    #[serde(deserialize_with = "deserialize_interval")]
+    pub check_interval: Option<Duration>,
+    /// How often to poll the `[rss]` feed for new uploads, e.g. "5m"
+    #[serde(deserialize_with = "deserialize_interval")]
+    pub rss_interval: Option<Duration>,
+    /// Address serving `/metrics` for Prometheus and `/healthz` and `/status`
+    /// for health checks, e.g. "127.0.0.1:9187"
+    pub listen: Option<SocketAddr>,
+    /// How long a task may run before it counts as hung, by default "1h"
+    #[serde(deserialize_with = "deserialize_interval")]
+    pub task_timeout: Option<Duration>,
+    /// `[daemon.<task>]` tables, taking the place of the `*_interval` keys
+    pub sync: Option<TaskConfig>,
+    pub watch: Option<TaskConfig>,
+    pub check: Option<TaskConfig>,
+    pub rss: Option<TaskConfig>,
+    /// Remove torrents the `[cleanup]` thresholds allow, as `redman cleanup --yes`
+    pub cleanup: Option<TaskConfig>,
+}
+
+impl DaemonConfig {
+    /// When `run` is due, from its `[daemon.<task>]` table or else its
+    /// `<task>_interval`, with its retry delay. `None` if neither is set or
+    /// the task is disabled.
+    pub fn task(&self, run: Run) -> Result<Option<(Schedule, Option<Duration>)>> {
+        let (table, interval) = match run {
+            Run::Sync => (&self.sync, self.sync_interval),
+            Run::Watch => (&self.watch, self.watch_interval),
+            Run::Check => (&self.check, self.check_interval),
+            Run::Rss => (&self.rss, self.rss_interval),
+            Run::Cleanup => (&self.cleanup, None),
+        };
+        let task = match (table, interval) {
+            (Some(_), Some(_)) => {
+                return Err(RedmanError::ConfigError(format!(
+                    "Set either {}_interval or [daemon.{}], not both",
+                    run, run
+                )));
+            }
+            (Some(task), None) => task.clone(),
+            (None, Some(interval)) => TaskConfig {
+                interval: Some(interval),
+                ..TaskConfig::default()
+            },
+            (None, None) => return Ok(None),
+        };
+        if !task.enabled {
+            return Ok(None);
+        }
+        let schedule = match (task.interval, task.cron) {
+            (Some(interval), None) => Schedule::Every(interval),
+            (None, Some(cron)) => Schedule::Cron(Box::new(cron)),
+            _ => {
+                return Err(RedmanError::ConfigError(format!(
+                    "Set either interval or cron in [daemon.{}]",
+                    run
+                )));
+            }
+        };
+        Ok(Some((schedule, task.retry_after)))
+    }
+}
+
+/// When a daemon task runs, in `[daemon.sync]`, `[daemon.watch]` and so on.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TaskConfig {
+    /// Off keeps the schedule in the file without running the task
+    pub enabled: bool,
+    /// Run at start and then every so often, e.g. "6h"
+    #[serde(deserialize_with = "deserialize_interval")]
+    pub interval: Option<Duration>,
+    /// Run at the times of a cron expression in local time, e.g. "0 3 * * *"
+    /// for 3am every day
+    #[serde(deserialize_with = "deserialize_cron")]
+    pub cron: Option<Cron>,
+    /// After a failed run, try again this much later instead of at the next
+    /// scheduled time, e.g. "15m"
+    #[serde(deserialize_with = "deserialize_interval")]
+    pub retry_after: Option<Duration>,
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        TaskConfig {
+            enabled: true,
+            interval: None,
+            cron: None,
+            retry_after: None,
+        }
+    }
+}
+
+/// Read an optional duration, rejecting "0s" so a task cannot run back to back.
+fn deserialize_interval<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    let interval: Option<Duration> = humantime_serde::deserialize(deserializer)?;
+    if interval.is_some_and(|i| i.is_zero()) {
+        return Err(serde::de::Error::custom("must be longer than 0s"));
+    }
+    Ok(interval)
+}
+
+fn deserialize_cron<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Cron>, D::Error> {
+    let expression = String::deserialize(deserializer)?;
+    let cron = Cron::from_str(&expression).map_err(serde::de::Error::custom)?;
+    if cron.find_next_occurrence(&Local::now(), false).is_err() {
+        return Err(serde::de::Error::custom(format!(
+            "{:?} never matches",
+            expression
+        )));
+    }
+    Ok(Some(cron))
+}
+
+/// When a daemon task is due.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Box<Cron>),
+}
+
+impl Schedule {
+    /// Time from now until the next run.
+    pub fn delay(&self) -> Duration {
+        match self {
+            Schedule::Every(interval) => *interval,
+            Schedule::Cron(cron) => {
+                let now = Local::now();
+                cron.find_next_occurrence(&now, false)
+                    .ok()
+                    .and_then(|next| (next - now).to_std().ok())
+                    // Checked when the config was read
+                    .unwrap_or(Duration::from_secs(24 * 60 * 60))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Schedule::Every(interval) => {
+                write!(f, "every {}", humantime::format_duration(*interval))
+            }
+            Schedule::Cron(cron) => write!(f, "cron {}", cron.pattern),
+        }
+    }
+}
+
+/// Options for unattended watch runs, mirroring the `download` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchConfig {
+    #[serde(default = "default_number")]
+    pub number: usize,
+    pub plex: String,
+    pub torrent_dir: String,
+    pub download_dir: String,
+    #[serde(default = "default_transmission_remote")]
+    pub transmission_remote: String,
+    /// Host and port of the Transmission RPC server
+    #[serde(default = "default_transmission_url")]
+    pub transmission_url: String,
+    /// Transmission RPC credentials as "USER:PASSWORD"
+    #[serde(default = "default_transmission_auth")]
+    pub transmission_auth: String,
+    #[serde(default)]
+    pub no_fl: bool,
+    #[serde(default)]
+    pub freeload_only: bool,
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    /// How pool candidates are ordered: "strict", "weighted", "fifo", "lifo" or "random"
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Halve a torrent's weight for every period it spends in the pool, e.g. "90d"
+    #[serde(default, with = "humantime_serde")]
+    pub half_life: Option<Duration>,
+    /// Selection weight worked out from the factors of each torrent, e.g.
+    /// "weight * boost * decay / (1 + years / 10)"
+    #[serde(default, deserialize_with = "deserialize_formula")]
+    pub weight_formula: Option<Formula>,
+    /// Pick at most this many torrents from the same artist per run
+    #[serde(default)]
+    pub max_per_artist: Option<usize>,
+    /// Pick at most this many torrents contributed by the same fetch per run
+    #[serde(default)]
+    pub max_per_fetch: Option<usize>,
+    /// Ignore pool torrents with a lower weight
+    #[serde(default)]
+    pub min_weight: u32,
+    /// Torrents without a release year: "keep", "last" or "skip"
+    #[serde(default)]
+    pub unknown_year: UnknownYear,
+    /// Download the album art of added torrents
+    #[serde(default)]
+    pub covers: bool,
+    /// Where album art is saved, defaulting to `$XDG_CACHE_HOME/redman/covers`
+    #[serde(default)]
+    pub covers_dir: Option<PathBuf>,
+    /// Add torrents stopped, to start them by hand
+    #[serde(default)]
+    pub paused: bool,
+    /// Bandwidth priority of added torrents: "low", "normal" or "high"
+    #[serde(default)]
+    pub priority: Priority,
+    /// Label for added torrents in the client, e.g. "redman"
+    #[serde(default)]
+    pub label: Option<String>,
+    /// How long the tracker wants torrents seeded, e.g. "72h"
+    #[serde(default, with = "humantime_serde")]
+    pub min_seed_time: Option<Duration>,
+}
+
+impl WatchConfig {
+    /// The torrent client of these settings.
+    pub fn transmission(&self) -> Transmission {
+        Transmission::new(
+            &self.transmission_remote,
+            &self.transmission_url,
+            &self.transmission_auth,
+        )
+    }
+
+    /// The library options for a run with these settings.
+    pub fn options(&self) -> WatchOptions {
+        WatchOptions::new(&self.plex, &self.torrent_dir, &self.download_dir)
+            .with_number(self.number)
+            .with_transmission(
+                self.transmission()
+                    .with_paused(self.paused)
+                    .with_priority(self.priority)
+                    .with_label(self.label.clone()),
+            )
+            .with_freeleech(!self.no_fl)
+            .with_freeload_only(self.freeload_only)
+            .with_max_failures(self.max_failures)
+            .with_strategy(self.strategy, self.half_life)
+            .with_weight_formula(self.weight_formula.clone())
+            .with_caps(self.max_per_artist, self.max_per_fetch)
+            .with_min_weight(self.min_weight)
+            .with_unknown_year(self.unknown_year)
+            .with_covers(
+                self.covers
+                    .then(|| self.covers_dir.clone().or_else(default_covers_dir))
+                    .flatten(),
+            )
+            .with_min_seed_time(self.min_seed_time)
+    }
+}
+
+fn default_number() -> usize {
+    10
+}
+
+fn default_max_failures() -> u32 {
+    3
+}
+
+fn default_transmission_remote() -> String {
+    "transmission-remote".to_string()
+}
+
+fn default_transmission_url() -> String {
+    "localhost:9091".to_string()
+}
+
+fn default_transmission_auth() -> String {
+    "transmission:transmission".to_string()
+}
+
+fn default_rate_limit() -> usize {
+    DEFAULT_RATE_LIMIT
+}
+
+fn default_rate_window() -> Duration {
+    DEFAULT_RATE_WINDOW
+}
+
+fn default_jobs() -> usize {
+    4
+}
+
+/// Profiles available without configuration: Redacted allows 10 API
+/// requests per 10 seconds, Orpheus 5.
+fn builtin_tracker(name: &str) -> Option<TrackerProfile> {
+    let (base_url, rate_limit, compat) = match name {
+        "red" => ("https://redacted.sh/", 10, Compat::Redacted),
+        "ops" => ("https://orpheus.network/", 5, Compat::Orpheus),
+        _ => return None,
+    };
+    Some(TrackerProfile {
+        base_url: base_url.to_string(),
+        api_key: None,
+        rate_limit,
+        rate_window: default_rate_window(),
+        compat,
+        jobs: default_jobs(),
+        pool: None,
+    })
+}
+
+/// `$XDG_DATA_HOME/redman/pool.db`, or `<TRACKER>.db` for a tracker profile,
+/// used when `--pool` is not given.
+pub fn default_pool_path(tracker: Option<&str>) -> Option<PathBuf> {
+    let file = format!("{}.db", tracker.unwrap_or("pool"));
+    xdg_dir("XDG_DATA_HOME", ".local/share").map(|d| d.join("redman").join(file))
+}
+
+/// `$XDG_CONFIG_HOME/redman/config.toml`, read when `--config` is not given
+/// and the file exists.
+pub fn default_config_path() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|d| d.join("redman").join("config.toml"))
+}
+
+/// `$XDG_CACHE_HOME/redman/covers`, where album art is saved when
+/// `--covers-dir` is not given.
+pub fn default_covers_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache").map(|d| d.join("redman").join("covers"))
+}
+
+/// An XDG base directory, falling back to `$HOME/<fallback>` when unset.
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(RedmanError::file(path))?;
+        toml::from_str(&content).map_err(|e| {
+            RedmanError::ConfigError(format!("Invalid config {}: {}", path.display(), e))
+        })
+    }
+
+    /// What to do about downloads `transmission` finished.
+    pub fn check_options(&self, transmission: Transmission) -> CheckOptions {
+        CheckOptions::new(transmission)
+            .with_hooks(self.hooks.clone())
+            .with_beets(self.beets.clone())
+            .with_plex_scan(self.plex.clone())
+    }
+
+    /// Fail if uploads of `[rss]` or `[irc]` may be downloaded right away, by
+    /// their action or a rule, but there is no `[watch]` to do it with.
+    pub fn check_feed_downloads(&self) -> Result<()> {
+        let sections: Vec<_> = self
+            .rss
+            .iter()
+            .map(|rss| &rss.settings)
+            .chain(self.irc.iter().map(|irc| &irc.settings))
+            .collect();
+        let downloads = sections.iter().any(|s| s.action == FeedAction::Download)
+            || (!sections.is_empty()
+                && self.rules.iter().any(|r| r.action == FeedAction::Download));
+        if downloads && self.watch.is_none() {
+            return Err(RedmanError::ConfigError(
+                "Downloading uploads of [rss] or [irc] needs a [watch] section".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The profile called `name`, from the config file or built in.
+    pub fn tracker(&self, name: &str) -> Result<TrackerProfile> {
+        self.trackers
+            .get(name)
+            .cloned()
+            .or_else(|| builtin_tracker(name))
+            .ok_or_else(|| RedmanError::ConfigError(format!("Unknown tracker profile {:?}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn daemon(toml: &str) -> std::result::Result<DaemonConfig, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    fn schedule(toml: &str, run: Run) -> Option<String> {
+        daemon(toml)
+            .unwrap()
+            .task(run)
+            .unwrap()
+            .map(|(schedule, _)| schedule.to_string())
+    }
+
+    #[test]
+    fn reads_intervals() {
+        let toml = r#"
+            sync_interval = "1day 12h"
+            check_interval = "10m"
+        "#;
+        assert_eq!(schedule(toml, Run::Sync).as_deref(), Some("every 1day 12h"));
+        assert_eq!(schedule(toml, Run::Check).as_deref(), Some("every 10m"));
+        assert_eq!(schedule(toml, Run::Watch), None);
+        assert_eq!(schedule(toml, Run::Cleanup), None);
+        assert!(daemon(r#"watch_interval = "0s""#).is_err());
+        assert!(daemon(r#"watch_interval = "often""#).is_err());
+    }
+
+    #[test]
+    fn reads_task_tables() {
+        let toml = r#"
+            [watch]
+            cron = "0 3 * * *"
+            retry_after = "15m"
+
+            [cleanup]
+            interval = "1w"
+
+            [rss]
+            enabled = false
+            interval = "5m"
+        "#;
+        let config = daemon(toml).unwrap();
+        let (watch, retry) = config.task(Run::Watch).unwrap().unwrap();
+        assert!(matches!(&watch, Schedule::Cron(_)));
+        assert_eq!(watch.to_string(), "cron 0 3 * * *");
+        assert!(watch.delay() <= Duration::from_secs(24 * 60 * 60));
+        assert_eq!(retry, Some(Duration::from_secs(15 * 60)));
+        assert_eq!(schedule(toml, Run::Cleanup).as_deref(), Some("every 7days"));
+        assert_eq!(schedule(toml, Run::Rss), None);
+    }
+
+    #[test]
+    fn rejects_conflicting_schedules() {
+        assert!(daemon("[sync]\ncron = \"not cron\"").is_err());
+        assert!(daemon("[sync]\ncron = \"0 0 30 2 *\"").is_err());
+        let both = daemon("sync_interval = \"1h\"\n[sync]\ninterval = \"2h\"").unwrap();
+        assert!(both.task(Run::Sync).is_err());
+        let neither = daemon("[sync]\nretry_after = \"1h\"").unwrap();
+        assert!(neither.task(Run::Sync).is_err());
+        let both = daemon("[sync]\ninterval = \"1h\"\ncron = \"0 3 * * *\"").unwrap();
+        assert!(both.task(Run::Sync).is_err());
+    }
+}
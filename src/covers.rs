@@ -0,0 +1,110 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use reqwest::Client;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    api::{USER_AGENT, content_type},
+    db::Torrent,
+    error::{RedmanError, Result},
+    progress::progress_bar,
+    shutdown,
+};
+
+/// Image extensions kept from the cover URL; anything else is saved as .jpg.
+const EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "gif"];
+
+/// Where the cover of `torrent` is stored in `dir`: `<torrent id>.<ext>`.
+pub fn cover_path(dir: &Path, torrent: &Torrent) -> Option<PathBuf> {
+    let url = torrent.cover_url.as_deref()?;
+    let ext = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .filter(|ext| EXTENSIONS.contains(&ext.as_str()))
+        .unwrap_or_else(|| "jpg".to_string());
+    Some(dir.join(format!("{}.{}", torrent.id, ext)))
+}
+
+/// The cover of `torrent` if it was already downloaded to `dir`.
+pub fn cached_cover(dir: &Path, torrent: &Torrent) -> Option<PathBuf> {
+    cover_path(dir, torrent).filter(|path| path.exists())
+}
+
+/// Download the cover of `torrent` into `dir` unless it is there already.
+/// Returns `None` for torrents without a cover URL.
+#[instrument(skip_all, fields(id = torrent.id))]
+pub async fn download_cover(
+    client: &Client,
+    torrent: &Torrent,
+    dir: &Path,
+) -> Result<Option<PathBuf>> {
+    let (Some(url), Some(path)) = (torrent.cover_url.as_deref(), cover_path(dir, torrent)) else {
+        return Ok(None);
+    };
+    if path.exists() {
+        return Ok(Some(path));
+    }
+    fs::create_dir_all(dir).map_err(RedmanError::file(dir))?;
+    let response = client
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() || !content_type(&response).starts_with("image/") {
+        return Err(RedmanError::ApiError(format!(
+            "Could not download cover {} ({}, {})",
+            url,
+            status,
+            content_type(&response)
+        )));
+    }
+    let bytes = response.bytes().await?;
+    // Written aside first so an interrupted download never looks cached
+    let partial = path.with_extension("part");
+    fs::write(&partial, &bytes).map_err(RedmanError::file(&partial))?;
+    fs::rename(&partial, &path).map_err(RedmanError::file(&path))?;
+    info!("Saved cover to {}", path.display());
+    Ok(Some(path))
+}
+
+/// What [`download_covers`] did.
+#[derive(Debug, Default)]
+pub struct CoverSummary {
+    pub saved: usize,
+    /// Already in the covers directory
+    pub cached: usize,
+    pub failed: usize,
+}
+
+/// Download the covers of `torrents` missing from `dir`. Failures are logged
+/// and counted rather than ending the run.
+pub async fn download_covers(client: &Client, torrents: &[Torrent], dir: &Path) -> CoverSummary {
+    let mut summary = CoverSummary::default();
+    let progress = progress_bar(torrents.len() as u64, "Saving covers");
+    for t in torrents {
+        if shutdown::requested() {
+            break;
+        }
+        progress.inc(1);
+        if cached_cover(dir, t).is_some() {
+            summary.cached += 1;
+            continue;
+        }
+        match download_cover(client, t, dir).await {
+            Ok(Some(_)) => summary.saved += 1,
+            Ok(None) => {}
+            Err(e) => {
+                warn!(id = t.id, "Could not save the cover: {}", e);
+                summary.failed += 1;
+            }
+        }
+    }
+    progress.finish_and_clear();
+    summary
+}
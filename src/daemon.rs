@@ -0,0 +1,642 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    api::{Type, sync_fetches, sync_subscriptions},
+    config::{Config, Schedule},
+    db::{Database, Torrent, format_size},
+    downloads::{
+        CleanupOptions, check_downloads, cleanup_candidates, poll_seeding, remove_downloads,
+    },
+    error::{RedmanError, Result},
+    irc::IrcConfig,
+    lastfm,
+    library::filter_torrents_not_in_plex_library,
+    metrics::{self, Counter},
+    notify::{Event, Run, notify_all},
+    rss::{FeedAction, FeedItem, FeedReport, Match, poll_feed, pool_uploads},
+    server::{self, Response},
+    shutdown, systemd,
+    tracker::TrackerClient,
+    watch::{WatchReport, add_new_torrents_for_download},
+};
+
+/// Consecutive failures of a task after which `/healthz` reports unhealthy.
+const UNHEALTHY_AFTER: u32 = 3;
+
+/// How long a scheduled task may run without `[daemon] task_timeout`.
+pub const DEFAULT_TASK_TIMEOUT: Duration = Duration::from_secs(3600);
+/// How long looking up and adding one announced upload may take.
+const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(600);
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(600);
+
+/// Whether a loop of the daemon is still making progress. Waiting for the
+/// next task or announce is progress; a run busy for longer than its limit is
+/// hung, and stays hung so the watchdog pings stop for good.
+#[derive(Default)]
+struct Heartbeat {
+    busy: Mutex<Option<(Instant, Duration)>>,
+}
+
+impl Heartbeat {
+    fn busy(&self, limit: Duration) {
+        if !self.hung() {
+            *self.busy.lock().unwrap() = Some((Instant::now(), limit));
+        }
+    }
+
+    fn idle(&self) {
+        *self.busy.lock().unwrap() = None;
+    }
+
+    fn hung(&self) -> bool {
+        self.busy
+            .lock()
+            .unwrap()
+            .is_some_and(|(since, limit)| since.elapsed() > limit)
+    }
+}
+
+struct Task {
+    kind: Run,
+    schedule: Schedule,
+    retry_after: Option<Duration>,
+    next: Instant,
+}
+
+/// What the daemon has been doing, served as JSON on `/status`.
+#[derive(Debug, Default, Serialize)]
+struct Status {
+    started_at: String,
+    pool_size: usize,
+    sync: Option<TaskStatus>,
+    watch: Option<TaskStatus>,
+    check: Option<TaskStatus>,
+    rss: Option<TaskStatus>,
+    cleanup: Option<TaskStatus>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct TaskStatus {
+    /// e.g. "every 6h" or "cron 0 3 * * *"
+    schedule: String,
+    last_run: Option<String>,
+    /// Summary of the last successful run
+    last_result: Option<String>,
+    /// Error of the last run, if it failed
+    last_error: Option<String>,
+    consecutive_failures: u32,
+    next_run: Option<String>,
+}
+
+impl Status {
+    fn task(&mut self, kind: Run) -> &mut TaskStatus {
+        match kind {
+            Run::Sync => self.sync.get_or_insert_default(),
+            Run::Watch => self.watch.get_or_insert_default(),
+            Run::Check => self.check.get_or_insert_default(),
+            Run::Rss => self.rss.get_or_insert_default(),
+            Run::Cleanup => self.cleanup.get_or_insert_default(),
+        }
+    }
+
+    /// Tasks failing over and over, as "watch failed 3 times: <error>".
+    fn stuck(&self) -> Vec<String> {
+        [
+            (Run::Sync, &self.sync),
+            (Run::Watch, &self.watch),
+            (Run::Check, &self.check),
+            (Run::Rss, &self.rss),
+            (Run::Cleanup, &self.cleanup),
+        ]
+        .into_iter()
+        .filter_map(|(kind, task)| {
+            let task = task.as_ref()?;
+            (task.consecutive_failures >= UNHEALTHY_AFTER).then(|| {
+                format!(
+                    "{} failed {} times: {}",
+                    kind,
+                    task.consecutive_failures,
+                    task.last_error.as_deref().unwrap_or_default()
+                )
+            })
+        })
+        .collect()
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// The wall-clock time of `instant`, for `/status`.
+fn next_run(instant: Instant) -> Option<String> {
+    chrono::Duration::from_std(instant.saturating_duration_since(Instant::now()))
+        .ok()
+        .map(|delay| timestamp(Utc::now() + delay))
+}
+
+/// Run the configured tasks on their intervals or cron schedules, and listen to
+/// the `[irc]` announce channel if there is one, until the process is stopped.
+/// A failing run is logged and retried after its `retry_after`, or else at its
+/// next slot.
+pub async fn run<C: TrackerClient + Send + Sync + 'static>(
+    api: Arc<C>,
+    pool: &str,
+    config: Arc<Config>,
+) -> Result<()> {
+    let db = Database::new(pool)?;
+    let mut tasks = Vec::new();
+    for kind in [Run::Sync, Run::Watch, Run::Check, Run::Rss, Run::Cleanup] {
+        let Some((schedule, retry_after)) = config.daemon.task(kind)? else {
+            continue;
+        };
+        match kind {
+            Run::Watch | Run::Check | Run::Cleanup if config.watch.is_none() => {
+                return Err(RedmanError::ConfigError(format!(
+                    "The {} task needs a [watch] section",
+                    kind
+                )));
+            }
+            Run::Rss if config.rss.is_none() => {
+                return Err(RedmanError::ConfigError(
+                    "The rss task needs an [rss] section".to_string(),
+                ));
+            }
+            _ => {}
+        }
+        // Interval tasks start right away, cron tasks at their first match
+        let next = match schedule {
+            Schedule::Every(_) => Instant::now(),
+            Schedule::Cron(_) => Instant::now() + schedule.delay(),
+        };
+        tasks.push(Task {
+            kind,
+            schedule,
+            retry_after,
+            next,
+        });
+    }
+    config.check_feed_downloads()?;
+    if tasks.is_empty() && config.irc.is_none() {
+        return Err(RedmanError::ConfigError(
+            "No daemon tasks configured, set sync_interval, watch_interval, check_interval or rss_interval, add [daemon.<task>] tables or an [irc] section"
+                .to_string(),
+        ));
+    }
+
+    let status = Arc::new(Mutex::new(Status {
+        started_at: timestamp(Utc::now()),
+        pool_size: db.pool_size()?,
+        ..Status::default()
+    }));
+    for task in &tasks {
+        let mut status = status.lock().unwrap();
+        let task_status = status.task(task.kind);
+        task_status.schedule = task.schedule.to_string();
+        task_status.next_run = next_run(task.next);
+    }
+    let server = match config.daemon.listen {
+        Some(addr) => {
+            let listener = server::bind(addr).await?;
+            let status = status.clone();
+            Some(tokio::spawn(server::serve(listener, move |path| {
+                route(path, &status.lock().unwrap())
+            })))
+        }
+        None => None,
+    };
+
+    info!(
+        tasks = tasks.len(),
+        irc = config.irc.is_some(),
+        "Daemon started"
+    );
+    systemd::notify("READY=1");
+    // The tasks and announces run on threads of their own, so the pings stop
+    // once either of them hangs rather than when the runtime does
+    let (scheduler_beat, irc_beat) = (
+        Arc::new(Heartbeat::default()),
+        Arc::new(Heartbeat::default()),
+    );
+    let watchdog = systemd::watchdog_interval().map(|interval| {
+        let beats = [scheduler_beat.clone(), irc_beat.clone()];
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if beats.iter().any(|beat| beat.hung()) {
+                    error!("Daemon hung, no longer pinging the watchdog");
+                    break;
+                }
+                systemd::notify("WATCHDOG=1");
+            }
+        })
+    });
+    let task_timeout = config.daemon.task_timeout.unwrap_or(DEFAULT_TASK_TIMEOUT);
+    let scheduler = async {
+        if tasks.is_empty() {
+            systemd::status("Listening to IRC announces");
+            shutdown::signal().await;
+            return;
+        }
+        let mut last = None;
+        loop {
+            let task = tasks.iter_mut().min_by_key(|t| t.next).unwrap();
+            let wait = humantime::format_duration(Duration::from_secs(
+                task.next
+                    .saturating_duration_since(Instant::now())
+                    .as_secs(),
+            ));
+            systemd::status(&match &last {
+                Some(last) => format!("{}; {} next in {}", last, task.kind, wait),
+                None => format!("{} next in {}", task.kind, wait),
+            });
+            tokio::select! {
+                _ = tokio::time::sleep_until(task.next) => {}
+                _ = shutdown::signal() => break,
+            }
+
+            info!(task = %task.kind, "Running scheduled task");
+            systemd::status(&format!("Running {}", task.kind));
+            // Off the runtime workers, as the tasks wait on transmission-remote,
+            // beets and SQLite and would stall the IRC session meanwhile
+            let result = {
+                let (api, pool, config) = (api.clone(), pool.to_string(), config.clone());
+                let (kind, runtime) = (task.kind, tokio::runtime::Handle::current());
+                scheduler_beat.busy(task_timeout);
+                let run = tokio::task::spawn_blocking(move || {
+                    runtime.block_on(run_task(kind, api.as_ref(), &pool, &config))
+                });
+                match tokio::time::timeout(task_timeout, run).await {
+                    Ok(result) => {
+                        scheduler_beat.idle();
+                        result.unwrap_or_else(|e| Err(RedmanError::ServiceError(e.to_string())))
+                    }
+                    Err(_) => Err(RedmanError::ServiceError(format!(
+                        "Did not finish within {}",
+                        humantime::format_duration(task_timeout)
+                    ))),
+                }
+            };
+            {
+                let mut status = status.lock().unwrap();
+                if let Ok(size) = db.pool_size() {
+                    status.pool_size = size;
+                }
+                let task_status = status.task(task.kind);
+                task_status.last_run = Some(timestamp(Utc::now()));
+                match &result {
+                    Ok(summary) => {
+                        task_status.last_result = Some(summary.clone());
+                        task_status.last_error = None;
+                        task_status.consecutive_failures = 0;
+                    }
+                    Err(e) => {
+                        task_status.last_error = Some(e.to_string());
+                        task_status.consecutive_failures += 1;
+                    }
+                }
+            }
+            last = Some(match &result {
+                Ok(summary) => format!("{}: {}", task.kind, summary),
+                Err(e) => format!("{} failed: {}", task.kind, e),
+            });
+            if let Err(e) = &result {
+                error!(task = %task.kind, "Scheduled task failed: {}", e);
+                metrics::add(Counter::RunErrors, 1);
+                notify_all(
+                    &config.notify,
+                    &Event::Failed {
+                        run: task.kind,
+                        error: e,
+                    },
+                )
+                .await;
+            }
+
+            if shutdown::requested() {
+                break;
+            }
+            let delay = match task.retry_after {
+                Some(retry_after) if result.is_err() => retry_after,
+                _ => task.schedule.delay(),
+            };
+            task.next = Instant::now() + delay;
+            status.lock().unwrap().task(task.kind).next_run = next_run(task.next);
+            info!(task = %task.kind, "Next run in {}",
+                humantime::format_duration(Duration::from_secs(delay.as_secs())));
+        }
+    };
+    let irc = {
+        let (api, pool, config) = (api.clone(), pool.to_string(), config.clone());
+        let runtime = tokio::runtime::Handle::current();
+        // A thread of its own, as lookups and downloads hold the pool open
+        // across awaits and call transmission-remote
+        let beat = irc_beat.clone();
+        tokio::task::spawn_blocking(move || {
+            runtime.block_on(listen(api.as_ref(), &pool, &config, &beat))
+        })
+    };
+    scheduler.await;
+    // The session quits on its own once shutdown is requested
+    let _ = irc.await;
+    systemd::notify("STOPPING=1");
+    for handle in [server, watchdog].into_iter().flatten() {
+        handle.abort();
+    }
+    info!("Daemon stopped");
+    Ok(())
+}
+
+/// One run of the scheduled task `kind`, summarised for `/status`.
+async fn run_task(
+    kind: Run,
+    api: &impl TrackerClient,
+    pool: &str,
+    config: &Config,
+) -> Result<String> {
+    match kind {
+        Run::Sync => {
+            let db = Database::new(pool)?;
+            let mut s = sync_fetches(api, &db, &config.sources).await?;
+            if let Some(subscriptions) = &config.subscriptions {
+                s += sync_subscriptions(api, &db, subscriptions.weight, &config.category_weights)
+                    .await?;
+            }
+            info!(task = %kind, inserted = s.inserted, replaced = s.replaced, "Torrents stored");
+            notify_all(&config.notify, &Event::Sync(&s)).await;
+            if let Some(lastfm) = &config.lastfm
+                && let Err(e) = lastfm::refresh(pool, lastfm).await
+            {
+                warn!(task = %kind, "Could not refresh Last.fm play counts: {}", e);
+            }
+            Ok(s.to_string())
+        }
+        Run::Watch => {
+            let r = run_watch(api, pool, config).await?;
+            info!(
+                task = %kind,
+                skipped = r.skipped.len(),
+                failed = r.failed.len(),
+                fl_tokens = r.fl_tokens,
+                "{} torrents added ({})",
+                r.added.len(),
+                format_size(r.total_bytes)
+            );
+            notify_all(&config.notify, &Event::Watch(&r)).await;
+            Ok(format!(
+                "{} torrents added ({}), {} failed",
+                r.added.len(),
+                format_size(r.total_bytes),
+                r.failed.len()
+            ))
+        }
+        Run::Check => {
+            let transmission = config.watch.as_ref().unwrap().transmission();
+            let completed =
+                check_downloads(pool, &config.check_options(transmission.clone())).await?;
+            let seeded = poll_seeding(pool, &transmission)?
+                .iter()
+                .filter(|d| d.seeded_enough())
+                .count();
+            info!(task = %kind, "{} downloads completed", completed.len());
+            Ok(format!(
+                "{} downloads completed, {} seeded long enough",
+                completed.len(),
+                seeded
+            ))
+        }
+        Run::Rss => {
+            let (feed, added) = run_rss(api, pool, config).await?;
+            let mut summary = feed.to_string();
+            if let Some(r) = &added {
+                info!(task = %kind, "{} matching uploads added", r.added.len());
+                notify_all(&config.notify, &Event::Watch(r)).await;
+                summary += &format!(", {} added", r.added.len());
+            }
+            Ok(summary)
+        }
+        Run::Cleanup => {
+            let watch = config.watch.as_ref().unwrap();
+            let options = CleanupOptions::new(watch.transmission(), &watch.plex)
+                .with_thresholds(config.cleanup.min_ratio, config.cleanup.min_seed_time)
+                .with_delete_data(config.cleanup.delete_data);
+            let candidates = cleanup_candidates(pool, &options)?;
+            let removed = remove_downloads(pool, &options, &candidates)?;
+            info!(task = %kind, "{} torrents removed from the client", removed);
+            Ok(format!("{} torrents removed from the client", removed))
+        }
+    }
+}
+
+/// The monitoring endpoints served on `[daemon] listen`.
+fn route(path: &str, status: &Status) -> Option<Response> {
+    match path {
+        "/metrics" => Some(Response::ok(
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics::render(),
+        )),
+        "/healthz" => {
+            let stuck = status.stuck();
+            if stuck.is_empty() {
+                Some(Response::text("200 OK", "ok"))
+            } else {
+                Some(Response::text("503 Service Unavailable", &stuck.join("\n")))
+            }
+        }
+        "/status" => Some(Response::ok(
+            "application/json",
+            serde_json::to_string_pretty(status).unwrap_or_default() + "\n",
+        )),
+        _ => None,
+    }
+}
+
+/// Poll the `[rss]` feed of `config` for new uploads and pool the matching
+/// ones. Those the action or `[[rules]]` download and that are not in the Plex
+/// library are also added right away with the `[watch]` settings. Does
+/// nothing without `[rss]`.
+pub async fn run_rss(
+    api: &impl TrackerClient,
+    pool: &str,
+    config: &Config,
+) -> Result<(FeedReport, Option<WatchReport>)> {
+    let Some(rss) = &config.rss else {
+        return Ok((FeedReport::default(), None));
+    };
+    let feed = poll_feed(api, pool, rss, &config.rules).await?;
+    let added = download_uploads(api, pool, config, &feed.matched).await?;
+    Ok((feed, added))
+}
+
+/// Add the `matched` uploads whose action is download and that are not in
+/// the Plex library right away, with the `[watch]` settings of `config`.
+async fn download_uploads(
+    api: &impl TrackerClient,
+    pool: &str,
+    config: &Config,
+    matched: &[Match],
+) -> Result<Option<WatchReport>> {
+    let Some(watch) = &config.watch else {
+        return Ok(None);
+    };
+    let wanted: Vec<Torrent> = matched
+        .iter()
+        .filter(|m| m.action == FeedAction::Download)
+        .map(|m| m.torrent.clone())
+        .collect();
+    let aliases = Database::new(pool)?.artist_aliases()?;
+    let ids: Vec<u32> = filter_torrents_not_in_plex_library(&wanted, &watch.plex, &aliases)?
+        .iter()
+        .map(|t| t.id)
+        .collect();
+    if ids.len() < wanted.len() {
+        info!(
+            "{} matching uploads already in the Plex library",
+            wanted.len() - ids.len()
+        );
+    }
+    if ids.is_empty() {
+        return Ok(None);
+    }
+    let options = watch
+        .options()
+        .with_hooks(config.hooks.clone())
+        .with_number(ids.len())
+        .with_torrent_ids(ids);
+    Ok(Some(
+        add_new_torrents_for_download(api, pool, &options).await?,
+    ))
+}
+
+/// Listen to the `[irc]` announce channel of `config` until shutdown, pooling
+/// announced uploads that pass its filter and `[[rules]]`, and adding them
+/// right away where those say download. A lost connection is retried with a growing
+/// delay. Does nothing without `[irc]`.
+pub async fn listen_announces(api: &impl TrackerClient, pool: &str, config: &Config) {
+    listen(api, pool, config, &Heartbeat::default()).await
+}
+
+async fn listen(api: &impl TrackerClient, pool: &str, config: &Config, beat: &Heartbeat) {
+    let Some(irc) = &config.irc else {
+        return;
+    };
+    let mut delay = MIN_RECONNECT_DELAY;
+    while !shutdown::requested() {
+        let started = Instant::now();
+        match announce_session(api, pool, config, irc, beat).await {
+            Ok(()) => break,
+            Err(e) => warn!("IRC connection lost: {}", e),
+        }
+        // Only back off further while the server keeps turning us away
+        if started.elapsed() > MAX_RECONNECT_DELAY {
+            delay = MIN_RECONNECT_DELAY;
+        }
+        info!(
+            "Reconnecting to IRC in {}",
+            humantime::format_duration(delay)
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown::signal() => break,
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// One connection to the announce channel, until it drops or shutdown is
+/// requested. Failing lookups and downloads are logged and do not drop it.
+async fn announce_session(
+    api: &impl TrackerClient,
+    pool: &str,
+    config: &Config,
+    irc: &IrcConfig,
+    beat: &Heartbeat,
+) -> Result<()> {
+    let mut conn = irc.connect().await?;
+    loop {
+        let item = tokio::select! {
+            item = conn.next_announce() => item?,
+            _ = shutdown::signal() => {
+                conn.quit().await;
+                return Ok(());
+            }
+        };
+        debug!(id = item.torrent_id, title = %item.title, "Announced");
+        beat.busy(ANNOUNCE_TIMEOUT);
+        match tokio::time::timeout(
+            ANNOUNCE_TIMEOUT,
+            add_announced(api, pool, config, irc, item),
+        )
+        .await
+        {
+            Ok(()) => beat.idle(),
+            Err(_) => warn!(
+                "Announced upload not added within {}",
+                humantime::format_duration(ANNOUNCE_TIMEOUT)
+            ),
+        }
+    }
+}
+
+/// Look up an announced upload and add it if the filter and rules say so.
+async fn add_announced(
+    api: &impl TrackerClient,
+    pool: &str,
+    config: &Config,
+    irc: &IrcConfig,
+    item: FeedItem,
+) {
+    let feed = match pool_uploads(
+        api,
+        pool,
+        vec![item],
+        &irc.settings,
+        &config.rules,
+        Type::Irc,
+    )
+    .await
+    {
+        Ok(feed) => feed,
+        Err(e) => {
+            warn!("Could not look up the announced upload: {}", e);
+            return;
+        }
+    };
+    match download_uploads(api, pool, config, &feed.matched).await {
+        Ok(Some(report)) => {
+            info!("{} announced uploads added", report.added.len());
+            notify_all(&config.notify, &Event::Watch(&report)).await;
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Could not add the announced upload: {}", e),
+    }
+}
+
+/// Add new torrents for download with the settings of the `[watch]` section
+/// of `config`, running its hooks for each and boosting by Last.fm plays when
+/// `[lastfm]` is set. Does nothing without `[watch]`.
+pub async fn run_watch(
+    api: &impl TrackerClient,
+    pool: &str,
+    config: &Config,
+) -> Result<WatchReport> {
+    let Some(watch) = &config.watch else {
+        return Ok(WatchReport::default());
+    };
+    let options = watch
+        .options()
+        .with_hooks(config.hooks.clone())
+        .with_scrobble_boost(config.lastfm.as_ref().map(|l| l.boost));
+    add_new_torrents_for_download(api, pool, &options).await
+}
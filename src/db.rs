@@ -0,0 +1,2216 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use clap::ValueEnum;
+use html_escape::decode_html_entities;
+use itertools::Itertools;
+use rusqlite::{Connection, MAIN_DB, OpenFlags, OptionalExtension, Params, params};
+use serde::{Serialize, Serializer};
+use tracing::{info, warn};
+
+use crate::{
+    api::{GroupData, Type, permalink, transform_groups},
+    error::{RedmanError, Result},
+    migrations,
+    portable::{self, ConflictPolicy, FetchRecord, ImportSummary, PoolExport, TorrentRecord},
+    progress::progress_bar,
+    rss::FeedItem,
+    selection::preferred_torrent,
+};
+
+#[derive(Debug, Clone)]
+pub struct Torrent {
+    pub id: u32,
+    /// Torrent group on the tracker, unknown for torrents fetched before it was recorded
+    pub group_id: Option<u32>,
+    pub album_name: String,
+    pub artist_names: String,
+    /// Release year, `None` when the tracker does not know it
+    pub year: Option<u32>,
+    pub(crate) release_type: u32,
+    pub media: String,
+    pub format: String,
+    pub encoding: String,
+    pub(crate) file_count: u32,
+    pub size: u64,
+    pub weight: u32,
+    pub created_at: NaiveDateTime,
+    /// Album art of the torrent group
+    pub cover_url: Option<String>,
+}
+
+impl Torrent {
+    /// Weight used for selection: the stored weight halved for every
+    /// `half_life` the torrent has spent in the pool.
+    pub(crate) fn effective_weight(&self, half_life: Option<Duration>) -> f64 {
+        self.weight as f64 * self.decay(half_life)
+    }
+
+    /// Halved for every `half_life` the torrent has spent in the pool, 1
+    /// without one.
+    pub(crate) fn decay(&self, half_life: Option<Duration>) -> f64 {
+        let Some(half_life) = half_life.filter(|h| !h.is_zero()) else {
+            return 1.0;
+        };
+        let age = (Utc::now().naive_utc() - self.created_at)
+            .to_std()
+            .unwrap_or_default();
+        0.5f64.powf(age.as_secs_f64() / half_life.as_secs_f64())
+    }
+
+    /// Page of the torrent on the tracker at `base_url`.
+    pub fn permalink(&self, base_url: &str) -> String {
+        permalink(base_url, self.group_id, self.id)
+    }
+}
+
+pub struct Database {
+    conn: Connection,
+}
+
+/// How long to wait for another connection to release a lock on the pool.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Groups stored per transaction, after each of which a failed fetch can be
+/// picked up again.
+const CHECKPOINT_GROUPS: usize = 200;
+
+impl Database {
+    pub fn new(db_path: &str) -> Result<Self> {
+        let db_exists = Path::new(db_path).exists();
+        let conn = Connection::open(db_path)?;
+
+        if !db_exists {
+            info!(path = db_path, "Creating new database");
+        }
+
+        // WAL lets readers such as `stats` run while the daemon writes; the
+        // busy timeout covers the short moments two writers overlap.
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        // INSERT OR REPLACE only fires the full-text index's delete trigger
+        // with recursive triggers enabled.
+        conn.execute_batch("PRAGMA recursive_triggers = ON")?;
+        migrations::migrate(&conn)?;
+
+        Ok(Self { conn })
+    }
+
+    /// Record the torrents selected for a new watch run, returning its id.
+    pub(crate) fn start_journal(&self, torrents: &[Torrent]) -> Result<(i64, Vec<JournalEntry>)> {
+        let run_id: i64 = self.conn.query_row(
+            "SELECT COALESCE(MAX(run_id), 0) + 1 FROM journal",
+            [],
+            |row| row.get(0),
+        )?;
+        for (position, t) in torrents.iter().enumerate() {
+            self.conn.execute(
+                r#"
+                INSERT INTO journal (
+                    run_id, torrent_id, position, state, group_id, album_name, artist_names,
+                    year, release_type, media, format, encoding, file_count, weight, size_bytes,
+                    created_at, cover_url
+                ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+                params![
+                    run_id,
+                    t.id,
+                    position,
+                    JournalState::Selected.as_str(),
+                    t.group_id,
+                    t.album_name,
+                    t.artist_names,
+                    t.year,
+                    t.release_type,
+                    t.media,
+                    t.format,
+                    t.encoding,
+                    t.file_count,
+                    t.weight,
+                    t.size as i64,
+                    t.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    t.cover_url,
+                ],
+            )?;
+        }
+        let entries = torrents
+            .iter()
+            .map(|t| JournalEntry {
+                torrent: t.clone(),
+                state: JournalState::Selected,
+                path: None,
+            })
+            .collect();
+        Ok((run_id, entries))
+    }
+
+    pub(crate) fn set_journal_state(
+        &self,
+        run_id: i64,
+        torrent_id: u32,
+        state: JournalState,
+        path: Option<&Path>,
+    ) -> Result<()> {
+        self.conn.execute(
+            r#"
+            UPDATE journal SET state = ?, path = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE run_id = ? AND torrent_id = ?
+            "#,
+            params![
+                state.as_str(),
+                path.and_then(|p| p.to_str()),
+                run_id,
+                torrent_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The pending entries of the latest watch run, if it did not complete.
+    pub(crate) fn unfinished_journal(&self) -> Result<Option<(i64, Vec<JournalEntry>)>> {
+        let run_id: Option<i64> =
+            self.conn
+                .query_row("SELECT MAX(run_id) FROM journal", [], |row| row.get(0))?;
+        let Some(run_id) = run_id else {
+            return Ok(None);
+        };
+        // Entries journaled before the torrents were kept in the journal fall
+        // back to the pool
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT j.torrent_id AS id,
+                CASE WHEN j.album_name IS NULL THEN t.group_id ELSE j.group_id END AS group_id,
+                COALESCE(j.album_name, t.album_name) AS album_name,
+                COALESCE(j.artist_names, t.artist_names) AS artist_names,
+                CASE WHEN j.album_name IS NULL THEN t.year ELSE j.year END AS year,
+                COALESCE(j.release_type, t.release_type) AS release_type,
+                COALESCE(j.media, t.media) AS media,
+                COALESCE(j.format, t.format) AS format,
+                COALESCE(j.encoding, t.encoding) AS encoding,
+                COALESCE(j.file_count, t.file_count) AS file_count,
+                COALESCE(j.weight, t.weight) AS weight,
+                COALESCE(j.size_bytes, t.size_bytes) AS size_bytes,
+                COALESCE(j.created_at, t.created_at) AS created_at,
+                CASE WHEN j.album_name IS NULL THEN t.cover_url ELSE j.cover_url END AS cover_url,
+                j.album_name IS NOT NULL OR t.id IS NOT NULL AS known,
+                j.state, j.path
+            FROM journal j LEFT JOIN torrents t ON t.id = j.torrent_id
+            WHERE j.run_id = ? AND j.state IN ('selected', 'downloaded')
+            ORDER BY j.position
+            "#,
+        )?;
+        let entries = stmt
+            .query_map([run_id], |row| {
+                if !row.get::<_, bool>("known")? {
+                    warn!(
+                        id = row.get::<_, u32>("id")?,
+                        "Torrent of the interrupted run is no longer in the pool, not resuming it"
+                    );
+                    return Ok(None);
+                }
+                Ok(Some(JournalEntry {
+                    torrent: torrent_from_row(row)?,
+                    state: JournalState::from_str(&row.get::<_, String>("state")?),
+                    path: row.get::<_, Option<String>>("path")?.map(PathBuf::from),
+                }))
+            })?
+            .filter_map(Result::transpose)
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if entries.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((run_id, entries)))
+        }
+    }
+
+    pub(crate) fn record_download(
+        &self,
+        t: &Torrent,
+        used_fl: bool,
+        info_hash: Option<&str>,
+        min_seed_time: Option<Duration>,
+    ) -> Result<()> {
+        self.conn.execute(
+            r#"
+            INSERT INTO downloads (
+                torrent_id, group_id, artist_names, album_name, size_bytes, fl_token, info_hash,
+                min_seed_secs
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                t.id,
+                t.group_id,
+                t.artist_names,
+                t.album_name,
+                t.size as i64,
+                used_fl,
+                info_hash,
+                min_seed_time.map(|d| d.as_secs() as i64)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Downloads the client has not finished yet, as far as redman knows.
+    pub(crate) fn unfinished_downloads(&self) -> Result<Vec<Download>> {
+        self.query_downloads(
+            "d.completed_at IS NULL AND d.info_hash IS NOT NULL AND d.removed_at IS NULL",
+            [],
+        )
+    }
+
+    /// Downloads that can be looked up in the client.
+    pub(crate) fn tracked_downloads(&self) -> Result<Vec<Download>> {
+        self.query_downloads("d.info_hash IS NOT NULL AND d.removed_at IS NULL", [])
+    }
+
+    /// Downloads added before `before` that were not queued again since.
+    pub(crate) fn downloads_added_before(&self, before: NaiveDateTime) -> Result<Vec<Download>> {
+        let before = before.format("%Y-%m-%d %H:%M:%S").to_string();
+        self.query_downloads("d.added_at <= ? AND d.requeued_at IS NULL", [before])
+    }
+
+    pub(crate) fn mark_requeued(&self, torrent_id: u32) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET requeued_at = CURRENT_TIMESTAMP WHERE torrent_id = ?",
+            [torrent_id],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn mark_removed(&self, torrent_id: u32) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET removed_at = CURRENT_TIMESTAMP WHERE torrent_id = ? AND removed_at IS NULL",
+            [torrent_id],
+        )?;
+        Ok(())
+    }
+
+    /// Downloads still expected in the client: not removed or queued again.
+    pub(crate) fn active_downloads(&self) -> Result<Vec<Download>> {
+        self.query_downloads("d.removed_at IS NULL AND d.requeued_at IS NULL", [])
+    }
+
+    /// Ids of every torrent redman ever added.
+    pub(crate) fn downloaded_ids(&self) -> Result<HashSet<u32>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT torrent_id FROM downloads")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<u32>>>()?;
+        Ok(ids)
+    }
+
+    /// Record the info hash of a download from before hashes were kept.
+    pub(crate) fn set_info_hash(&self, torrent_id: u32, info_hash: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET info_hash = ? WHERE torrent_id = ? AND info_hash IS NULL",
+            params![info_hash, torrent_id],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn update_seeding(
+        &self,
+        info_hash: &str,
+        ratio: Option<f64>,
+        seeding_time: Option<Duration>,
+    ) -> Result<()> {
+        self.conn.execute(
+            r#"
+            UPDATE downloads SET ratio = ?, seeding_secs = ?, seeding_polled_at = CURRENT_TIMESTAMP
+            WHERE info_hash = ?
+            "#,
+            params![ratio, seeding_time.map(|d| d.as_secs() as i64), info_hash],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn mark_completed(&self, info_hash: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET completed_at = CURRENT_TIMESTAMP WHERE info_hash = ?",
+            [info_hash],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn record_failure(&self, torrent_id: u32, reason: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO failures (torrent_id, reason) VALUES (?, ?)",
+            params![torrent_id, reason],
+        )?;
+        Ok(())
+    }
+
+    /// Torrents that failed to download or add at least `max_failures` times.
+    pub(crate) fn failed_torrent_ids(&self, max_failures: u32) -> Result<HashSet<u32>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT torrent_id FROM failures GROUP BY torrent_id HAVING COUNT(*) >= ?")?;
+        let ids = stmt
+            .query_map([max_failures], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<u32>>>()?;
+        Ok(ids)
+    }
+
+    /// Torrents added to the client by earlier runs and not queued again,
+    /// wherever their torrent files are now.
+    pub(crate) fn added_torrent_ids(&self) -> Result<HashSet<u32>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT torrent_id FROM downloads WHERE requeued_at IS NULL")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<u32>>>()?;
+        Ok(ids)
+    }
+
+    /// The fetch each pool torrent is attributed to, as `(fetch id, type code)`.
+    /// Torrents contributed by several fetches count toward the heaviest one.
+    pub(crate) fn torrent_sources(&self) -> Result<HashMap<u32, (u32, u32)>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT tf.torrent_id, tf.fetch_id, tf.fetch_type
+            FROM torrent_fetches tf
+            JOIN fetches f ON f.id = tf.fetch_id AND f.type = tf.fetch_type
+            ORDER BY f.weight DESC, f.created_at
+            "#,
+        )?;
+        let mut sources = HashMap::new();
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))? {
+            let (torrent_id, source) = row?;
+            sources.entry(torrent_id).or_insert(source);
+        }
+        Ok(sources)
+    }
+
+    /// Torrents added to the client, newest first, optionally limited to
+    /// those added at or after `since`.
+    pub fn get_downloads(&self, since: Option<NaiveDateTime>) -> Result<Vec<Download>> {
+        let since = since
+            .map(|s| s.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        self.query_downloads("d.added_at >= ?", [since])
+    }
+
+    pub(crate) fn record_upgrade(
+        &self,
+        torrent_id: u32,
+        plex_album_id: i64,
+        artist_names: &str,
+        album_name: &str,
+        old_path: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            r#"
+            INSERT OR REPLACE INTO upgrades (
+                torrent_id, plex_album_id, artist_names, album_name, old_path
+            ) VALUES (?, ?, ?, ?, ?)
+            "#,
+            params![
+                torrent_id,
+                plex_album_id,
+                artist_names,
+                album_name,
+                old_path
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Plex albums that already have a lossless download recorded.
+    pub(crate) fn upgraded_album_ids(&self) -> Result<HashSet<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT plex_album_id FROM upgrades")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<i64>>>()?;
+        Ok(ids)
+    }
+
+    pub fn get_upgrades(&self) -> Result<Vec<Upgrade>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT u.torrent_id, u.plex_album_id, u.artist_names, u.album_name, u.old_path,
+                u.created_at, MAX(d.completed_at) AS completed_at
+            FROM upgrades u LEFT JOIN downloads d ON d.torrent_id = u.torrent_id
+            GROUP BY u.torrent_id
+            ORDER BY u.created_at DESC
+            "#,
+        )?;
+        let upgrades = stmt
+            .query_map([], |row| {
+                Ok(Upgrade {
+                    torrent_id: row.get("torrent_id")?,
+                    plex_album_id: row.get("plex_album_id")?,
+                    artist_names: row.get("artist_names")?,
+                    album_name: row.get("album_name")?,
+                    old_path: row.get("old_path")?,
+                    created_at: row.get("created_at")?,
+                    completed_at: row.get("completed_at")?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(upgrades)
+    }
+
+    /// Replace all stored play counts with `rows` of artist key, album key
+    /// (empty for an artist), display name and play count.
+    pub(crate) fn replace_scrobbles(&self, rows: &[(String, String, String, u64)]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM scrobbles", [])?;
+        let mut insert = tx.prepare(
+            r#"
+            INSERT INTO scrobbles (artist_key, album_key, name, playcount) VALUES (?, ?, ?, ?)
+            ON CONFLICT(artist_key, album_key) DO UPDATE
+                SET playcount = playcount + excluded.playcount
+            "#,
+        )?;
+        for (artist, album, name, plays) in rows {
+            insert.execute(params![artist, album, name, *plays as i64])?;
+        }
+        drop(insert);
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Stored play counts as artist key, album key and play count.
+    pub(crate) fn scrobbles(&self) -> Result<Vec<(String, String, u64)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT artist_key, album_key, playcount FROM scrobbles")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u64))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rows)
+    }
+
+    /// The `limit` most played artists and albums as name and play count.
+    pub fn top_scrobbles(&self, limit: usize) -> Result<Vec<(String, u64)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, playcount FROM scrobbles ORDER BY playcount DESC LIMIT ?")?;
+        let rows = stmt
+            .query_map([limit as i64], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rows)
+    }
+
+    pub(crate) fn feed_item_seen(&self, torrent_id: u32) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM feed_items WHERE torrent_id = ?)",
+            [torrent_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Remember `item` of the RSS feed or IRC announces (`ftype`).
+    pub(crate) fn mark_feed_item_seen(&self, item: &FeedItem, ftype: Type) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO feed_items (torrent_id, title, tags, type) VALUES (?, ?, ?, ?)",
+            params![
+                item.torrent_id,
+                item.title,
+                item.tags.join(","),
+                ftype.code()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The `limit` feed items seen most recently, newest first, with where
+    /// they came from.
+    pub fn recent_feed_items(&self, limit: usize) -> Result<Vec<(FeedItem, Type)>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT torrent_id, title, tags, type FROM feed_items
+            WHERE title IS NOT NULL ORDER BY seen_at DESC, rowid DESC LIMIT ?
+            "#,
+        )?;
+        let items = stmt
+            .query_map([limit as i64], |row| {
+                let tags: String = row.get(2)?;
+                Ok((
+                    FeedItem {
+                        torrent_id: row.get(0)?,
+                        title: row.get(1)?,
+                        tags: tags
+                            .split(',')
+                            .filter(|t| !t.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    },
+                    Type::from_code(row.get(3)?).unwrap_or(Type::Rss),
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(items)
+    }
+
+    /// Artist names of the torrents in the pool.
+    pub(crate) fn pool_artist_names(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT artist_names FROM torrents")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(names)
+    }
+
+    fn query_downloads(&self, condition: &str, params: impl Params) -> Result<Vec<Download>> {
+        let mut stmt = self.conn.prepare(&format!(
+            r#"
+            SELECT d.torrent_id, COALESCE(d.group_id, t.group_id) AS group_id, d.artist_names,
+                d.album_name, d.size_bytes, d.fl_token, d.added_at, d.info_hash, d.completed_at,
+                d.min_seed_secs, d.ratio, d.seeding_secs
+            FROM downloads d LEFT JOIN torrents t ON t.id = d.torrent_id
+            WHERE {} ORDER BY d.added_at DESC
+            "#,
+            condition
+        ))?;
+        let downloads = stmt
+            .query_map(params, |row| {
+                Ok(Download {
+                    torrent_id: row.get("torrent_id")?,
+                    group_id: row.get("group_id")?,
+                    artist_names: row.get("artist_names")?,
+                    album_name: row.get("album_name")?,
+                    size: row.get::<_, i64>("size_bytes")? as u64,
+                    fl_token: row.get("fl_token")?,
+                    added_at: row.get("added_at")?,
+                    info_hash: row.get("info_hash")?,
+                    completed_at: row.get("completed_at")?,
+                    min_seed_time: row
+                        .get::<_, Option<i64>>("min_seed_secs")?
+                        .map(|s| Duration::from_secs(s as u64)),
+                    ratio: row.get("ratio")?,
+                    seeding_time: row
+                        .get::<_, Option<i64>>("seeding_secs")?
+                        .map(|s| Duration::from_secs(s as u64)),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(downloads)
+    }
+
+    pub fn get_fetches(&self) -> Result<Vec<Fetch>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT f.id, f.type, f.name, f.weight, f.created_at, f.source, f.category,
+                COUNT(t.id) AS torrents
+            FROM fetches f
+            LEFT JOIN torrent_fetches tf ON tf.fetch_id = f.id AND tf.fetch_type = f.type
+            LEFT JOIN torrents t ON t.id = tf.torrent_id
+            GROUP BY f.id, f.type
+            ORDER BY f.created_at
+            "#,
+        )?;
+        let fetches = stmt.query_map([], fetch_from_row)?;
+        let fetches = readable_rows(fetches).into_iter().flatten().collect();
+        Ok(fetches)
+    }
+
+    /// The id of the `ftype` fetch looked up by `source`, or the next free one
+    /// if there is none yet.
+    pub fn source_fetch_id(&self, ftype: Type, source: &str) -> Result<u32> {
+        Ok(self.conn.query_row(
+            r#"
+            SELECT COALESCE(
+                (SELECT id FROM fetches WHERE type = ?1 AND source = ?2),
+                (SELECT COALESCE(MAX(id), 0) + 1 FROM fetches WHERE type = ?1)
+            )
+            "#,
+            params![ftype.code(), source],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Delete a fetch record and its provenance links. With `purge`, also
+    /// delete the pool torrents no other fetch contributed. Returns `None`
+    /// if the fetch does not exist, otherwise the number of purged torrents.
+    pub fn remove_fetch(&self, ftype: Type, id: u32, purge: bool) -> Result<Option<usize>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM fetches WHERE id = ? AND type = ?)",
+            params![id, ftype.code()],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(None);
+        }
+        let purged = if purge {
+            tx.execute(
+                r#"
+                DELETE FROM torrents WHERE id IN (
+                    SELECT tf.torrent_id FROM torrent_fetches tf
+                    WHERE tf.fetch_id = ?1 AND tf.fetch_type = ?2
+                    AND NOT EXISTS (
+                        SELECT 1 FROM torrent_fetches o
+                        WHERE o.torrent_id = tf.torrent_id
+                        AND NOT (o.fetch_id = ?1 AND o.fetch_type = ?2)
+                    )
+                )
+                "#,
+                params![id, ftype.code()],
+            )?
+        } else {
+            0
+        };
+        tx.execute(
+            "DELETE FROM torrent_fetches WHERE fetch_id = ? AND fetch_type = ?",
+            params![id, ftype.code()],
+        )?;
+        tx.execute(
+            "DELETE FROM fetches WHERE id = ? AND type = ?",
+            params![id, ftype.code()],
+        )?;
+        tx.commit()?;
+        Ok(Some(purged))
+    }
+
+    /// Change the weight of a fetch and of every pool torrent it contributed.
+    /// Returns `None` if the fetch does not exist, otherwise the number of
+    /// torrents updated.
+    pub fn reweight_fetch(&self, ftype: Type, id: u32, weight: u32) -> Result<Option<usize>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let updated = tx.execute(
+            "UPDATE fetches SET weight = ? WHERE id = ? AND type = ?",
+            params![weight, id, ftype.code()],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        let torrents = tx.execute(
+            r#"
+            UPDATE torrents SET weight = ?1 WHERE id IN (
+                SELECT torrent_id FROM torrent_fetches WHERE fetch_id = ?2 AND fetch_type = ?3
+            )
+            "#,
+            params![weight, id, ftype.code()],
+        )?;
+        tx.commit()?;
+        Ok(Some(torrents))
+    }
+
+    /// Store the best torrent of every group. Large fetches are committed in
+    /// chunks, each recording the last group stored, so a fetch that failed
+    /// halfway goes on from there when run again with the same weight.
+    pub fn store_data(&self, group_data: &GroupData, weight: u32) -> Result<StoreSummary> {
+        let mut summary = StoreSummary::default();
+        let (fetch_id, fetch_type, fetch_name, source) = match group_data {
+            GroupData::ArtistData(a) => (a.id, Type::Artist, &a.name, None),
+            GroupData::CollageData(c) => (c.id, Type::Collage, &c.name, None),
+            GroupData::Resolved(r) => (r.id, r.ftype, &r.name, r.source.as_deref()),
+        };
+        let category = match group_data {
+            GroupData::CollageData(c) => Some(decode_html_entities(&c.collage_category_name)),
+            _ => None,
+        };
+
+        if let GroupData::ArtistData(artist) = group_data {
+            self.store_aliases(&decode_html_entities(&artist.name), &artist.aliases())?;
+        }
+
+        let groups = transform_groups(group_data, weight);
+        // A checkpoint is only valid for a re-run with the same weight, as the
+        // groups stored before it were stored with that
+        let checkpoint: Option<u32> = self
+            .conn
+            .query_row(
+                "SELECT checkpoint FROM fetches WHERE id = ? AND type = ? AND weight = ?",
+                params![fetch_id, fetch_type.code(), weight],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        // The response may have changed since, so go by the groups the fetch
+        // already has rather than by where the last run stopped
+        let groups = match checkpoint {
+            Some(_) => {
+                let stored = self.fetch_group_ids(fetch_id, fetch_type)?;
+                let rest: Vec<_> = groups
+                    .into_iter()
+                    .filter(|g| {
+                        !g.first()
+                            .and_then(|t| t.group_id)
+                            .is_some_and(|id| stored.contains(&id))
+                    })
+                    .collect();
+                info!(
+                    fetch = %fetch_type,
+                    id = fetch_id,
+                    stored = stored.len(),
+                    "Going on from the last checkpoint"
+                );
+                rest
+            }
+            None => groups,
+        };
+
+        let progress = progress_bar(groups.len() as u64, "Storing groups");
+        let mut rest = &groups[..];
+        let mut done = 0;
+        loop {
+            let (chunk, tail) = rest.split_at(rest.len().min(CHECKPOINT_GROUPS));
+            let tx = self.conn.unchecked_transaction()?;
+            tx.execute(
+                r#"
+                INSERT INTO fetches (id, type, name, weight, source, category)
+                VALUES (?, ?, ?, ?, ?, ?)
+                ON CONFLICT(id, type) DO UPDATE
+                    SET name = excluded.name, weight = excluded.weight, source = excluded.source,
+                        category = excluded.category
+                "#,
+                params![
+                    fetch_id,
+                    fetch_type.code(),
+                    fetch_name,
+                    weight,
+                    source,
+                    category
+                ],
+            )?;
+            for g in chunk {
+                progress.inc(1);
+                store_group(&tx, g, fetch_id, fetch_type, &mut summary)?;
+            }
+            // Groups stored so far, set for as long as the fetch is unfinished
+            done += chunk.len();
+            let checkpoint = (!tail.is_empty()).then_some(done);
+            tx.execute(
+                "UPDATE fetches SET checkpoint = ? WHERE id = ? AND type = ?",
+                params![checkpoint, fetch_id, fetch_type.code()],
+            )?;
+            tx.commit()?;
+            if tail.is_empty() {
+                break;
+            }
+            rest = tail;
+        }
+        progress.finish_and_clear();
+
+        Ok(summary)
+    }
+
+    /// Torrent groups of the pool torrents that came from a fetch.
+    fn fetch_group_ids(&self, fetch_id: u32, fetch_type: Type) -> Result<HashSet<u32>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT DISTINCT t.group_id FROM torrent_fetches tf
+            JOIN torrents t ON t.id = tf.torrent_id
+            WHERE tf.fetch_id = ? AND tf.fetch_type = ? AND t.group_id IS NOT NULL
+            "#,
+        )?;
+        let ids = stmt
+            .query_map(params![fetch_id, fetch_type.code()], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    /// Replace the recorded aliases of `artist`.
+    fn store_aliases(&self, artist: &str, aliases: &[String]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM artist_aliases WHERE artist = ?", [artist])?;
+        for alias in aliases {
+            tx.execute(
+                "INSERT OR IGNORE INTO artist_aliases (artist, alias) VALUES (?, ?)",
+                [artist, alias],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Other names of fetched artists, by artist name.
+    pub fn artist_aliases(&self) -> Result<HashMap<String, Vec<String>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT artist, alias FROM artist_aliases ORDER BY artist, alias")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        Ok(rows.into_iter().into_group_map())
+    }
+
+    /// Drop the fetch of artist `requested_id` once it turned out to redirect
+    /// to the artist in `data`, which is now stored under its own id.
+    pub fn drop_alias_fetch(&self, data: &GroupData, requested_id: u32) -> Result<()> {
+        if let GroupData::ArtistData(artist) = data
+            && artist.id != requested_id
+        {
+            self.remove_fetch(Type::Artist, requested_id, false)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> Result<DatabaseStats> {
+        let total_torrents: i64 =
+            self.conn
+                .query_row("SELECT COUNT(*) FROM torrents", [], |row| row.get(0))?;
+
+        let unique_artists: i64 = self.conn.query_row(
+            "SELECT COUNT(DISTINCT artist_names) FROM torrents",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let unique_albums: i64 = self.conn.query_row(
+            "SELECT COUNT(DISTINCT album_name) FROM torrents",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let format_counts = self.count_by("format")?;
+        let media_counts = self.count_by("media")?;
+        let encoding_counts = self.count_by("encoding")?;
+        let release_type_counts = self
+            .count_by("release_type")?
+            .into_iter()
+            .map(|(code, count)| {
+                let name = code
+                    .parse()
+                    .ok()
+                    .and_then(release_type_name)
+                    .map(str::to_string)
+                    .unwrap_or(code);
+                (name, count)
+            })
+            .collect();
+
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT year / 10 * 10 AS decade, COUNT(*) FROM torrents
+            GROUP BY decade ORDER BY decade
+            "#,
+        )?;
+        let decade_counts = stmt
+            .query_map([], |row| {
+                let label = match row.get::<_, Option<u32>>(0)? {
+                    Some(decade) if decade > 0 => format!("{}s", decade),
+                    _ => "Unknown".to_string(),
+                };
+                Ok((label, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let (total_size, average_size): (i64, f64) = self.conn.query_row(
+            "SELECT COALESCE(SUM(size_bytes), 0), COALESCE(AVG(size_bytes), 0) FROM torrents",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT weight, COUNT(*), SUM(size_bytes) FROM torrents
+            GROUP BY weight ORDER BY weight DESC
+            "#,
+        )?;
+        let weight_counts = stmt
+            .query_map([], |row| {
+                Ok(WeightStats {
+                    weight: row.get(0)?,
+                    count: row.get(1)?,
+                    size: row.get::<_, i64>(2)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT f.id, f.type, f.name, f.weight,
+                COUNT(t.id) AS torrents,
+                COALESCE(SUM(t.size_bytes), 0) AS size,
+                COALESCE(SUM(
+                    EXISTS(SELECT 1 FROM downloads d WHERE d.torrent_id = tf.torrent_id)
+                ), 0) AS downloaded
+            FROM fetches f
+            LEFT JOIN torrent_fetches tf ON tf.fetch_id = f.id AND tf.fetch_type = f.type
+            LEFT JOIN torrents t ON t.id = tf.torrent_id
+            GROUP BY f.id, f.type
+            ORDER BY torrents DESC
+            "#,
+        )?;
+        let fetch_stats = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, u32>("type")?,
+                FetchStats {
+                    id: row.get("id")?,
+                    ftype: Type::Artist,
+                    name: row.get("name")?,
+                    weight: row.get("weight")?,
+                    torrents: row.get("torrents")?,
+                    size: row.get::<_, i64>("size")? as u64,
+                    downloaded: row.get("downloaded")?,
+                },
+            ))
+        })?;
+        let fetch_stats = readable_rows(fetch_stats)
+            .into_iter()
+            .filter_map(|(code, f)| {
+                let ftype = Type::from_code(code);
+                if ftype.is_none() {
+                    warn!(id = f.id, code, "Skipping fetch of unknown type");
+                }
+                ftype.map(|ftype| FetchStats { ftype, ..f })
+            })
+            .collect();
+
+        Ok(DatabaseStats {
+            total_torrents,
+            unique_artists,
+            unique_albums,
+            format_counts,
+            media_counts,
+            encoding_counts,
+            release_type_counts,
+            decade_counts,
+            total_size: total_size as u64,
+            average_size: average_size as u64,
+            weight_counts,
+            fetch_stats,
+        })
+    }
+
+    /// Number of pool torrents per value of `column`, most common first.
+    fn count_by(&self, column: &str) -> Result<Vec<(String, i64)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT CAST({0} AS TEXT), COUNT(*) AS count FROM torrents GROUP BY {0} ORDER BY count DESC",
+            column
+        ))?;
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(counts)
+    }
+
+    /// Number of torrents in the pool.
+    pub fn pool_size(&self) -> Result<usize> {
+        Ok(self
+            .conn
+            .query_row("SELECT COUNT(*) FROM torrents", [], |row| row.get(0))?)
+    }
+
+    /// Pool torrents matching `filter`, ordered by `sort` and limited to one
+    /// page, together with the total number of matches.
+    pub fn list_torrents(
+        &self,
+        filter: &PoolFilter,
+        sort: PoolSort,
+        reverse: bool,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<Torrent>, usize)> {
+        let mut conditions = vec!["1 = 1"];
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(artist) = &filter.artist {
+            conditions.push("artist_names LIKE '%' || ? || '%'");
+            values.push(artist.clone().into());
+        }
+        if let Some(year) = filter.year {
+            conditions.push("year = ?");
+            values.push(year.into());
+        }
+        if let Some(encoding) = &filter.encoding {
+            conditions.push("encoding LIKE ? || '%'");
+            values.push(encoding.clone().into());
+        }
+        if let Some(min_weight) = filter.min_weight {
+            conditions.push("weight >= ?");
+            values.push(min_weight.into());
+        }
+        if let Some(query) = filter.query.as_deref().map(fts_query)
+            && !query.is_empty()
+        {
+            conditions.push("id IN (SELECT rowid FROM torrents_fts WHERE torrents_fts MATCH ?)");
+            values.push(query.into());
+        }
+        let filter_sql = conditions.join(" AND ");
+
+        let total: usize = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM torrents WHERE {}", filter_sql),
+            rusqlite::params_from_iter(&values),
+            |row| row.get(0),
+        )?;
+
+        let (column, descending) = sort.column();
+        let direction = if descending != reverse { "DESC" } else { "ASC" };
+        let mut stmt = self.conn.prepare(&format!(
+            r#"
+            SELECT id, group_id, album_name, artist_names, year, release_type, media, format,
+                encoding, file_count, weight, size_bytes, created_at, cover_url
+            FROM torrents WHERE {}
+            ORDER BY {} {}, id
+            LIMIT {} OFFSET {}
+            "#,
+            filter_sql, column, direction, limit, offset
+        ))?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(&values), torrent_from_row)?;
+        Ok((readable_rows(rows), total))
+    }
+
+    /// Pool torrents with one of `ids` or whose artist names contain `artist`.
+    pub fn find_torrents(&self, ids: &[u32], artist: Option<&str>) -> Result<Vec<Torrent>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if !ids.is_empty() {
+            conditions.push(format!("id IN ({})", vec!["?"; ids.len()].join(", ")));
+            values.extend(ids.iter().map(|&id| id.into()));
+        }
+        if let Some(artist) = artist {
+            conditions.push("artist_names LIKE '%' || ? || '%'".to_string());
+            values.push(artist.to_string().into());
+        }
+        if conditions.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self.conn.prepare(&format!(
+            r#"
+            SELECT id, group_id, album_name, artist_names, year, release_type, media, format,
+                encoding, file_count, weight, size_bytes, created_at, cover_url
+            FROM torrents WHERE {}
+            ORDER BY artist_names COLLATE NOCASE, year
+            "#,
+            conditions.join(" OR ")
+        ))?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(&values), torrent_from_row)?;
+        Ok(readable_rows(rows))
+    }
+
+    /// Pool torrents the tracker has a cover image for.
+    pub fn torrents_with_covers(&self) -> Result<Vec<Torrent>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT id, group_id, album_name, artist_names, year, release_type, media, format,
+                encoding, file_count, weight, size_bytes, created_at, cover_url
+            FROM torrents WHERE cover_url IS NOT NULL
+            ORDER BY weight DESC, id
+            "#,
+        )?;
+        let rows = stmt.query_map([], torrent_from_row)?;
+        Ok(readable_rows(rows))
+    }
+
+    /// Delete torrents from the pool along with their provenance links,
+    /// returning how many were deleted.
+    pub fn remove_torrents(&self, ids: &[u32]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut removed = 0;
+        for id in ids {
+            tx.execute("DELETE FROM torrent_fetches WHERE torrent_id = ?", [id])?;
+            removed += tx.execute("DELETE FROM torrents WHERE id = ?", [id])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// All fetches and pool torrents, with the provenance of each torrent.
+    pub fn export_pool(&self) -> Result<PoolExport> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, type, name, weight, created_at, source, category FROM fetches ORDER BY created_at",
+        )?;
+        let fetches = stmt
+            .query_map([], |row| {
+                Ok(FetchRecord {
+                    id: row.get("id")?,
+                    ftype: Type::from_code(row.get("type")?)
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                    name: row.get("name")?,
+                    weight: row.get("weight")?,
+                    created_at: row.get("created_at")?,
+                    source: row.get("source")?,
+                    category: row.get("category")?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT t.id, t.group_id, t.album_name, t.artist_names, t.year, t.release_type, t.media,
+                t.format, t.encoding, t.file_count, t.size_bytes, t.weight, t.created_at,
+                t.cover_url, GROUP_CONCAT(tf.fetch_type || ':' || tf.fetch_id, ';') AS sources
+            FROM torrents t
+            LEFT JOIN torrent_fetches tf ON tf.torrent_id = t.id
+            GROUP BY t.id
+            ORDER BY t.id
+            "#,
+        )?;
+        let torrents = stmt
+            .query_map([], |row| {
+                Ok(TorrentRecord {
+                    id: row.get("id")?,
+                    group_id: row.get("group_id")?,
+                    album_name: row.get("album_name")?,
+                    artist_names: row.get("artist_names")?,
+                    year: row.get("year")?,
+                    release_type: row.get("release_type")?,
+                    media: row.get("media")?,
+                    format: row.get("format")?,
+                    encoding: row.get("encoding")?,
+                    file_count: row.get("file_count")?,
+                    size_bytes: row.get::<_, i64>("size_bytes")? as u64,
+                    weight: row.get("weight")?,
+                    created_at: row.get("created_at")?,
+                    cover_url: row.get("cover_url")?,
+                    sources: export_sources(row.get("sources")?),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(PoolExport { fetches, torrents })
+    }
+
+    /// Merge an export into the pool. Fetches are added or updated, torrents
+    /// already in the pool, under their own id or as a duplicate record, are
+    /// resolved with `policy`.
+    pub fn import_pool(
+        &self,
+        export: &PoolExport,
+        policy: ConflictPolicy,
+    ) -> Result<ImportSummary> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut summary = ImportSummary::default();
+        for f in &export.fetches {
+            let ftype = Type::from_str(&f.ftype, true).map_err(|_| {
+                RedmanError::InvalidInput(format!("Invalid fetch type {:?}", f.ftype))
+            })?;
+            tx.execute(
+                r#"
+                INSERT INTO fetches (id, type, name, weight, created_at, source, category)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(id, type) DO UPDATE
+                    SET name = excluded.name, weight = excluded.weight, source = excluded.source,
+                        category = excluded.category
+                "#,
+                params![
+                    f.id,
+                    ftype.code(),
+                    f.name,
+                    f.weight,
+                    f.created_at,
+                    f.source,
+                    f.category
+                ],
+            )?;
+            summary.fetches += 1;
+        }
+        for t in &export.torrents {
+            let existing: Option<u32> = tx
+                .query_row("SELECT weight FROM torrents WHERE id = ?", [t.id], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            let write = |weight: Option<u32>| match (weight, policy) {
+                (None, _) => true,
+                (Some(_), ConflictPolicy::Skip) => false,
+                (Some(_), ConflictPolicy::Replace) => true,
+                (Some(weight), ConflictPolicy::HighestWeight) => t.weight > weight,
+            };
+            if !write(existing) {
+                summary.skipped += 1;
+                continue;
+            }
+            let sources = portable::parse_sources(&t.sources)?;
+            // The same record under another torrent id counts as a conflict too
+            let key = release_key(&t.artist_names, &t.album_name);
+            let dup: Option<(u32, u32)> = tx
+                .query_row(
+                    "SELECT id, weight FROM torrents WHERE release_key = ? AND id != ?",
+                    params![key, t.id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            if let Some((dup_id, dup_weight)) = dup {
+                summary.duplicates += 1;
+                if !write(Some(dup_weight)) {
+                    for (ftype, fetch_id) in sources {
+                        tx.execute(
+                            r#"
+                            INSERT OR IGNORE INTO torrent_fetches (torrent_id, fetch_id, fetch_type)
+                            VALUES (?, ?, ?)
+                            "#,
+                            params![dup_id, fetch_id, ftype.code()],
+                        )?;
+                    }
+                    continue;
+                }
+                replace_duplicate(&tx, dup_id, t.id)?;
+            }
+            tx.execute(
+                r#"
+                INSERT INTO torrents (
+                    id, group_id, album_name, artist_names, year, release_type, media, format,
+                    encoding, file_count, size_bytes, weight, created_at, release_key, cover_url
+                ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(id) DO UPDATE SET
+                    group_id = excluded.group_id, album_name = excluded.album_name,
+                    artist_names = excluded.artist_names, year = excluded.year,
+                    release_type = excluded.release_type, media = excluded.media,
+                    format = excluded.format, encoding = excluded.encoding,
+                    file_count = excluded.file_count, size_bytes = excluded.size_bytes,
+                    weight = excluded.weight, created_at = excluded.created_at,
+                    release_key = excluded.release_key, cover_url = excluded.cover_url
+                "#,
+                params![
+                    t.id,
+                    t.group_id,
+                    t.album_name,
+                    t.artist_names,
+                    t.year,
+                    t.release_type,
+                    t.media,
+                    t.format,
+                    t.encoding,
+                    t.file_count,
+                    t.size_bytes as i64,
+                    t.weight,
+                    t.created_at,
+                    key,
+                    t.cover_url,
+                ],
+            )?;
+            for (ftype, fetch_id) in sources {
+                tx.execute(
+                    r#"
+                    INSERT OR IGNORE INTO torrent_fetches (torrent_id, fetch_id, fetch_type)
+                    VALUES (?, ?, ?)
+                    "#,
+                    params![t.id, fetch_id, ftype.code()],
+                )?;
+            }
+            if existing.is_some() || dup.is_some() {
+                summary.replaced += 1;
+            } else {
+                summary.inserted += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Check integrity, then VACUUM and ANALYZE the database. Returns early
+    /// without rewriting anything if the integrity check fails.
+    pub fn maintain(&self) -> Result<MaintenanceReport> {
+        let size_before = self.size_bytes()?;
+        let free_pages: u64 = self
+            .conn
+            .query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        let problems: Vec<String> = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?
+            .into_iter()
+            .filter(|msg| msg != "ok")
+            .collect();
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT name, tbl_name FROM sqlite_master
+            WHERE type = 'index' ORDER BY tbl_name, name
+            "#,
+        )?;
+        let indexes = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+
+        if problems.is_empty() {
+            self.conn.execute_batch(
+                r#"
+                INSERT INTO torrents_fts (torrents_fts) VALUES ('optimize');
+                VACUUM;
+                ANALYZE;
+                PRAGMA optimize;
+                "#,
+            )?;
+        }
+        Ok(MaintenanceReport {
+            size_before,
+            size_after: self.size_bytes()?,
+            free_pages,
+            problems,
+            indexes,
+        })
+    }
+
+    /// Copy the database to `path` with SQLite's online backup, so it is
+    /// consistent even while another process writes to the pool.
+    pub fn backup(&self, path: &Path) -> Result<()> {
+        self.conn.backup(MAIN_DB, path, None)?;
+        Ok(())
+    }
+
+    /// Replace the contents of the database with the backup at `path`.
+    pub fn restore(&mut self, path: &Path) -> Result<()> {
+        let backup = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        backup
+            .query_row("SELECT COUNT(*) FROM torrents", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map_err(|e| {
+                RedmanError::InvalidInput(format!("{} is not a pool backup: {}", path.display(), e))
+            })?;
+        drop(backup);
+        self.conn
+            .restore(MAIN_DB, path, None::<fn(rusqlite::backup::Progress)>)?;
+        migrations::migrate(&self.conn)?;
+        Ok(())
+    }
+
+    fn size_bytes(&self) -> Result<u64> {
+        let size = self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count, pragma_page_size",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(size)
+    }
+
+    /// Set or, with `None`, clear the mark of a pool torrent.
+    pub fn set_mark(&self, torrent_id: u32, mark: Option<Mark>) -> Result<()> {
+        match mark {
+            Some(mark) => self.conn.execute(
+                "INSERT OR REPLACE INTO torrent_marks (torrent_id, mark) VALUES (?, ?)",
+                params![torrent_id, mark.as_str()],
+            )?,
+            None => self.conn.execute(
+                "DELETE FROM torrent_marks WHERE torrent_id = ?",
+                [torrent_id],
+            )?,
+        };
+        Ok(())
+    }
+
+    pub fn get_marks(&self) -> Result<HashMap<u32, Mark>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT torrent_id, mark FROM torrent_marks")?;
+        let marks = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?;
+        let marks = readable_rows(marks)
+            .into_iter()
+            .filter_map(|(id, mark): (u32, String)| {
+                let m = Mark::from_str(&mark);
+                if m.is_none() {
+                    warn!(id, mark, "Skipping unknown mark");
+                }
+                m.map(|m| (id, m))
+            })
+            .collect();
+        Ok(marks)
+    }
+
+    /// The fetches that contributed a pool torrent.
+    pub fn torrent_sources_of(&self, torrent_id: u32) -> Result<Vec<Fetch>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT f.id, f.type, f.name, f.weight, f.created_at, f.source, f.category,
+                (SELECT COUNT(*) FROM torrent_fetches c
+                    WHERE c.fetch_id = f.id AND c.fetch_type = f.type) AS torrents
+            FROM fetches f
+            JOIN torrent_fetches tf ON tf.fetch_id = f.id AND tf.fetch_type = f.type
+            WHERE tf.torrent_id = ?
+            ORDER BY f.weight DESC
+            "#,
+        )?;
+        let fetches = stmt.query_map([torrent_id], fetch_from_row)?;
+        let fetches = readable_rows(fetches).into_iter().flatten().collect();
+        Ok(fetches)
+    }
+
+    /// Pool torrents whose artist or album names match every word of
+    /// `query` as a prefix, best matches first.
+    pub fn search_torrents(&self, query: &str, limit: usize) -> Result<Vec<Torrent>> {
+        let terms = fts_query(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT t.id, t.group_id, t.album_name, t.artist_names, t.year, t.release_type, t.media,
+                t.format, t.encoding, t.file_count, t.weight, t.size_bytes, t.created_at,
+                t.cover_url
+            FROM torrents_fts
+            JOIN torrents t ON t.id = torrents_fts.rowid
+            WHERE torrents_fts MATCH ?
+            ORDER BY rank
+            LIMIT ?
+            "#,
+        )?;
+        let rows = stmt.query_map(params![terms, limit], torrent_from_row)?;
+        Ok(readable_rows(rows))
+    }
+}
+
+/// Outcome of [`Database::maintain`].
+#[derive(Debug)]
+pub struct MaintenanceReport {
+    pub size_before: u64,
+    pub size_after: u64,
+    /// Unused pages before vacuuming
+    pub free_pages: u64,
+    /// Messages from the integrity check; empty if it passed
+    pub problems: Vec<String>,
+    /// Index names and the table each belongs to
+    pub indexes: Vec<(String, String)>,
+}
+
+/// Turn free text into an FTS5 query matching every word as a prefix.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .join(" ")
+}
+
+/// Filters for listing the pool; `None` matches everything.
+#[derive(Debug, Default)]
+pub struct PoolFilter {
+    /// Substring of the artist names, case-insensitive
+    pub artist: Option<String>,
+    pub year: Option<u32>,
+    /// Prefix of the encoding, e.g. "V0" or "320"
+    pub encoding: Option<String>,
+    pub min_weight: Option<u32>,
+    /// Words matching the start of words in artist or album names
+    pub query: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolSort {
+    Weight,
+    Artist,
+    Album,
+    Year,
+    Size,
+    Added,
+}
+
+impl PoolSort {
+    /// Column to order by and whether it sorts descending by default.
+    fn column(&self) -> (&'static str, bool) {
+        match self {
+            PoolSort::Weight => ("weight", true),
+            PoolSort::Artist => ("artist_names COLLATE NOCASE", false),
+            PoolSort::Album => ("album_name COLLATE NOCASE", false),
+            PoolSort::Year => ("year IS NULL, year", false),
+            PoolSort::Size => ("size_bytes", true),
+            PoolSort::Added => ("created_at", true),
+        }
+    }
+}
+
+/// Progress of a torrent through a watch run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JournalState {
+    Selected,
+    Downloaded,
+    Added,
+    Skipped,
+}
+
+impl JournalState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JournalState::Selected => "selected",
+            JournalState::Downloaded => "downloaded",
+            JournalState::Added => "added",
+            JournalState::Skipped => "skipped",
+        }
+    }
+
+    fn from_str(s: &str) -> Self {
+        match s {
+            "downloaded" => JournalState::Downloaded,
+            "added" => JournalState::Added,
+            "skipped" => JournalState::Skipped,
+            _ => JournalState::Selected,
+        }
+    }
+}
+
+/// A user's manual decision about a pool torrent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    /// Never select it
+    Skip,
+    /// Already owned elsewhere, never select it
+    Own,
+    /// Select it before anything else
+    Prioritize,
+}
+
+impl Mark {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mark::Skip => "skip",
+            Mark::Own => "own",
+            Mark::Prioritize => "prioritize",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "skip" => Some(Mark::Skip),
+            "own" => Some(Mark::Own),
+            "prioritize" => Some(Mark::Prioritize),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct JournalEntry {
+    pub(crate) torrent: Torrent,
+    pub(crate) state: JournalState,
+    pub(crate) path: Option<PathBuf>,
+}
+
+/// A torrent added to the client by a watch run.
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub torrent_id: u32,
+    pub group_id: Option<u32>,
+    pub artist_names: String,
+    pub album_name: String,
+    pub size: u64,
+    pub fl_token: bool,
+    pub added_at: String,
+    /// Identifies the torrent in the client; unknown for old downloads
+    pub info_hash: Option<String>,
+    /// When the client was first seen to have finished it
+    pub completed_at: Option<String>,
+    /// The tracker's minimum seed time when it was added
+    pub min_seed_time: Option<Duration>,
+    /// Ratio the client last reported
+    pub ratio: Option<f64>,
+    /// Time seeded as the client last reported
+    pub seeding_time: Option<Duration>,
+}
+
+impl Download {
+    /// Whether it was seeded for the minimum seed time, or no minimum applies.
+    pub fn seeded_enough(&self) -> bool {
+        self.min_seed_time
+            .is_none_or(|min| self.seeding_time.is_some_and(|t| t >= min))
+    }
+}
+
+/// A lossless download replacing an MP3 album in Plex.
+#[derive(Debug, Clone)]
+pub struct Upgrade {
+    pub torrent_id: u32,
+    /// Plex's id of the MP3 album
+    pub plex_album_id: i64,
+    /// Artist and album as Plex names them
+    pub artist_names: String,
+    pub album_name: String,
+    /// Folder of the MP3 files
+    pub old_path: Option<String>,
+    pub created_at: String,
+    /// When the client finished the lossless download
+    pub completed_at: Option<String>,
+}
+
+/// Format a release year, with a dash when it is unknown.
+pub fn format_year(year: Option<u32>) -> String {
+    year.map_or_else(|| "—".to_string(), |y| y.to_string())
+}
+
+/// Format a byte count with binary units, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// A collage or artist previously fetched into the pool.
+#[derive(Debug, Clone)]
+pub struct Fetch {
+    pub id: u32,
+    pub ftype: Type,
+    pub name: String,
+    pub weight: u32,
+    pub created_at: String,
+    /// Number of pool torrents this fetch contributed
+    pub torrents: u32,
+    /// What a fetch of a service besides the tracker is looked up by, such as
+    /// a Spotify playlist id
+    pub source: Option<String>,
+    /// Category of a collage, such as "Staff picks"
+    pub category: Option<String>,
+}
+
+/// Readable name of a tracker release type code.
+pub fn release_type_name(code: u32) -> Option<&'static str> {
+    let name = match code {
+        1 => "Album",
+        3 => "Soundtrack",
+        5 => "EP",
+        6 => "Anthology",
+        7 => "Compilation",
+        9 => "Single",
+        11 => "Live album",
+        13 => "Remix",
+        14 => "Bootleg",
+        15 => "Interview",
+        16 => "Mixtape",
+        17 => "Demo",
+        18 => "Concert Recording",
+        19 => "DJ Mix",
+        21 => "Unknown",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Torrents written by [`Database::store_data`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StoreSummary {
+    /// Torrents new to the pool
+    pub inserted: u32,
+    /// Torrents already in the pool that were refreshed
+    pub replaced: u32,
+    /// Records already in the pool under another torrent id
+    pub duplicates: u32,
+}
+
+/// Store the preferred torrent of `group` for the fetch, keeping the heavier
+/// of duplicates.
+fn store_group(
+    conn: &Connection,
+    group: &[Torrent],
+    fetch_id: u32,
+    fetch_type: Type,
+    summary: &mut StoreSummary,
+) -> Result<()> {
+    let Some(t) = preferred_torrent(group.iter().filter(|t| t.release_type == 1)) else {
+        return Ok(());
+    };
+    let mut exists = conn.prepare_cached("SELECT EXISTS(SELECT 1 FROM torrents WHERE id = ?)")?;
+    let mut duplicate =
+        conn.prepare_cached("SELECT id, weight FROM torrents WHERE release_key = ? AND id != ?")?;
+    let mut insert = conn.prepare_cached(
+        r#"
+        INSERT OR REPLACE INTO torrents (
+            id, 
+            group_id,
+            album_name, 
+            artist_names,
+            year, 
+            release_type,
+            media, 
+            format, 
+            encoding, 
+            file_count,
+            weight, 
+            size_bytes,
+            release_key,
+            cover_url,
+            created_at
+        ) VALUES (
+            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+            COALESCE((SELECT created_at FROM torrents WHERE id = ?), CURRENT_TIMESTAMP)
+        )
+        "#,
+    )?;
+    let mut link = conn.prepare_cached(
+        r#"
+        INSERT OR IGNORE INTO torrent_fetches (torrent_id, fetch_id, fetch_type)
+        VALUES (?, ?, ?)
+        "#,
+    )?;
+    // The same record can come in under another torrent id, e.g.
+    // once via a collage and once via the artist; keep the heavier.
+    let key = release_key(&t.artist_names, &t.album_name);
+    let dup: Option<(u32, u32)> = duplicate
+        .query_row(params![key, t.id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()?;
+    if let Some((dup_id, dup_weight)) = dup {
+        if dup_weight >= t.weight {
+            link.execute(params![dup_id, fetch_id, fetch_type.code()])?;
+            summary.duplicates += 1;
+            return Ok(());
+        }
+        replace_duplicate(conn, dup_id, t.id)?;
+        summary.duplicates += 1;
+    }
+    let existed: bool = exists.query_row([t.id], |row| row.get(0))?;
+    let result = insert.execute(params![
+        t.id,
+        t.group_id,
+        t.album_name,
+        t.artist_names,
+        t.year,
+        t.release_type,
+        t.media,
+        t.format,
+        t.encoding,
+        t.file_count,
+        t.weight,
+        t.size as i64,
+        key,
+        t.cover_url,
+        t.id,
+    ])?;
+    link.execute(params![t.id, fetch_id, fetch_type.code()])?;
+
+    if result > 0 {
+        if existed {
+            summary.replaced += 1;
+        } else {
+            summary.inserted += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Drop pool torrent `old_id` in favour of `new_id` for the same record,
+/// moving its provenance and mark over.
+pub(crate) fn replace_duplicate(
+    conn: &Connection,
+    old_id: u32,
+    new_id: u32,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE OR IGNORE torrent_fetches SET torrent_id = ? WHERE torrent_id = ?",
+        [new_id, old_id],
+    )?;
+    conn.execute("DELETE FROM torrent_fetches WHERE torrent_id = ?", [old_id])?;
+    conn.execute(
+        "UPDATE OR IGNORE torrent_marks SET torrent_id = ? WHERE torrent_id = ?",
+        [new_id, old_id],
+    )?;
+    conn.execute("DELETE FROM torrent_marks WHERE torrent_id = ?", [old_id])?;
+    conn.execute("DELETE FROM torrents WHERE id = ?", [old_id])?;
+    Ok(())
+}
+
+impl std::ops::AddAssign for StoreSummary {
+    fn add_assign(&mut self, other: Self) {
+        self.inserted += other.inserted;
+        self.replaced += other.replaced;
+        self.duplicates += other.duplicates;
+    }
+}
+
+impl std::fmt::Display for StoreSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} new, {} updated, {} duplicates",
+            self.inserted, self.replaced, self.duplicates
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseStats {
+    pub total_torrents: i64,
+    pub unique_artists: i64,
+    pub unique_albums: i64,
+    #[serde(serialize_with = "counts_as_map")]
+    pub format_counts: Vec<(String, i64)>,
+    #[serde(serialize_with = "counts_as_map")]
+    pub media_counts: Vec<(String, i64)>,
+    #[serde(serialize_with = "counts_as_map")]
+    pub encoding_counts: Vec<(String, i64)>,
+    /// Counts per release type name, e.g. "Album" or "EP"
+    #[serde(serialize_with = "counts_as_map")]
+    pub release_type_counts: Vec<(String, i64)>,
+    /// Counts per release decade, e.g. "1990s", oldest first
+    #[serde(serialize_with = "counts_as_map")]
+    pub decade_counts: Vec<(String, i64)>,
+    pub total_size: u64,
+    pub average_size: u64,
+    /// Pool torrents per weight, heaviest first
+    pub weight_counts: Vec<WeightStats>,
+    /// Contribution of every stored fetch, largest first
+    pub fetch_stats: Vec<FetchStats>,
+}
+
+/// Serialize `(value, count)` pairs as a JSON object, keeping their order.
+fn counts_as_map<S: Serializer>(
+    counts: &[(String, i64)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(counts.iter().map(|(k, v)| (k, v)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct FetchStats {
+    pub id: u32,
+    #[serde(rename = "type")]
+    pub ftype: Type,
+    pub name: String,
+    pub weight: u32,
+    /// Pool torrents this fetch contributed
+    pub torrents: i64,
+    pub size: u64,
+    /// Contributed torrents that were added to the client
+    pub downloaded: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WeightStats {
+    pub weight: u32,
+    pub count: i64,
+    pub size: u64,
+}
+
+/// Provenance as `<type code>:<id>;...` turned into `<type>:<id>;...`, the
+/// form `portable::parse_sources` reads. Unknown types are left out.
+fn export_sources(sources: Option<String>) -> String {
+    sources
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|source| {
+            let (code, id) = source.split_once(':')?;
+            let ftype = Type::from_code(code.parse().ok()?)?;
+            Some(format!("{}:{}", ftype, id))
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Map a `fetches` row with a `torrents` count; `None` for an unknown type.
+/// The fetch of a row, or `None` with a warning for a type this version does
+/// not know, e.g. one added by a newer redman.
+fn fetch_from_row(row: &rusqlite::Row) -> rusqlite::Result<Option<Fetch>> {
+    let code: u32 = row.get("type")?;
+    let Some(ftype) = Type::from_code(code) else {
+        warn!(
+            id = row.get::<_, u32>("id")?,
+            code, "Skipping fetch of unknown type"
+        );
+        return Ok(None);
+    };
+    Ok(Some(Fetch {
+        id: row.get("id")?,
+        ftype,
+        name: row.get("name")?,
+        weight: row.get("weight")?,
+        created_at: row.get("created_at")?,
+        torrents: row.get("torrents")?,
+        source: row.get("source")?,
+        category: row.get("category")?,
+    }))
+}
+
+fn torrent_from_row(row: &rusqlite::Row) -> rusqlite::Result<Torrent> {
+    Ok(Torrent {
+        id: row.get("id")?,
+        group_id: row.get("group_id")?,
+        album_name: row.get("album_name")?,
+        artist_names: row.get("artist_names")?,
+        year: row.get("year")?,
+        release_type: row.get("release_type")?,
+        media: row.get("media")?,
+        format: row.get("format")?,
+        encoding: row.get("encoding")?,
+        file_count: row.get("file_count")?,
+        weight: row.get("weight")?,
+        size: row.get::<_, i64>("size_bytes")? as u64,
+        created_at: NaiveDateTime::parse_from_str(
+            &row.get::<_, String>("created_at")?,
+            "%Y-%m-%d %H:%M:%S",
+        )
+        .unwrap_or_else(|_| Utc::now().naive_utc()),
+        cover_url: row.get("cover_url")?,
+    })
+}
+
+/// Collect the rows of a query, skipping the ones that cannot be read with a
+/// warning so one malformed row does not fail the whole query.
+fn readable_rows<T>(rows: impl Iterator<Item = rusqlite::Result<T>>) -> Vec<T> {
+    let mut skipped = 0;
+    let items = rows
+        .filter_map(|row| {
+            row.inspect_err(|e| {
+                warn!("Skipping unreadable pool row: {}", e);
+                skipped += 1;
+            })
+            .ok()
+        })
+        .collect();
+    if skipped > 0 {
+        warn!(skipped, "Some pool rows could not be read");
+    }
+    items
+}
+
+pub(crate) fn get_pool_torrents(db_path: &str) -> Result<Vec<Torrent>> {
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    let mut stmt = conn.prepare(
+        r#"
+            SELECT id, group_id, album_name, artist_names, year, release_type, media, format, encoding, file_count, weight, size_bytes, created_at, cover_url
+            FROM torrents
+        "#)?;
+    Ok(readable_rows(stmt.query_map([], torrent_from_row)?))
+}
+
+/// Key identifying the same record across torrent ids: artist and album
+/// lowercased with everything but letters and digits removed.
+pub(crate) fn release_key(artist_names: &str, album_name: &str) -> String {
+    let normalize = |s: &str| {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    format!("{}/{}", normalize(artist_names), normalize(album_name))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::api::ResolvedData;
+
+    pub(crate) fn torrent(id: u32, artist: &str, album: &str, weight: u32) -> Torrent {
+        Torrent {
+            id,
+            group_id: Some(id * 10),
+            album_name: album.to_string(),
+            artist_names: artist.to_string(),
+            year: Some(2001),
+            release_type: 1,
+            media: "CD".to_string(),
+            format: "MP3".to_string(),
+            encoding: "V0 (VBR)".to_string(),
+            file_count: 10,
+            size: 100 * 1024 * 1024,
+            weight,
+            created_at: NaiveDateTime::parse_from_str("2026-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            cover_url: None,
+        }
+    }
+
+    pub(crate) fn resolved(torrents: Vec<Torrent>) -> GroupData {
+        GroupData::Resolved(ResolvedData {
+            ftype: Type::Lidarr,
+            id: 1,
+            name: "Wanted".to_string(),
+            source: None,
+            torrents,
+        })
+    }
+
+    #[test]
+    fn resumes_torrents_missing_from_the_pool() {
+        let db = Database::new(":memory:").unwrap();
+        let (run_id, _) = db
+            .start_journal(&[
+                torrent(1, "Artist", "First", 5),
+                torrent(2, "Artist", "Second", 5),
+            ])
+            .unwrap();
+        db.set_journal_state(run_id, 1, JournalState::Added, None)
+            .unwrap();
+        let (resumed, entries) = db.unfinished_journal().unwrap().unwrap();
+        assert_eq!(resumed, run_id);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].torrent.id, 2);
+        assert_eq!(entries[0].torrent.album_name, "Second");
+        assert_eq!(entries[0].torrent.group_id, Some(20));
+
+        db.set_journal_state(run_id, 2, JournalState::Added, None)
+            .unwrap();
+        assert!(db.unfinished_journal().unwrap().is_none());
+    }
+
+    fn resolved_as(id: u32, torrents: Vec<Torrent>) -> GroupData {
+        let mut data = resolved(torrents);
+        if let GroupData::Resolved(r) = &mut data {
+            r.id = id;
+        }
+        data
+    }
+
+    fn ids(db: &Database, sql: &str) -> Vec<u32> {
+        db.conn
+            .prepare(sql)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn keeps_the_heavier_of_duplicate_releases() {
+        let db = Database::new(":memory:").unwrap();
+        let summary = db
+            .store_data(&resolved_as(1, vec![torrent(1, "Artist", "Album", 5)]), 5)
+            .unwrap();
+        assert_eq!((summary.inserted, summary.duplicates), (1, 0));
+        db.set_mark(1, Some(Mark::Own)).unwrap();
+
+        // Heavier under another id, so it takes the place of the first
+        let summary = db
+            .store_data(&resolved_as(2, vec![torrent(2, "ARTIST", "Album!", 8)]), 8)
+            .unwrap();
+        assert_eq!((summary.inserted, summary.duplicates), (1, 1));
+        assert_eq!(ids(&db, "SELECT id FROM torrents"), [2]);
+        assert_eq!(ids(&db, "SELECT torrent_id FROM torrent_marks"), [2]);
+
+        // Lighter, so only linked to the one kept
+        let summary = db
+            .store_data(&resolved_as(3, vec![torrent(3, "Artist", "Album", 3)]), 3)
+            .unwrap();
+        assert_eq!((summary.inserted, summary.duplicates), (0, 1));
+        assert_eq!(ids(&db, "SELECT id FROM torrents"), [2]);
+        assert_eq!(
+            ids(
+                &db,
+                "SELECT fetch_id FROM torrent_fetches WHERE torrent_id = 2 ORDER BY fetch_id"
+            ),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn round_trips_exports() {
+        let db = Database::new(":memory:").unwrap();
+        db.store_data(
+            &resolved_as(
+                1,
+                vec![
+                    torrent(1, "Artist", "First", 5),
+                    torrent(2, "Other", "Second", 5),
+                ],
+            ),
+            5,
+        )
+        .unwrap();
+        db.store_data(&resolved_as(2, vec![torrent(2, "Other", "Second", 5)]), 5)
+            .unwrap();
+        let export = db.export_pool().unwrap();
+        let json = serde_json::to_string(&export).unwrap();
+
+        let copy = Database::new(":memory:").unwrap();
+        let parsed: PoolExport = serde_json::from_str(&json).unwrap();
+        let summary = copy.import_pool(&parsed, ConflictPolicy::Skip).unwrap();
+        assert_eq!((summary.fetches, summary.inserted), (2, 2));
+        assert_eq!(
+            serde_json::to_string(&copy.export_pool().unwrap()).unwrap(),
+            json
+        );
+
+        let summary = copy.import_pool(&parsed, ConflictPolicy::Skip).unwrap();
+        assert_eq!((summary.inserted, summary.skipped), (0, 2));
+
+        let mut heavier: PoolExport = serde_json::from_str(&json).unwrap();
+        heavier.torrents[0].weight = 9;
+        let summary = copy
+            .import_pool(&heavier, ConflictPolicy::HighestWeight)
+            .unwrap();
+        assert_eq!((summary.replaced, summary.skipped), (1, 1));
+
+        // Another id for a record the pool has counts as a duplicate
+        let mut renamed: PoolExport = serde_json::from_str(&json).unwrap();
+        renamed.torrents.truncate(1);
+        renamed.torrents[0].id = 10;
+        let summary = copy
+            .import_pool(&renamed, ConflictPolicy::HighestWeight)
+            .unwrap();
+        assert_eq!((summary.inserted, summary.duplicates), (0, 1));
+        let summary = copy.import_pool(&renamed, ConflictPolicy::Replace).unwrap();
+        assert_eq!((summary.replaced, summary.duplicates), (1, 1));
+        assert_eq!(ids(&copy, "SELECT id FROM torrents ORDER BY id"), [2, 10]);
+    }
+
+    #[test]
+    fn resumes_groups_added_ahead_of_the_checkpoint() {
+        let db = Database::new(":memory:").unwrap();
+        db.store_data(&resolved(vec![torrent(1, "Artist", "First", 5)]), 5)
+            .unwrap();
+        // As if the run was interrupted after the first group
+        db.conn
+            .execute("UPDATE fetches SET checkpoint = 1", [])
+            .unwrap();
+
+        let data = resolved(vec![
+            torrent(2, "Artist", "Second", 5),
+            torrent(1, "Artist", "First", 5),
+            torrent(3, "Artist", "Third", 5),
+        ]);
+        db.store_data(&data, 5).unwrap();
+        let stored: Vec<u32> = db
+            .conn
+            .prepare("SELECT torrent_id FROM torrent_fetches ORDER BY torrent_id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(stored, [1, 2, 3]);
+        let checkpoint: Option<u32> = db
+            .conn
+            .query_row("SELECT checkpoint FROM fetches", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(checkpoint, None);
+    }
+
+    #[test]
+    fn skips_fetches_and_marks_it_does_not_know() {
+        let db = Database::new(":memory:").unwrap();
+        db.store_data(&resolved(vec![torrent(1, "Artist", "Album", 5)]), 5)
+            .unwrap();
+        db.conn
+            .execute_batch(
+                r#"
+                INSERT INTO fetches (id, type, name, weight) VALUES (2, 99, 'Future', 5);
+                INSERT INTO torrent_fetches (torrent_id, fetch_id, fetch_type) VALUES (1, 2, 99);
+                INSERT INTO torrent_marks (torrent_id, mark) VALUES (1, 'someday');
+                "#,
+            )
+            .unwrap();
+        let fetches = db.get_fetches().unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert!(matches!(fetches[0].ftype, Type::Lidarr));
+        assert_eq!(db.torrent_sources_of(1).unwrap().len(), 1);
+        assert_eq!(db.get_stats().unwrap().fetch_stats.len(), 1);
+        assert!(db.get_marks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn skips_journal_entries_it_cannot_resolve() {
+        let db = Database::new(":memory:").unwrap();
+        // Journaled before the torrents were, and gone from the pool since
+        db.conn
+            .execute(
+                "INSERT INTO journal (run_id, torrent_id, position, state) VALUES (1, 3, 0, 'selected')",
+                [],
+            )
+            .unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO journal (run_id, torrent_id, position, state) VALUES (1, 4, 1, 'selected')",
+                [],
+            )
+            .unwrap();
+        db.store_data(&resolved(vec![torrent(4, "Artist", "Album", 5)]), 5)
+            .unwrap();
+        let (_, entries) = db.unfinished_journal().unwrap().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].torrent.id, 4);
+    }
+}
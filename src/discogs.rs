@@ -0,0 +1,143 @@
+use std::sync::LazyLock;
+
+use itertools::Itertools;
+use regex::Regex;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::{
+    api::USER_AGENT,
+    error::{RedmanError, Result},
+};
+
+const DISCOGS_API_URL: &str = "https://api.discogs.com/";
+
+/// Releases asked for per page, the most Discogs allows.
+const PAGE_SIZE: usize = 100;
+
+/// The number Discogs appends to tell artists of the same name apart.
+static DISAMBIGUATION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r" \(\d+\)$").unwrap());
+
+/// A Discogs user whose wantlist is pooled with `redman fetch discogs`,
+/// configured in `[discogs]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Discogs {
+    pub user: String,
+    /// Personal access token from https://www.discogs.com/settings/developers,
+    /// needed for private wantlists and collections
+    pub token: Option<String>,
+    /// Also pool the releases in the user's collection
+    #[serde(default)]
+    pub collection: bool,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_api_url() -> String {
+    DISCOGS_API_URL.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page {
+    pagination: Pagination,
+    /// Wantlist entries
+    #[serde(default)]
+    wants: Vec<Entry>,
+    /// Collection entries
+    #[serde(default)]
+    releases: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    pages: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    basic_information: Release,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    title: String,
+    artists: Vec<Artist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Artist {
+    name: String,
+}
+
+impl Discogs {
+    /// Every entry of the paginated list at `path`.
+    async fn entries(&self, client: &Client, path: &str) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for page in 1.. {
+            let mut request = client
+                .get(format!("{}{}", self.api_url, path))
+                .query(&[("page", page), ("per_page", PAGE_SIZE)])
+                .header("User-Agent", USER_AGENT);
+            if let Some(token) = &self.token {
+                request = request.header("Authorization", format!("Discogs token={}", token));
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            let body = response.text().await?;
+            if !status.is_success() {
+                let message = serde_json::from_str::<ApiError>(&body)
+                    .map(|e| e.message)
+                    .unwrap_or_else(|_| status.to_string());
+                return Err(RedmanError::ServiceError(format!(
+                    "Discogs error: {}",
+                    message
+                )));
+            }
+            let page_data: Page = serde_json::from_str(&body).map_err(|e| {
+                RedmanError::ServiceError(format!("Could not read the Discogs response: {}", e))
+            })?;
+            entries.extend(page_data.wants);
+            entries.extend(page_data.releases);
+            if page >= page_data.pagination.pages {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The releases in the user's wantlist, and collection if configured, as
+    /// first artist and title, without repeats.
+    #[instrument(skip_all, fields(user = %self.user))]
+    pub async fn wanted_releases(&self) -> Result<Vec<(String, String)>> {
+        let client = Client::new();
+        let mut entries = self
+            .entries(&client, &format!("users/{}/wants", self.user))
+            .await?;
+        if self.collection {
+            entries.extend(
+                self.entries(
+                    &client,
+                    &format!("users/{}/collection/folders/0/releases", self.user),
+                )
+                .await?,
+            );
+        }
+        let releases: Vec<_> = entries
+            .into_iter()
+            .filter_map(|e| {
+                let artist = e.basic_information.artists.into_iter().next()?;
+                let artist = DISAMBIGUATION.replace(&artist.name, "").into_owned();
+                Some((artist, e.basic_information.title))
+            })
+            .unique()
+            .collect();
+        info!(releases = releases.len(), "Read Discogs lists");
+        Ok(releases)
+    }
+}
@@ -0,0 +1,301 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use chrono::Utc;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    beets::Beets,
+    clients::Transmission,
+    db::{Database, Download},
+    error::{RedmanError, Result},
+    hooks::{self, Hooks},
+    library::{PlexLibrary, torrent_files},
+    plex::PlexServer,
+};
+
+/// What to do about downloads the client finished.
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    transmission: Transmission,
+    hooks: Hooks,
+    beets: Option<Beets>,
+    plex: Option<PlexServer>,
+}
+
+impl CheckOptions {
+    pub fn new(transmission: Transmission) -> Self {
+        CheckOptions {
+            transmission,
+            hooks: Hooks::default(),
+            beets: None,
+            plex: None,
+        }
+    }
+
+    /// Commands to run for every completed download.
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Import completed downloads into beets.
+    pub fn with_beets(mut self, beets: Option<Beets>) -> Self {
+        self.beets = beets;
+        self
+    }
+
+    /// Scan the Plex music section once downloads completed.
+    pub fn with_plex_scan(mut self, plex: Option<PlexServer>) -> Self {
+        self.plex = plex;
+        self
+    }
+}
+
+/// A download the client finished since the last check.
+#[derive(Debug, Clone)]
+pub struct CompletedDownload {
+    pub download: Download,
+    /// Where the client saved the files
+    pub path: PathBuf,
+    /// Whether beets imported it; `None` without beets
+    pub imported: Option<bool>,
+}
+
+/// Ask the client about every download redman added that is not known to be
+/// finished and mark the finished ones. Each is imported into beets, then the
+/// `on_completed` hooks run; after all of them Plex rescans its section.
+#[instrument(skip_all)]
+pub async fn check_downloads(
+    pool_db: &str,
+    options: &CheckOptions,
+) -> Result<Vec<CompletedDownload>> {
+    let db = Database::new(pool_db)?;
+    let mut completed = Vec::new();
+    for download in db.unfinished_downloads()? {
+        let Some(hash) = download.info_hash.as_deref() else {
+            continue;
+        };
+        let Some(torrent) = options.transmission.status(hash)? else {
+            debug!(id = download.torrent_id, "No longer in the client");
+            continue;
+        };
+        if !torrent.is_complete() {
+            continue;
+        }
+        db.mark_completed(hash)?;
+        let path = torrent.path();
+        info!(id = download.torrent_id, path = %path.display(), "Download complete");
+        let imported = options
+            .beets
+            .as_ref()
+            .map(|beets| match beets.import(&path) {
+                Ok(()) => {
+                    info!(id = download.torrent_id, "Imported into beets");
+                    true
+                }
+                Err(e) => {
+                    warn!(id = download.torrent_id, "{}", e);
+                    false
+                }
+            });
+        hooks::run(
+            &options.hooks.on_completed,
+            &[
+                ("TORRENT_ID", download.torrent_id.to_string()),
+                ("ARTIST", download.artist_names.clone()),
+                ("ALBUM", download.album_name.clone()),
+                ("DOWNLOAD_PATH", path.display().to_string()),
+            ],
+        );
+        completed.push(CompletedDownload {
+            download,
+            path,
+            imported,
+        });
+    }
+    if let Some(plex) = &options.plex
+        && !completed.is_empty()
+    {
+        match plex.scan(&reqwest::Client::new()).await {
+            Ok(()) => info!(section = plex.section, "Plex library scan started"),
+            Err(e) => warn!("{}", e),
+        }
+    }
+    Ok(completed)
+}
+
+/// Ask the client for the ratio and seeding time of every download that has
+/// not been seeded long enough yet, store them and return all tracked
+/// downloads with their latest numbers.
+#[instrument(skip_all)]
+pub fn poll_seeding(pool_db: &str, transmission: &Transmission) -> Result<Vec<Download>> {
+    let db = Database::new(pool_db)?;
+    for download in db.tracked_downloads()? {
+        let Some(hash) = download.info_hash.as_deref() else {
+            continue;
+        };
+        if download.seeded_enough() {
+            continue;
+        }
+        match transmission.status(hash)? {
+            Some(torrent) => db.update_seeding(hash, torrent.ratio, torrent.seeding_time)?,
+            None => debug!(id = download.torrent_id, "No longer in the client"),
+        }
+    }
+    db.tracked_downloads()
+}
+
+/// Which finished downloads `cleanup` removes from the client.
+#[derive(Debug, Clone)]
+pub struct CleanupOptions {
+    transmission: Transmission,
+    plex: String,
+    min_ratio: Option<f64>,
+    min_seed_time: Option<Duration>,
+    delete_data: bool,
+}
+
+impl CleanupOptions {
+    /// Only downloads found in the Plex database at `plex` are removed.
+    pub fn new(transmission: Transmission, plex: &str) -> Self {
+        CleanupOptions {
+            transmission,
+            plex: plex.to_string(),
+            min_ratio: None,
+            min_seed_time: None,
+            delete_data: false,
+        }
+    }
+
+    /// Also require this ratio and seeding time; the tracker's minimum seed
+    /// time always applies.
+    pub fn with_thresholds(
+        mut self,
+        min_ratio: Option<f64>,
+        min_seed_time: Option<Duration>,
+    ) -> Self {
+        self.min_ratio = min_ratio;
+        self.min_seed_time = min_seed_time;
+        self
+    }
+
+    /// Delete the downloaded files along with the torrent.
+    pub fn with_delete_data(mut self, delete_data: bool) -> Self {
+        self.delete_data = delete_data;
+        self
+    }
+}
+
+/// Finished downloads that met the seed requirements and thresholds and are
+/// in the Plex library, with ratio and seeding time fresh from the client.
+#[instrument(skip_all)]
+pub fn cleanup_candidates(pool_db: &str, options: &CleanupOptions) -> Result<Vec<Download>> {
+    let db = Database::new(pool_db)?;
+    let library = PlexLibrary::load(&options.plex)?.with_aliases(&db.artist_aliases()?);
+    let mut candidates = Vec::new();
+    for mut download in db.tracked_downloads()? {
+        let Some(hash) = download.info_hash.clone() else {
+            continue;
+        };
+        if download.completed_at.is_none() {
+            continue;
+        }
+        let Some(torrent) = options.transmission.status(&hash)? else {
+            debug!(id = download.torrent_id, "No longer in the client");
+            continue;
+        };
+        db.update_seeding(&hash, torrent.ratio, torrent.seeding_time)?;
+        download.ratio = torrent.ratio;
+        download.seeding_time = torrent.seeding_time;
+        let ratio_met = options
+            .min_ratio
+            .is_none_or(|min| download.ratio.is_some_and(|r| r >= min));
+        let time_met = options
+            .min_seed_time
+            .is_none_or(|min| download.seeding_time.is_some_and(|t| t >= min));
+        if !(download.seeded_enough() && ratio_met && time_met) {
+            continue;
+        }
+        if !library.contains(&download.artist_names, &download.album_name) {
+            debug!(id = download.torrent_id, "Not in the Plex library yet");
+            continue;
+        }
+        candidates.push(download);
+    }
+    Ok(candidates)
+}
+
+/// Remove `downloads` from the client, returning how many were removed.
+/// Failures are logged and skipped.
+#[instrument(skip_all)]
+pub fn remove_downloads(
+    pool_db: &str,
+    options: &CleanupOptions,
+    downloads: &[Download],
+) -> Result<usize> {
+    let db = Database::new(pool_db)?;
+    let mut removed = 0;
+    for download in downloads {
+        let Some(hash) = download.info_hash.as_deref() else {
+            continue;
+        };
+        match options.transmission.remove(hash, options.delete_data) {
+            Ok(()) => {
+                db.mark_removed(download.torrent_id)?;
+                info!(id = download.torrent_id, "Removed from the client");
+                removed += 1;
+            }
+            Err(e) => warn!(id = download.torrent_id, "{}", e),
+        }
+    }
+    Ok(removed)
+}
+
+/// Downloads added more than `after` ago whose album is not in the Plex
+/// database at `plex_db`: failed imports, bad tags or downloads that never
+/// finished.
+#[instrument(skip_all)]
+pub fn missing_from_plex(pool_db: &str, plex_db: &str, after: Duration) -> Result<Vec<Download>> {
+    let db = Database::new(pool_db)?;
+    let library = PlexLibrary::load(plex_db)?.with_aliases(&db.artist_aliases()?);
+    let before = (Utc::now() - after).naive_utc();
+    Ok(db
+        .downloads_added_before(before)?
+        .into_iter()
+        .filter(|d| !library.contains(&d.artist_names, &d.album_name))
+        .collect())
+}
+
+/// Make `downloads` eligible for the next watch run again: their torrent
+/// files in `torrent_dir` are deleted and the torrents removed from the
+/// client, keeping the data so a new add can verify what is already there.
+/// Returns how many were queued again.
+#[instrument(skip_all)]
+pub fn requeue(
+    pool_db: &str,
+    torrent_dir: &str,
+    transmission: &Transmission,
+    downloads: &[Download],
+) -> Result<usize> {
+    let db = Database::new(pool_db)?;
+    let files = torrent_files(torrent_dir)?;
+    let mut requeued = 0;
+    for download in downloads {
+        if let Some(hash) = download.info_hash.as_deref()
+            && transmission.status(hash)?.is_some()
+        {
+            if let Err(e) = transmission.remove(hash, false) {
+                warn!(id = download.torrent_id, "{}", e);
+                continue;
+            }
+            db.mark_removed(download.torrent_id)?;
+        }
+        for (_, path) in files.iter().filter(|(id, _)| *id == download.torrent_id) {
+            fs::remove_file(path).map_err(RedmanError::file(path))?;
+        }
+        db.mark_requeued(download.torrent_id)?;
+        info!(id = download.torrent_id, "Queued again");
+        requeued += 1;
+    }
+    Ok(requeued)
+}
@@ -0,0 +1,134 @@
+use std::{io, path::PathBuf};
+
+pub type Result<T, E = RedmanError> = std::result::Result<T, E>;
+
+/// Everything the library can fail with, classified by [`RedmanError::action`]
+/// so callers can decide whether to retry, skip the item or give up.
+#[derive(Debug, thiserror::Error)]
+pub enum RedmanError {
+    /// The tracker asked to slow down
+    #[error("Rate limited by the tracker")]
+    RateLimited,
+    /// The collage, artist or torrent does not exist
+    #[error("Unknown id: {0}")]
+    BadId(String),
+    #[error("The API key was rejected by the tracker")]
+    BadApiKey,
+    /// A freeleech token was asked for, but the account has none left
+    #[error("No freeleech tokens left: {0}")]
+    NoFreeleechTokens(String),
+    #[error("The API key lacks permission for this request")]
+    InsufficientPermissions,
+    /// Any other tracker failure, including responses that could not be read
+    #[error("{0}")]
+    ApiError(String),
+    /// The request could not be answered from the cache in offline mode
+    #[error("{0}")]
+    Offline(String),
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+    #[error(transparent)]
+    DbError(#[from] rusqlite::Error),
+    #[error("Pool database has schema version {found}, but this redman only knows up to {known}")]
+    UnsupportedSchema { found: usize, known: usize },
+    /// The torrent client did not accept a torrent
+    #[error("{0}")]
+    ClientError(String),
+    #[error("Could not access {}: {error}", path.display())]
+    FileError { path: PathBuf, error: io::Error },
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+    #[error("{0}")]
+    ConfigError(String),
+    /// A run summary could not be delivered
+    #[error("{0}")]
+    NotifyError(String),
+    /// An outside service such as Last.fm or an IRC server failed or refused
+    /// a request
+    #[error("{0}")]
+    ServiceError(String),
+    /// Malformed input such as a pool export or a fetch type
+    #[error("{0}")]
+    InvalidInput(String),
+    #[cfg(feature = "keyring")]
+    #[error("{context}: {error}")]
+    KeyringError {
+        context: &'static str,
+        error: keyring::Error,
+    },
+}
+
+/// How a caller should react to a failed tracker request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorAction {
+    Retry,
+    Skip,
+    Abort,
+}
+
+impl RedmanError {
+    /// Classify an error message returned by the tracker.
+    pub(crate) fn from_message(message: &str) -> Self {
+        let m = message.to_lowercase();
+        if m.contains("rate limit") || m.contains("too many") {
+            RedmanError::RateLimited
+        } else if m.contains("bad id")
+            || m.contains("not found")
+            || m.contains("no such")
+            || m.contains("no artist")
+        {
+            RedmanError::BadId(message.to_string())
+        } else if m.contains("freeleech") || m.contains("fl token") {
+            RedmanError::NoFreeleechTokens(message.to_string())
+        } else if m.contains("api key")
+            || m.contains("invalid token")
+            || m.contains("bad token")
+            || m.contains("credentials")
+            || m.contains("not logged in")
+        {
+            RedmanError::BadApiKey
+        } else if m.contains("permission") || m.contains("not allowed") || m.contains("forbidden") {
+            RedmanError::InsufficientPermissions
+        } else {
+            RedmanError::ApiError(format!("Tracker error: {}", message))
+        }
+    }
+
+    pub(crate) fn from_status(status: reqwest::StatusCode) -> Option<Self> {
+        match status.as_u16() {
+            401 => Some(RedmanError::BadApiKey),
+            403 => Some(RedmanError::InsufficientPermissions),
+            429 => Some(RedmanError::RateLimited),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn file(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |error| RedmanError::FileError { path, error }
+    }
+
+    /// Rate limits are worth waiting out, unknown ids, spent freeleech tokens
+    /// and failing outside services are worth skipping; anything else ends
+    /// the run.
+    pub fn action(&self) -> ErrorAction {
+        match self {
+            RedmanError::RateLimited => ErrorAction::Retry,
+            RedmanError::BadId(_)
+            | RedmanError::NoFreeleechTokens(_)
+            | RedmanError::ServiceError(_) => ErrorAction::Skip,
+            _ => ErrorAction::Abort,
+        }
+    }
+
+    /// Whether the error is a problem with the account rather than with the
+    /// requested item, so retrying other items is pointless.
+    pub fn is_account_error(&self) -> bool {
+        matches!(
+            self,
+            RedmanError::RateLimited
+                | RedmanError::BadApiKey
+                | RedmanError::InsufficientPermissions
+        )
+    }
+}
@@ -0,0 +1,451 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use chrono::{Datelike, Utc};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    db::Torrent,
+    error::{RedmanError, Result},
+};
+
+/// What a weight formula can refer to, worked out for every candidate of a
+/// watch run.
+#[derive(Debug, Clone, Copy)]
+pub struct Factors {
+    /// Stored weight of the torrent
+    pub weight: f64,
+    /// Last.fm factor: 1, up to 1 + `boost` of `[lastfm]` for the artists
+    /// and albums played most
+    pub boost: f64,
+    /// Halved for every `half_life` in the pool, 1 without one
+    pub decay: f64,
+    /// Days in the pool
+    pub age: f64,
+    /// Release year, 0 if unknown
+    pub year: f64,
+    /// Years since the release, 0 if unknown
+    pub years: f64,
+    /// Size in MiB
+    pub size: f64,
+}
+
+impl Factors {
+    /// The factors of `t` with the Last.fm factor `boost`.
+    pub fn of(t: &Torrent, boost: f64, half_life: Option<Duration>) -> Self {
+        let now = Utc::now().naive_utc();
+        let age = (now - t.created_at).to_std().unwrap_or_default();
+        let year = t.year.map_or(0.0, f64::from);
+        Factors {
+            weight: t.weight as f64,
+            boost,
+            decay: t.decay(half_life),
+            age: age.as_secs_f64() / 86400.0,
+            year,
+            years: t
+                .year
+                .map_or(0.0, |y| (now.year() as f64 - y as f64).max(0.0)),
+            size: t.size as f64 / (1024.0 * 1024.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Var {
+    Weight,
+    Boost,
+    Decay,
+    Age,
+    Year,
+    Years,
+    Size,
+}
+
+const VARS: &[(&str, Var)] = &[
+    ("weight", Var::Weight),
+    ("boost", Var::Boost),
+    ("decay", Var::Decay),
+    ("age", Var::Age),
+    ("year", Var::Year),
+    ("years", Var::Years),
+    ("size", Var::Size),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Func {
+    Min,
+    Max,
+    Ln,
+    Exp,
+    Sqrt,
+    Abs,
+}
+
+const FUNCS: &[(&str, Func)] = &[
+    ("min", Func::Min),
+    ("max", Func::Max),
+    ("ln", Func::Ln),
+    ("exp", Func::Exp),
+    ("sqrt", Func::Sqrt),
+    ("abs", Func::Abs),
+];
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(f64),
+    Var(Var),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, f: &Factors) -> f64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Var(var) => match var {
+                Var::Weight => f.weight,
+                Var::Boost => f.boost,
+                Var::Decay => f.decay,
+                Var::Age => f.age,
+                Var::Year => f.year,
+                Var::Years => f.years,
+                Var::Size => f.size,
+            },
+            Expr::Neg(e) => -e.eval(f),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(f), b.eval(f));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            }
+            Expr::Call(func, args) => {
+                let mut values = args.iter().map(|e| e.eval(f));
+                match func {
+                    Func::Min => values.fold(f64::INFINITY, f64::min),
+                    Func::Max => values.fold(f64::NEG_INFINITY, f64::max),
+                    Func::Ln => values.next().unwrap_or_default().ln(),
+                    Func::Exp => values.next().unwrap_or_default().exp(),
+                    Func::Sqrt => values.next().unwrap_or_default().sqrt(),
+                    Func::Abs => values.next().unwrap_or_default().abs(),
+                }
+            }
+        }
+    }
+}
+
+/// A weight formula such as `weight * boost * decay / (1 + size / 1000)`,
+/// ordering the candidates of a watch run in place of the stored weights.
+/// Takes numbers, the variables of [`Factors`], `+ - * / ^`, parentheses and
+/// the functions `min`, `max`, `ln`, `exp`, `sqrt` and `abs`.
+#[derive(Debug, Clone)]
+pub struct Formula {
+    source: String,
+    expr: Expr,
+}
+
+impl Formula {
+    /// The weight for `factors`; negative and undefined results count as 0.
+    pub fn eval(&self, factors: &Factors) -> f64 {
+        let weight = self.expr.eval(factors);
+        if weight.is_finite() {
+            weight.max(0.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = RedmanError;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            source,
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(parser.error(&format!("unexpected {}", token)));
+        }
+        Ok(Formula {
+            source: source.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Read an optional formula from the config file, rejecting invalid ones at
+/// load time rather than at the first watch run.
+pub(crate) fn deserialize_formula<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Formula>, D::Error> {
+    let source = String::deserialize(deserializer)?;
+    source.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{:?}", name),
+            Token::Symbol(c) => write!(f, "{:?}", c),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek()
+                && (c.is_ascii_digit() || c == '.')
+            {
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let number = source[start..end].parse().map_err(|_| {
+                formula_error(source, &format!("invalid number {:?}", &source[start..end]))
+            })?;
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek()
+                && (c.is_ascii_alphanumeric() || c == '_')
+            {
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Name(source[start..end].to_string()));
+        } else if "+-*/^(),".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return Err(formula_error(source, &format!("unexpected {:?}", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+fn formula_error(source: &str, message: &str) -> RedmanError {
+    RedmanError::InvalidInput(format!("Invalid weight formula {:?}: {}", source, message))
+}
+
+/// Recursive descent over the tokens, binding `^` tightest, then unary minus,
+/// then `* /`, then `+ -`.
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> RedmanError {
+        formula_error(self.source, message)
+    }
+
+    fn peek_symbol(&self, symbols: &str) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Symbol(c)) if symbols.contains(*c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<()> {
+        match self.peek_symbol(&symbol.to_string()) {
+            Some(_) => {
+                self.pos += 1;
+                Ok(())
+            }
+            None => Err(self.error(&format!("expected {:?}", symbol))),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+        while let Some(op) = self.peek_symbol("+-") {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.peek_symbol("*/") {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.peek_symbol("-").is_some() {
+            self.pos += 1;
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr> {
+        let base = self.atom()?;
+        if self.peek_symbol("^").is_some() {
+            self.pos += 1;
+            // Right associative, and -2^2 is -(2^2) while 2^-1 is 0.5
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(self.error("unexpected end"));
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Symbol('(') => {
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Token::Name(name) if self.peek_symbol("(").is_some() => {
+                let Some(&(_, func)) = FUNCS.iter().find(|(n, _)| *n == name) else {
+                    return Err(self.error(&format!("unknown function {:?}", name)));
+                };
+                self.pos += 1;
+                let mut args = vec![self.expr()?];
+                while self.peek_symbol(",").is_some() {
+                    self.pos += 1;
+                    args.push(self.expr()?);
+                }
+                self.expect(')')?;
+                if !matches!(func, Func::Min | Func::Max) && args.len() != 1 {
+                    return Err(self.error(&format!("{} takes one argument", name)));
+                }
+                Ok(Expr::Call(func, args))
+            }
+            Token::Name(name) => match VARS.iter().find(|(n, _)| *n == name) {
+                Some(&(_, var)) => Ok(Expr::Var(var)),
+                None => Err(self.error(&format!(
+                    "unknown variable {:?}, expected one of {}",
+                    name,
+                    VARS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")
+                ))),
+            },
+            token => Err(self.error(&format!("unexpected {}", token))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> f64 {
+        let factors = Factors {
+            weight: 4.0,
+            boost: 1.5,
+            decay: 0.5,
+            age: 10.0,
+            year: 2001.0,
+            years: 25.0,
+            size: 300.0,
+        };
+        source.parse::<Formula>().unwrap().expr.eval(&factors)
+    }
+
+    fn error(source: &str) -> String {
+        source.parse::<Formula>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn binds_operators_by_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("8 - 4 - 2"), 2.0);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("2 * 3 ^ 2"), 18.0);
+        assert_eq!(eval("weight * boost * decay / (1 + size / 100)"), 0.75);
+    }
+
+    #[test]
+    fn negates_after_powers() {
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("(-2)^2"), 4.0);
+        assert_eq!(eval("2^-1"), 0.5);
+        assert_eq!(eval("--weight"), 4.0);
+        assert_eq!(eval("3 - -1"), 4.0);
+    }
+
+    #[test]
+    fn calls_functions() {
+        assert_eq!(eval("min(weight, 3, years)"), 3.0);
+        assert_eq!(eval("max(weight)"), 4.0);
+        assert_eq!(eval("sqrt(weight) + abs(-1)"), 3.0);
+        assert_eq!(eval("ln(exp(2))"), 2.0);
+    }
+
+    #[test]
+    fn clamps_results() {
+        let factors = Factors {
+            weight: 0.0,
+            boost: 1.0,
+            decay: 1.0,
+            age: 0.0,
+            year: 0.0,
+            years: 0.0,
+            size: 0.0,
+        };
+        let formula = |s: &str| s.parse::<Formula>().unwrap().eval(&factors);
+        assert_eq!(formula("weight - 1"), 0.0);
+        assert_eq!(formula("1 / weight"), 0.0);
+        assert_eq!(formula("ln(weight - 1)"), 0.0);
+        assert_eq!(formula("boost + 1"), 2.0);
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert!(error("weight * bost").contains("unknown variable \"bost\""));
+        assert!(error("log(weight)").contains("unknown function \"log\""));
+    }
+
+    #[test]
+    fn checks_arity() {
+        assert!(error("ln(weight, 2)").contains("ln takes one argument"));
+        assert!(error("sqrt()").contains("unexpected ')'"));
+        assert!("min(1, 2) + max(1, 2, 3)".parse::<Formula>().is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(error("").contains("unexpected end"));
+        assert!(error("weight +").contains("unexpected end"));
+        assert!(error("(weight").contains("expected ')'"));
+        assert!(error("weight weight").contains("unexpected \"weight\""));
+        assert!(error("1..2").contains("invalid number"));
+        assert!(error("weight % 2").contains("unexpected '%'"));
+    }
+}
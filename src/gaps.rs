@@ -0,0 +1,86 @@
+use tracing::{info, instrument, warn};
+
+use crate::{
+    api::{GroupData, transform_groups},
+    db::Torrent,
+    error::{ErrorAction, Result},
+    library::{PlexLibrary, library_artists},
+    selection::preferred_torrent,
+    shutdown,
+    tracker::TrackerClient,
+};
+
+/// A studio album of an artist in the Plex library that the library lacks.
+#[derive(Debug, Clone)]
+pub struct Gap {
+    pub album: String,
+    pub year: Option<u32>,
+    pub group_id: Option<u32>,
+    /// The torrent `fetch` would pool, `None` without an MP3 V0 or 320
+    pub torrent: Option<Torrent>,
+}
+
+/// The missing albums of one artist, with the discography they came from so
+/// it can be stored as a fetch.
+#[derive(Debug)]
+pub struct ArtistGaps {
+    /// The artist as Plex names it
+    pub artist: String,
+    pub data: GroupData,
+    pub gaps: Vec<Gap>,
+}
+
+/// Fetch the discography of each of `artists`, or of every artist in the Plex
+/// database at `plex_db` when empty, and collect the studio albums Plex does
+/// not have. Artists the tracker does not know are skipped.
+#[instrument(skip(api, artists))]
+pub async fn find_gaps(
+    api: &impl TrackerClient,
+    plex_db: &str,
+    artists: &[String],
+) -> Result<Vec<ArtistGaps>> {
+    let library = PlexLibrary::load(plex_db)?;
+    let artists = if artists.is_empty() {
+        library_artists(plex_db)?
+    } else {
+        artists.to_vec()
+    };
+    info!(artists = artists.len(), "Looking for missing albums");
+
+    let mut found = Vec::new();
+    for artist in artists {
+        if shutdown::requested() {
+            warn!("Gap search interrupted");
+            break;
+        }
+        let data = match api.fetch_artist_by_name(&artist).await {
+            Ok(data) => GroupData::ArtistData(data),
+            Err(e) if e.action() == ErrorAction::Skip => {
+                warn!(artist, "Skipping: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let gaps: Vec<Gap> = transform_groups(&data, 0)
+            .into_iter()
+            .filter_map(|group| {
+                let albums: Vec<&Torrent> = group.iter().filter(|t| t.release_type == 1).collect();
+                let first = albums.first()?;
+                if library.contains(&artist, &first.album_name) {
+                    return None;
+                }
+                Some(Gap {
+                    album: first.album_name.clone(),
+                    year: first.year,
+                    group_id: first.group_id,
+                    torrent: preferred_torrent(albums.iter().copied()).cloned(),
+                })
+            })
+            .collect();
+        info!(artist, missing = gaps.len(), "Compared with the tracker");
+        if !gaps.is_empty() {
+            found.push(ArtistGaps { artist, data, gaps });
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,39 @@
+use std::process::Command;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// Shell commands run for each torrent redman hands to the client, configured
+/// in `[hooks]`. They get the torrent in `TORRENT_ID`, `ARTIST` and `ALBUM`;
+/// `PATH` keeps its usual meaning so hooks can find programs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    /// Run after a torrent was added, with `TORRENT_FILE` and `DOWNLOAD_DIR`
+    pub on_added: Vec<String>,
+    /// Run once the client reports the download complete, with
+    /// `DOWNLOAD_PATH` pointing at the downloaded files
+    pub on_completed: Vec<String>,
+}
+
+/// Run each of `commands` with `sh -c` and the variables in `env`, waiting
+/// for it to finish. Failures are logged; they never stop a run.
+pub(crate) fn run(commands: &[String], env: &[(&str, String)]) {
+    for command in commands {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .output();
+        match output {
+            Ok(output) if output.status.success() => info!("Hook finished: {}", command),
+            Ok(output) => warn!(
+                "Hook {} failed ({}): {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => warn!("Could not run hook {}: {}", command, e),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::{sync::LazyLock, time::Duration};
+
+use regex::Regex;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+use tokio_native_tls::{TlsConnector, native_tls};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    error::{RedmanError, Result},
+    rss::{FeedItem, FeedSettings, split_tags, torrent_id},
+};
+
+/// Silence after which the connection counts as lost; servers ping every
+/// few minutes.
+const READ_TIMEOUT: Duration = Duration::from_secs(600);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bold, color, italics and the like, which announcers sprinkle over lines.
+static FORMATTING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x03(\d{1,2}(,\d{1,2})?)?|[\x02\x0f\x11\x16\x1d\x1e\x1f]").unwrap()
+});
+
+/// The tracker's IRC announce channel, listened to by `redman irc` and the
+/// daemon, configured in `[irc]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IrcConfig {
+    /// Host and port, e.g. "irc.example.net:7000"
+    pub server: String,
+    #[serde(default = "default_tls")]
+    pub tls: bool,
+    pub nick: String,
+    /// Identifies the nick with NickServ
+    pub nickserv_password: Option<String>,
+    /// Raw IRC lines sent once connected, such as the message asking the
+    /// tracker's bot for an invite to the announce channel
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// e.g. "#announce"
+    pub channel: String,
+    /// Nick of the bot posting announces; lines of anyone else are ignored
+    pub announcer: Option<String>,
+    #[serde(flatten)]
+    pub settings: FeedSettings,
+}
+
+fn default_tls() -> bool {
+    true
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// A registered connection to the IRC server.
+pub struct Connection {
+    reader: BufReader<ReadHalf<Box<dyn Stream>>>,
+    writer: WriteHalf<Box<dyn Stream>>,
+    nick: String,
+    channel: String,
+    announcer: Option<String>,
+}
+
+/// A line from the server, split into the sender's nick, the command and its
+/// parameters.
+#[derive(Debug)]
+struct Message<'a> {
+    nick: Option<&'a str>,
+    command: &'a str,
+    params: Vec<&'a str>,
+}
+
+fn parse_message(line: &str) -> Option<Message<'_>> {
+    let (prefix, rest) = match line.strip_prefix(':') {
+        Some(line) => {
+            let (prefix, rest) = line.split_once(' ')?;
+            (Some(prefix), rest)
+        }
+        None => (None, line),
+    };
+    let (middle, trailing) = match rest.split_once(" :") {
+        Some((middle, trailing)) => (middle, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = middle.split_whitespace();
+    let command = words.next()?;
+    Some(Message {
+        nick: prefix.map(|p| p.split('!').next().unwrap_or(p)),
+        command,
+        params: words.chain(trailing).collect(),
+    })
+}
+
+/// The upload in an announce line such as `Artist - Album [2024] [Album] -
+/// FLAC / Lossless / WEB - https://…/torrents.php?id=1 /
+/// https://…/torrents.php?action=download&id=2 - rock,jazz`. Lines without
+/// a torrent link are not announces.
+pub(crate) fn parse_announce(line: &str) -> Option<FeedItem> {
+    let line = FORMATTING.replace_all(line, "");
+    let torrent_id = line
+        .split_whitespace()
+        .filter(|word| word.starts_with("http"))
+        .find_map(torrent_id)?;
+    let title = line.split(" - http").next().unwrap_or_default().trim();
+    let last = line.rsplit(" - ").next().unwrap_or_default();
+    Some(FeedItem {
+        torrent_id,
+        title: title.to_string(),
+        tags: if last.contains("://") {
+            Vec::new()
+        } else {
+            split_tags(last)
+        },
+    })
+}
+
+fn tls_error(e: native_tls::Error) -> RedmanError {
+    RedmanError::ServiceError(format!("IRC TLS error: {}", e))
+}
+
+impl IrcConfig {
+    /// Connect to the server, register the nick and join the announce channel.
+    #[instrument(skip_all, fields(server = %self.server))]
+    pub async fn connect(&self) -> Result<Connection> {
+        let tcp = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&self.server))
+            .await
+            .map_err(|_| RedmanError::ServiceError(format!("Could not reach {}", self.server)))??;
+        let stream: Box<dyn Stream> = if self.tls {
+            let host = self
+                .server
+                .rsplit_once(':')
+                .map_or(self.server.as_str(), |(host, _)| host);
+            let connector = TlsConnector::from(native_tls::TlsConnector::new().map_err(tls_error)?);
+            Box::new(connector.connect(host, tcp).await.map_err(tls_error)?)
+        } else {
+            Box::new(tcp)
+        };
+        let (reader, writer) = tokio::io::split(stream);
+        let mut conn = Connection {
+            reader: BufReader::new(reader),
+            writer,
+            nick: self.nick.clone(),
+            channel: self.channel.clone(),
+            announcer: self.announcer.clone(),
+        };
+        conn.send(&format!("NICK {}", self.nick)).await?;
+        conn.send(&format!("USER {} 0 * :redman", self.nick))
+            .await?;
+
+        // Wait for the welcome before identifying and joining
+        loop {
+            let line = conn.read_line().await?;
+            let Some(message) = parse_message(&line) else {
+                continue;
+            };
+            match message.command {
+                "001" => break,
+                // Nick in use
+                "433" => {
+                    conn.nick.push('_');
+                    let nick = format!("NICK {}", conn.nick);
+                    conn.send(&nick).await?;
+                }
+                "PING" => conn.pong(&message).await?,
+                "ERROR" => {
+                    return Err(RedmanError::ServiceError(format!(
+                        "IRC server refused the connection: {}",
+                        message.params.join(" ")
+                    )));
+                }
+                _ => {}
+            }
+        }
+        if let Some(password) = &self.nickserv_password {
+            conn.send(&format!(
+                "PRIVMSG NickServ :IDENTIFY {} {}",
+                self.nick, password
+            ))
+            .await?;
+        }
+        for command in &self.commands {
+            conn.send(command).await?;
+        }
+        conn.send(&format!("JOIN {}", self.channel)).await?;
+        info!(nick = %conn.nick, channel = %self.channel, "Connected to IRC");
+        Ok(conn)
+    }
+}
+
+impl Connection {
+    async fn send(&mut self, line: &str) -> Result<()> {
+        // Lines may carry passwords, so only the command is logged
+        debug!(
+            command = line.split(' ').next().unwrap_or_default(),
+            "IRC send"
+        );
+        self.writer
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(READ_TIMEOUT, self.reader.read_until(b'\n', &mut buf))
+            .await
+            .map_err(|_| RedmanError::ServiceError("IRC server went silent".to_string()))??;
+        if read == 0 {
+            return Err(RedmanError::ServiceError(
+                "IRC server closed the connection".to_string(),
+            ));
+        }
+        // Not every announcer sends valid UTF-8
+        Ok(String::from_utf8_lossy(&buf).trim_end().to_string())
+    }
+
+    async fn pong(&mut self, ping: &Message<'_>) -> Result<()> {
+        let token = ping.params.first().copied().unwrap_or_default();
+        self.send(&format!("PONG :{}", token)).await
+    }
+
+    /// Wait for the next announce in the channel, answering pings and
+    /// joining on invites along the way.
+    pub async fn next_announce(&mut self) -> Result<FeedItem> {
+        loop {
+            let line = self.read_line().await?;
+            let Some(message) = parse_message(&line) else {
+                continue;
+            };
+            match (message.command, message.params.as_slice()) {
+                ("PING", _) => self.pong(&message).await?,
+                ("INVITE", [_, channel]) if channel.eq_ignore_ascii_case(&self.channel) => {
+                    let join = format!("JOIN {}", self.channel);
+                    self.send(&join).await?;
+                }
+                ("JOIN", [channel, ..])
+                    if message.nick == Some(self.nick.as_str())
+                        && channel.eq_ignore_ascii_case(&self.channel) =>
+                {
+                    info!(channel = %self.channel, "Joined the announce channel");
+                }
+                ("PRIVMSG", [target, text]) if target.eq_ignore_ascii_case(&self.channel) => {
+                    if let Some(announcer) = &self.announcer
+                        && !message
+                            .nick
+                            .is_some_and(|n| n.eq_ignore_ascii_case(announcer))
+                    {
+                        continue;
+                    }
+                    if let Some(item) = parse_announce(text) {
+                        return Ok(item);
+                    }
+                }
+                ("ERROR", _) => {
+                    return Err(RedmanError::ServiceError(format!(
+                        "IRC server closed the connection: {}",
+                        message.params.join(" ")
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Leave the server politely.
+    pub async fn quit(&mut self) {
+        let _ = self.send("QUIT :redman stopping").await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_messages() {
+        let m = parse_message(":Drone!bot@host PRIVMSG #announce :Artist - Album").unwrap();
+        assert_eq!(m.nick, Some("Drone"));
+        assert_eq!(m.command, "PRIVMSG");
+        assert_eq!(m.params, ["#announce", "Artist - Album"]);
+
+        let m = parse_message("PING :irc.example.org").unwrap();
+        assert_eq!(m.nick, None);
+        assert_eq!(m.command, "PING");
+        assert_eq!(m.params, ["irc.example.org"]);
+
+        let m = parse_message(":irc.example.org 001 redman :Welcome :)").unwrap();
+        assert_eq!(m.nick, Some("irc.example.org"));
+        assert_eq!(m.command, "001");
+        assert_eq!(m.params, ["redman", "Welcome :)"]);
+
+        let m = parse_message(":nick!u@h MODE #announce +o  other").unwrap();
+        assert_eq!(m.params, ["#announce", "+o", "other"]);
+
+        assert!(parse_message("").is_none());
+        assert!(parse_message(":prefix-only").is_none());
+    }
+
+    #[test]
+    fn parses_announces() {
+        let item = parse_announce(
+            "\x0304Artist\x03 - Album [2024] [Album] - FLAC / Lossless / WEB - \
+             https://tracker/torrents.php?id=1 / \
+             https://tracker/torrents.php?action=download&id=2 - rock,jazz",
+        )
+        .unwrap();
+        assert_eq!(item.torrent_id, 2);
+        assert_eq!(
+            item.title,
+            "Artist - Album [2024] [Album] - FLAC / Lossless / WEB"
+        );
+        assert_eq!(item.tags, ["rock", "jazz"]);
+
+        let item = parse_announce(
+            "\x02Artist - Album\x02 - https://tracker/torrents.php?id=1&torrentid=3",
+        )
+        .unwrap();
+        assert_eq!(item.torrent_id, 3);
+        assert_eq!(item.title, "Artist - Album");
+        assert!(item.tags.is_empty());
+
+        assert!(parse_announce("Artist - Album - https://tracker/torrents.php?id=1").is_none());
+        assert!(parse_announce("Welcome to the announce channel").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use reqwest::Client;
+use serde::{Deserialize, de::DeserializeOwned};
+use tracing::{info, instrument};
+
+use crate::{
+    api::USER_AGENT,
+    db::{Database, Torrent},
+    error::{RedmanError, Result},
+    library::normalize,
+};
+
+const LASTFM_API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// A Last.fm account whose scrobbles raise the weight of pool torrents by
+/// the artists and albums played most, configured in `[lastfm]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LastFm {
+    pub user: String,
+    /// https://www.last.fm/api/account/create
+    pub api_key: String,
+    /// Torrents of the most played artist or album get their weight
+    /// multiplied by 1 + `boost`, the others by a share of it
+    #[serde(default = "default_boost")]
+    pub boost: f64,
+    /// How many of the top artists and albums to take into account
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    /// API root, for compatible services such as Libre.fm
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_boost() -> f64 {
+    1.0
+}
+
+fn default_limit() -> usize {
+    500
+}
+
+fn default_api_url() -> String {
+    LASTFM_API_URL.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error: u32,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopArtists {
+    topartists: ArtistList,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtistList {
+    artist: Vec<TopArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopArtist {
+    name: String,
+    #[serde(deserialize_with = "playcount")]
+    playcount: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopAlbums {
+    topalbums: AlbumList,
+}
+
+#[derive(Debug, Deserialize)]
+struct AlbumList {
+    album: Vec<TopAlbum>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopAlbum {
+    name: String,
+    artist: AlbumArtist,
+    #[serde(deserialize_with = "playcount")]
+    playcount: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AlbumArtist {
+    name: String,
+}
+
+/// Last.fm sends counts as strings.
+fn playcount<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+impl LastFm {
+    async fn get<T: DeserializeOwned>(&self, client: &Client, method: &str) -> Result<T> {
+        let response = client
+            .get(&self.api_url)
+            .query(&[
+                ("method", method),
+                ("user", &self.user),
+                ("api_key", &self.api_key),
+                ("period", "overall"),
+                ("limit", &self.limit.to_string()),
+                ("format", "json"),
+            ])
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await
+            // The URL carries the API key
+            .map_err(|e| e.without_url())?;
+        let status = response.status();
+        let body = response.text().await?;
+        if let Ok(e) = serde_json::from_str::<ApiError>(&body) {
+            return Err(RedmanError::ServiceError(format!(
+                "Last.fm error {}: {}",
+                e.error, e.message
+            )));
+        }
+        if !status.is_success() {
+            return Err(RedmanError::ServiceError(format!(
+                "Last.fm answered {}",
+                status
+            )));
+        }
+        serde_json::from_str(&body).map_err(|e| {
+            RedmanError::ServiceError(format!("Could not read the Last.fm response: {}", e))
+        })
+    }
+
+    /// The user's most played artists with their play counts.
+    pub async fn top_artists(&self, client: &Client) -> Result<Vec<(String, u64)>> {
+        let top: TopArtists = self.get(client, "user.gettopartists").await?;
+        Ok(top
+            .topartists
+            .artist
+            .into_iter()
+            .map(|a| (a.name, a.playcount))
+            .collect())
+    }
+
+    /// The user's most played albums as artist, album and play count.
+    pub async fn top_albums(&self, client: &Client) -> Result<Vec<(String, String, u64)>> {
+        let top: TopAlbums = self.get(client, "user.gettopalbums").await?;
+        Ok(top
+            .topalbums
+            .album
+            .into_iter()
+            .map(|a| (a.artist.name, a.name, a.playcount))
+            .collect())
+    }
+}
+
+/// Replace the play counts stored in `pool_db` with the current top artists
+/// and albums of `lastfm`. Returns how many of each were stored.
+#[instrument(skip_all)]
+pub async fn refresh(pool_db: &str, lastfm: &LastFm) -> Result<(usize, usize)> {
+    let client = Client::new();
+    let artists = lastfm.top_artists(&client).await?;
+    let albums = lastfm.top_albums(&client).await?;
+    let db = Database::new(pool_db)?;
+    let rows: Vec<(String, String, String, u64)> = artists
+        .iter()
+        .map(|(artist, plays)| (normalize(artist), String::new(), artist.clone(), *plays))
+        .chain(albums.iter().map(|(artist, album, plays)| {
+            (
+                normalize(artist),
+                normalize(album),
+                format!("{} - {}", artist, album),
+                *plays,
+            )
+        }))
+        .collect();
+    db.replace_scrobbles(&rows)?;
+    info!(
+        artists = artists.len(),
+        albums = albums.len(),
+        "Stored Last.fm play counts"
+    );
+    Ok((artists.len(), albums.len()))
+}
+
+/// Play counts relative to the most played artist and album.
+pub(crate) struct Scrobbles {
+    artists: HashMap<String, f64>,
+    albums: HashMap<(String, String), f64>,
+}
+
+impl Scrobbles {
+    pub fn load(db: &Database) -> Result<Self> {
+        let rows = db.scrobbles()?;
+        let most = |albums: bool| {
+            rows.iter()
+                .filter(|(_, album, _)| album.is_empty() != albums)
+                .map(|(_, _, plays)| *plays)
+                .max()
+                .unwrap_or_default()
+                .max(1) as f64
+        };
+        let (most_artist, most_album) = (most(false), most(true));
+        let mut artists = HashMap::new();
+        let mut albums = HashMap::new();
+        for (artist, album, plays) in rows {
+            if album.is_empty() {
+                artists.insert(artist, plays as f64 / most_artist);
+            } else {
+                albums.insert((artist, album), plays as f64 / most_album);
+            }
+        }
+        Ok(Scrobbles { artists, albums })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty() && self.albums.is_empty()
+    }
+
+    /// What to multiply the weight of `t` with: 1 plus `boost` times the
+    /// larger share of its artist's and album's plays.
+    pub fn factor(&self, t: &Torrent, boost: f64) -> f64 {
+        let artist = normalize(&t.artist_names);
+        let share = self.artists.get(&artist).copied().unwrap_or_default().max(
+            self.albums
+                .get(&(artist, normalize(&t.album_name)))
+                .copied()
+                .unwrap_or_default(),
+        );
+        1.0 + boost * share
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use rusqlite::{Connection, OpenFlags, params};
+
+use crate::{
+    db::Torrent,
+    error::{RedmanError, Result},
+};
+
+#[derive(Debug)]
+struct Album {
+    pub name: String,
+    pub artists: String,
+}
+
+fn get_plex_library_albums(db_path: &str) -> Result<Vec<Album>> {
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(
+        r#"
+            SELECT DISTINCT b.title as album, c.title as artist
+            from metadata_items a
+            JOIN metadata_items b ON a.parent_id = b.id
+            JOIN metadata_items c ON b.parent_id = c.id
+            where b.metadata_type = 9 AND c.metadata_type = 8
+        "#,
+    )?;
+
+    let r = stmt
+        .query_map([], |row| {
+            Ok(Album {
+                name: row.get("album")?,
+                artists: row.get("artist")?,
+            })
+        })?
+        .flatten()
+        .collect();
+    Ok(r)
+}
+
+/// The albums in a Plex library, matched by artist and album name ignoring
+/// case and anything but ASCII letters and digits.
+pub(crate) struct PlexLibrary {
+    albums: HashSet<(String, String)>,
+    /// Other names of artists, normalized
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl PlexLibrary {
+    pub fn load(plex_db: &str) -> Result<Self> {
+        let albums = get_plex_library_albums(plex_db)?
+            .iter()
+            .map(|a| (normalize(&a.artists), normalize(&a.name)))
+            .collect();
+        Ok(PlexLibrary {
+            albums,
+            aliases: HashMap::new(),
+        })
+    }
+
+    /// Also find albums Plex files under another name of the artist, as
+    /// recorded by artist fetches.
+    pub fn with_aliases(mut self, aliases: &HashMap<String, Vec<String>>) -> Self {
+        self.aliases = aliases
+            .iter()
+            .map(|(artist, names)| {
+                (
+                    normalize(artist),
+                    names.iter().map(|n| normalize(n)).collect(),
+                )
+            })
+            .collect();
+        self
+    }
+
+    pub fn contains(&self, artist: &str, album: &str) -> bool {
+        let artist = normalize(artist);
+        let album = normalize(album);
+        self.aliases
+            .get(&artist)
+            .into_iter()
+            .flatten()
+            .chain([&artist])
+            .any(|name| self.albums.contains(&(name.clone(), album.clone())))
+    }
+}
+
+/// How Plex names the artist of compilations; the tracker has no such artist.
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// The artists with at least one album in the Plex database at `plex_db`,
+/// leaving out "Various Artists".
+pub(crate) fn library_artists(plex_db: &str) -> Result<Vec<String>> {
+    let conn = Connection::open_with_flags(plex_db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(
+        r#"
+            SELECT DISTINCT c.title
+            FROM metadata_items b
+            JOIN metadata_items c ON b.parent_id = c.id
+            WHERE b.metadata_type = 9 AND c.metadata_type = 8 AND c.title != ?
+            ORDER BY c.title
+        "#,
+    )?;
+    let artists = stmt
+        .query_map([VARIOUS_ARTISTS], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(artists)
+}
+
+/// The `top` most played artists in the Plex database at `plex_db` with how
+/// often their tracks were played, most played first. Unplayed artists and
+/// "Various Artists" are left out.
+pub(crate) fn most_played_artists(plex_db: &str, top: usize) -> Result<Vec<(String, u64)>> {
+    let conn = Connection::open_with_flags(plex_db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(
+        r#"
+            SELECT c.title AS artist, SUM(s.view_count) AS plays
+            FROM metadata_items a
+            JOIN metadata_items b ON a.parent_id = b.id
+            JOIN metadata_items c ON b.parent_id = c.id
+            JOIN metadata_item_settings s ON s.guid = a.guid
+            WHERE a.metadata_type = 10 AND b.metadata_type = 9 AND c.metadata_type = 8
+                AND c.title != ?
+            GROUP BY c.id
+            HAVING plays > 0
+            ORDER BY plays DESC, c.title
+            LIMIT ?
+        "#,
+    )?;
+    let artists = stmt
+        .query_map(params![VARIOUS_ARTISTS, top as i64], |row| {
+            Ok((row.get("artist")?, row.get::<_, i64>("plays")? as u64))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(artists)
+}
+
+/// A Plex album whose tracks are all MP3.
+#[derive(Debug, Clone)]
+pub(crate) struct LossyAlbum {
+    /// Plex's `metadata_items` id
+    pub id: i64,
+    pub artist: String,
+    pub name: String,
+    /// Folder of its first track
+    pub path: Option<PathBuf>,
+}
+
+/// The albums in the Plex database at `plex_db` that have only MP3 tracks,
+/// by artist and album name.
+pub(crate) fn lossy_albums(plex_db: &str) -> Result<Vec<LossyAlbum>> {
+    let conn = Connection::open_with_flags(plex_db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(
+        r#"
+            SELECT b.id, b.title AS album, c.title AS artist, MIN(p.file) AS file
+            FROM metadata_items a
+            JOIN metadata_items b ON a.parent_id = b.id
+            JOIN metadata_items c ON b.parent_id = c.id
+            JOIN media_items m ON m.metadata_item_id = a.id
+            LEFT JOIN media_parts p ON p.media_item_id = m.id
+            WHERE a.metadata_type = 10 AND b.metadata_type = 9 AND c.metadata_type = 8
+            GROUP BY b.id
+            HAVING SUM(LOWER(COALESCE(m.audio_codec, '')) != 'mp3') = 0
+            ORDER BY c.title, b.title
+        "#,
+    )?;
+    let albums = stmt
+        .query_map([], |row| {
+            Ok(LossyAlbum {
+                id: row.get("id")?,
+                artist: row.get("artist")?,
+                name: row.get("album")?,
+                path: row
+                    .get::<_, Option<String>>("file")?
+                    .and_then(|f| PathBuf::from(f).parent().map(PathBuf::from)),
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(albums)
+}
+
+/// Whether two artist and album names are the same release for
+/// [`PlexLibrary`].
+pub(crate) fn same_release(a: (&str, &str), b: (&str, &str)) -> bool {
+    normalize(a.0) == normalize(b.0) && normalize(a.1) == normalize(b.1)
+}
+
+pub(crate) fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+pub(crate) fn filter_torrents_not_in_plex_library(
+    torrents: &[Torrent],
+    plex_db: &str,
+    aliases: &HashMap<String, Vec<String>>,
+) -> Result<Vec<Torrent>> {
+    let library = PlexLibrary::load(plex_db)?.with_aliases(aliases);
+    Ok(torrents
+        .iter()
+        .filter(|t| !library.contains(&t.artist_names, &t.album_name))
+        .cloned()
+        .collect())
+}
+
+/// The torrent files in `torrent_dir` with the torrent id at the end of
+/// their names.
+pub(crate) fn torrent_files(torrent_dir: &str) -> Result<Vec<(u32, PathBuf)>> {
+    Ok(fs::read_dir(torrent_dir)
+        .map_err(RedmanError::file(torrent_dir))?
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter_map(|p| {
+            let stem = p.file_stem()?.to_str()?;
+            let digits = stem.len() - stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+            let id = stem[stem.len() - digits..].parse().ok()?;
+            Some((id, p))
+        })
+        .collect())
+}
+
+/// Leave out torrents whose file is in `torrent_dir`. The download records
+/// are what counts; this catches torrents added before redman kept them or
+/// by hand.
+pub(crate) fn filter_torrents_not_in_torrent_dir(
+    torrents: &[Torrent],
+    torrent_dir: &str,
+) -> Result<Vec<Torrent>> {
+    let dir_torrent_ids = torrent_files(torrent_dir)?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect::<HashSet<_>>();
+
+    Ok(torrents
+        .iter()
+        .filter(|t| !dir_torrent_ids.contains(&t.id))
+        .cloned()
+        .collect::<Vec<Torrent>>())
+}
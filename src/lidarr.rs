@@ -0,0 +1,91 @@
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::{
+    api::USER_AGENT,
+    error::{RedmanError, Result},
+};
+
+/// Albums asked for per page of the wanted list.
+const PAGE_SIZE: usize = 250;
+
+/// A Lidarr server whose monitored albums without files are pooled with
+/// `redman fetch lidarr`, configured in `[lidarr]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Lidarr {
+    /// e.g. http://localhost:8686
+    pub url: String,
+    /// Settings > General > Security in the Lidarr web app
+    pub api_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WantedPage {
+    total_records: usize,
+    records: Vec<WantedAlbum>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WantedAlbum {
+    title: String,
+    artist: Option<WantedArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WantedArtist {
+    artist_name: String,
+}
+
+impl Lidarr {
+    /// Monitored albums Lidarr has no files for, as artist and album name.
+    #[instrument(skip_all, fields(url = %self.url))]
+    pub async fn wanted_albums(&self) -> Result<Vec<(String, String)>> {
+        let client = Client::new();
+        let url = format!("{}/api/v1/wanted/missing", self.url.trim_end_matches('/'));
+        let mut albums = Vec::new();
+        for page in 1.. {
+            let response = client
+                .get(&url)
+                .query(&[
+                    ("page", page.to_string()),
+                    ("pageSize", PAGE_SIZE.to_string()),
+                    ("includeArtist", "true".to_string()),
+                    ("monitored", "true".to_string()),
+                ])
+                .header("X-Api-Key", &self.api_key)
+                .header("User-Agent", USER_AGENT)
+                .send()
+                .await?;
+            let status = response.status();
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                return Err(RedmanError::ServiceError(
+                    "Lidarr refused the api_key in [lidarr]".to_string(),
+                ));
+            }
+            if !status.is_success() {
+                return Err(RedmanError::ServiceError(format!(
+                    "Lidarr answered {}",
+                    status
+                )));
+            }
+            let wanted: WantedPage = response.json().await.map_err(|e| {
+                RedmanError::ServiceError(format!("Could not read the Lidarr wanted list: {}", e))
+            })?;
+            let done = wanted.records.is_empty() || page * PAGE_SIZE >= wanted.total_records;
+            albums.extend(
+                wanted
+                    .records
+                    .into_iter()
+                    .filter_map(|a| Some((a.artist?.artist_name, a.title))),
+            );
+            if done {
+                break;
+            }
+        }
+        info!(albums = albums.len(), "Read the wanted list");
+        Ok(albums)
+    }
+}
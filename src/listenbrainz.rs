@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, de::DeserializeOwned};
+use tracing::{info, instrument};
+
+use crate::{
+    api::USER_AGENT,
+    error::{RedmanError, Result},
+};
+
+const LISTENBRAINZ_API_URL: &str = "https://api.listenbrainz.org/1/";
+
+/// Recording MBIDs looked up per metadata request.
+const METADATA_BATCH: usize = 50;
+
+/// A ListenBrainz account whose collaborative filtering recommendations are
+/// pooled with `redman fetch listenbrainz`, configured in `[listenbrainz]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListenBrainz {
+    pub user: String,
+    /// User token from https://listenbrainz.org/settings/, for rate limits
+    /// tied to the account instead of the IP address
+    pub token: Option<String>,
+    /// How many recommended recordings to look at
+    #[serde(default = "default_count")]
+    pub count: usize,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_count() -> usize {
+    100
+}
+
+fn default_api_url() -> String {
+    LISTENBRAINZ_API_URL.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recommendations {
+    payload: RecommendationPayload,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecommendationPayload {
+    mbids: Vec<Recommendation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recommendation {
+    recording_mbid: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordingMetadata {
+    artist: Option<Credit>,
+    release: Option<Credit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Credit {
+    name: String,
+}
+
+impl ListenBrainz {
+    /// GET `path` below the API root; `None` when there is no content.
+    async fn get<T: DeserializeOwned>(
+        &self,
+        client: &Client,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>> {
+        let mut request = client
+            .get(format!("{}{}", self.api_url, path))
+            .query(query)
+            .header("User-Agent", USER_AGENT);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if status == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        let body = response.text().await?;
+        if !status.is_success() {
+            let message = serde_json::from_str::<ApiError>(&body)
+                .map(|e| e.error)
+                .unwrap_or_else(|_| status.to_string());
+            return Err(RedmanError::ServiceError(format!(
+                "ListenBrainz error: {}",
+                message
+            )));
+        }
+        serde_json::from_str(&body).map(Some).map_err(|e| {
+            RedmanError::ServiceError(format!("Could not read the ListenBrainz response: {}", e))
+        })
+    }
+
+    /// The releases of the recordings recommended to the user, as artist and
+    /// release name in order of recommendation, without repeats.
+    #[instrument(skip_all, fields(user = %self.user))]
+    pub async fn recommended_releases(&self) -> Result<Vec<(String, String)>> {
+        let client = Client::new();
+        let path = format!("cf/recommendation/user/{}/recording", self.user);
+        let count = self.count.to_string();
+        let Some(recommendations) = self
+            .get::<Recommendations>(&client, &path, &[("count", &count)])
+            .await?
+        else {
+            info!("No recommendations generated yet");
+            return Ok(Vec::new());
+        };
+        let mbids: Vec<String> = recommendations
+            .payload
+            .mbids
+            .into_iter()
+            .map(|r| r.recording_mbid)
+            .collect();
+        info!(recordings = mbids.len(), "Got recommendations");
+
+        let mut releases = Vec::new();
+        for batch in mbids.chunks(METADATA_BATCH) {
+            let ids = batch.join(",");
+            let metadata: HashMap<String, RecordingMetadata> = self
+                .get(
+                    &client,
+                    "metadata/recording/",
+                    &[("recording_mbids", &ids), ("inc", "artist release")],
+                )
+                .await?
+                .unwrap_or_default();
+            releases.extend(batch.iter().filter_map(|mbid| {
+                let m = metadata.get(mbid)?;
+                Some((
+                    m.artist.as_ref()?.name.clone(),
+                    m.release.as_ref()?.name.clone(),
+                ))
+            }));
+        }
+        Ok(releases.into_iter().unique().collect())
+    }
+}
@@ -1,20 +1,109 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use clap::{
+    CommandFactory, Parser, Subcommand, ValueEnum, builder::BoolishValueParser, error::ErrorKind,
+};
+use clap_complete::Shell;
 use colored::*;
 use dotenv::dotenv;
-use redman::{Database, GroupData, Type, add_new_torrents_for_download, fetch_data};
+use futures_util::{StreamExt, stream};
+use redman::{
+    auth,
+    config::{self, Config},
+    covers, daemon,
+    downloads::{
+        self, CleanupOptions, check_downloads, cleanup_candidates, missing_from_plex, poll_seeding,
+        remove_downloads,
+    },
+    gaps, lastfm,
+    notify::{Event, Run, notify_all},
+    portable::{self, Format},
+    prelude::*,
+    reconcile,
+    rss::{FeedAction, Match},
+    rules::{self, Verdict},
+    shutdown, spotify,
+    tracker::{DEFAULT_RATE_LIMIT, DEFAULT_RATE_WINDOW},
+    tui, upgrade,
+};
+use tabled::{Table, Tabled, settings::Style};
+use tracing::{Level, info};
 use url::Url;
 
 #[derive(Parser)]
 #[command(author, version, about = "Fetch and manage torrent collections", long_about = None)]
 struct Args {
-    /// Base URL for the tracker API
-    #[arg(short, long, default_value = "https://redacted.sh/", global = true)]
-    base_url: String,
+    /// Base URL for the tracker API [default: https://redacted.sh/, or that of --tracker]
+    #[arg(short, long, global = true, env = "REDMAN_BASE_URL")]
+    base_url: Option<String>,
+
+    /// Tracker profile from the config file, or the built-in "red" or "ops"
+    #[arg(short, long, global = true, env = "REDMAN_TRACKER")]
+    tracker: Option<String>,
+
+    /// Override the User-Agent header sent to the tracker API
+    #[arg(long, global = true, env = "REDMAN_USER_AGENT")]
+    user_agent: Option<String>,
+
+    /// Write raw API responses to this directory (API key redacted)
+    #[arg(long, global = true, env = "REDMAN_DUMP_JSON")]
+    dump_json: Option<PathBuf>,
+
+    /// Directory for caching collage and artist API responses
+    #[arg(long, global = true, env = "REDMAN_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Maximum age in seconds of a cached API response before it is refetched
+    #[arg(
+        long,
+        default_value = "3600",
+        global = true,
+        env = "REDMAN_CACHE_MAX_AGE"
+    )]
+    cache_max_age: u64,
+
+    /// Work only from the pool database and cached API responses
+    #[arg(long, global = true, env = "REDMAN_OFFLINE", value_parser = BoolishValueParser::new())]
+    offline: bool,
+
+    /// Collages and artists fetched at once by sync and fetch [default: 4 with --tracker, else 1]
+    #[arg(short, long, global = true, env = "REDMAN_JOBS")]
+    jobs: Option<usize>,
+
+    /// Minimum level of log messages to emit
+    #[arg(long, default_value = "info", global = true, env = "REDMAN_LOG_LEVEL")]
+    log_level: Level,
+
+    /// Append log messages to this file instead of stderr
+    #[arg(long, global = true, env = "REDMAN_LOG_FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Only print errors and command results
+    #[arg(short, long, global = true, env = "REDMAN_QUIET", value_parser = BoolishValueParser::new())]
+    quiet: bool,
+
+    /// Directory album art is saved to [default: $XDG_CACHE_HOME/redman/covers]
+    #[arg(long, global = true, env = "REDMAN_COVERS_DIR")]
+    covers_dir: Option<PathBuf>,
 
-    /// Database file path for storing torrent pool data
-    #[arg(short, long)]
-    pool: String,
+    /// Path to the TOML configuration file [default: $XDG_CONFIG_HOME/redman/config.toml]
+    #[arg(short, long, global = true, env = "REDMAN_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Database file path for storing torrent pool data [default: $XDG_DATA_HOME/redman/pool.db]
+    #[arg(short, long, global = true, env = "REDMAN_POOL")]
+    pool: Option<String>,
 
     #[command(subcommand)]
     command: Commands,
@@ -25,127 +114,1300 @@ enum Commands {
     /// Fetch collage data from API and store in database
     Fetch {
         /// The type of the group to be fetched
-        #[arg(value_enum)]
-        ftype: Type,
-        /// Collage or artist ID to fetch
-        id: u32,
-        /// Relative weight when selecting torrents for download
-        #[arg(short, long, default_value = "10")]
-        weight: u32,
-        /// Show verbose output
+        #[arg(value_enum, required_unless_present_any = ["from_plex", "spotify_playlist", "category"])]
+        ftype: Option<Type>,
+        /// Collage or artist IDs to fetch, or links to their pages copied from the browser; a
+        /// group link fetches the group's artist. listenbrainz, lidarr and discogs take none
+        #[arg(value_name = "ID", required_if_eq("ftype", "collage"))]
+        ids: Vec<String>,
+        /// Look up the artist by name instead of id; may be given several times
+        #[arg(long = "name", value_name = "NAME", conflicts_with_all = ["ids", "from_plex", "spotify_playlist"])]
+        names: Vec<String>,
+        /// Relative weight when selecting torrents for download; with --from-plex that of the most played artist
+        /// [default: 10, or that of the collage category in [category_weights]]
+        #[arg(short, long, env = "REDMAN_WEIGHT")]
+        weight: Option<u32>,
+        /// Fetch the most played artists in Plex instead, weighted by play count
+        #[arg(long, conflicts_with_all = ["ftype", "ids"])]
+        from_plex: bool,
+        /// Pool the albums of the tracks in a Spotify playlist, given as link, URI or id
+        #[arg(long, value_name = "PLAYLIST", conflicts_with_all = ["ftype", "ids", "from_plex"])]
+        spotify_playlist: Option<String>,
+        /// Fetch the biggest collages of a category that are not stored yet
+        #[arg(long, value_enum, conflicts_with_all = ["ftype", "ids", "names", "from_plex", "spotify_playlist"])]
+        category: Option<CollageCategory>,
+        /// How many collages --category fetches
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Path to the Plex database file for --from-plex [default: plex of the [watch] config]
+        #[arg(long, env = "REDMAN_PLEX")]
+        plex: Option<String>,
+        /// How many of the most played artists --from-plex fetches
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+        /// Show verbose output (same as --log-level debug)
         #[arg(short, long)]
         verbose: bool,
     },
     /// Add torrents not in library to the transmission client for download
+    #[command(visible_alias = "watch")]
     Download {
         /// The number of torrents to add to the watchlist
-        #[arg(short, long, default_value = "10")]
+        #[arg(short, long, default_value = "10", env = "REDMAN_NUMBER")]
         number: usize,
         /// Path to the Plex database file
-        #[arg(long)]
+        #[arg(long, env = "REDMAN_PLEX")]
         plex: String,
         /// Directory where downloaded torrents are stored
-        #[arg(long)]
+        #[arg(long, env = "REDMAN_TORRENT_DIR")]
         torrent_dir: String,
         /// Directory where downloaded files are stored
-        #[arg(long)]
+        #[arg(long, env = "REDMAN_DOWNLOAD_DIR")]
         download_dir: String,
-        /// transmission-remote executable
-        #[arg(long, default_value = "transmission-remote")]
-        transmission_remote: String,
+        #[command(flatten)]
+        transmission: TransmissionArgs,
         /// Do not try to use freeleech tokens
-        #[arg(long)]
+        #[arg(long, env = "REDMAN_NO_FL", value_parser = BoolishValueParser::new())]
         no_fl: bool,
         /// Only download freeload torrents
-        #[arg(long)]
+        #[arg(long, env = "REDMAN_FREELOAD_ONLY", value_parser = BoolishValueParser::new())]
         freeload_only: bool,
+        /// Continue the last interrupted run instead of selecting new torrents
+        #[arg(long)]
+        resume: bool,
+        /// Skip torrents that failed to download or add this many times
+        #[arg(long, default_value = "3", env = "REDMAN_MAX_FAILURES")]
+        max_failures: u32,
+        /// How pool candidates are ordered before picking
+        #[arg(long, value_enum, default_value_t = Strategy::Strict, env = "REDMAN_STRATEGY")]
+        strategy: Strategy,
+        /// Halve a torrent's weight for every period it spends in the pool (e.g. 90d)
+        #[arg(long, value_parser = humantime::parse_duration, env = "REDMAN_HALF_LIFE")]
+        half_life: Option<Duration>,
+        /// Order by this formula of weight, boost, decay, age, year, years and size instead of the weights
+        #[arg(long, value_name = "FORMULA", env = "REDMAN_WEIGHT_FORMULA")]
+        weight_formula: Option<Formula>,
+        /// Pick at most this many torrents from the same artist
+        #[arg(long, env = "REDMAN_MAX_PER_ARTIST")]
+        max_per_artist: Option<usize>,
+        /// Pick at most this many torrents from the same collage or artist fetch
+        #[arg(long, env = "REDMAN_MAX_PER_FETCH")]
+        max_per_fetch: Option<usize>,
+        /// Ignore pool torrents with a lower weight
+        #[arg(long, default_value = "0", env = "REDMAN_MIN_WEIGHT")]
+        min_weight: u32,
+        /// Where torrents without a known release year go
+        #[arg(long, value_enum, default_value_t = UnknownYear::Keep, env = "REDMAN_UNKNOWN_YEAR")]
+        unknown_year: UnknownYear,
+        /// Save the album art of added torrents to the covers directory
+        #[arg(long, env = "REDMAN_COVERS", value_parser = BoolishValueParser::new())]
+        covers: bool,
+        /// Add torrents stopped, to start them by hand
+        #[arg(long, env = "REDMAN_PAUSED", value_parser = BoolishValueParser::new())]
+        paused: bool,
+        /// Bandwidth priority of added torrents
+        #[arg(long, value_enum, default_value_t = Priority::Normal, env = "REDMAN_PRIORITY")]
+        priority: Priority,
+        /// Label added torrents in the client, e.g. redman (Transmission 3.0+)
+        #[arg(long, env = "REDMAN_LABEL")]
+        label: Option<String>,
+        /// How long the tracker wants torrents seeded (e.g. 72h), recorded for `seeding`
+        #[arg(long, value_parser = humantime::parse_duration, env = "REDMAN_MIN_SEED_TIME")]
+        min_seed_time: Option<Duration>,
     },
+    /// Download and add specific torrents, bypassing pool selection
+    Queue {
+        /// Tracker torrent IDs
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        #[command(flatten)]
+        queue: QueueArgs,
+    },
+    /// Download and add the torrent behind a tracker link, picking the preferred torrent of a group
+    Snatch {
+        /// Torrent or group page, e.g. https://redacted.sh/torrents.php?id=1&torrentid=2
+        url: String,
+        #[command(flatten)]
+        queue: QueueArgs,
+    },
+    /// Re-fetch all stored collages and artists
+    Sync,
+    /// Run sync, watch and check on the intervals set in the config file
+    Daemon,
     /// Show statistics about stored data
-    Stats,
+    Stats {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// List stored fetches
+    Fetches,
+    /// Delete a stored fetch so it is no longer synced
+    RemoveFetch {
+        /// The type of the fetch to remove
+        #[arg(value_enum)]
+        ftype: Type,
+        /// ID of the fetch as `fetches` lists it; listenbrainz, lidarr, discogs, rss and irc take none
+        #[arg(required_if_eq_any = [("ftype", "collage"), ("ftype", "artist"), ("ftype", "spotify")])]
+        id: Option<u32>,
+        /// Also delete pool torrents contributed only by this fetch
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Change the weight of a stored fetch and its pool torrents
+    Reweight {
+        /// The type of the fetch to reweight
+        #[arg(value_enum)]
+        ftype: Type,
+        /// ID of the fetch as `fetches` lists it; listenbrainz, lidarr, discogs, rss and irc take none
+        #[arg(required_if_eq_any = [("ftype", "collage"), ("ftype", "artist"), ("ftype", "spotify")])]
+        id: Option<u32>,
+        /// New relative weight when selecting torrents for download
+        #[arg(short, long)]
+        weight: u32,
+    },
+    /// List torrents in the pool
+    List {
+        /// Only torrents whose artist contains this text
+        #[arg(long)]
+        artist: Option<String>,
+        /// Only torrents released in this year
+        #[arg(long)]
+        year: Option<u32>,
+        /// Only torrents with this encoding, e.g. V0 or 320
+        #[arg(long)]
+        encoding: Option<String>,
+        /// Only torrents with at least this weight
+        #[arg(long)]
+        min_weight: Option<u32>,
+        /// Order of the listed torrents
+        #[arg(long, value_enum, default_value_t = PoolSort::Weight)]
+        sort: PoolSort,
+        /// Reverse the sort order
+        #[arg(short, long)]
+        reverse: bool,
+        /// Number of torrents per page
+        #[arg(short, long, default_value = "50", value_parser = clap::value_parser!(u32).range(1..))]
+        limit: u32,
+        /// Page to show, starting at 1
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+        page: u32,
+    },
+    /// Search the pool by artist and album name
+    Search {
+        /// Words to look for; each matches the start of a word
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Maximum number of results
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+    },
+    /// Delete torrents from the pool by id or artist
+    #[command(group(clap::ArgGroup::new("target").required(true).multiple(true)))]
+    Rm {
+        /// Torrent ID to delete; can be repeated
+        #[arg(long, group = "target")]
+        id: Vec<u32>,
+        /// Delete every torrent whose artist contains this text
+        #[arg(long, group = "target")]
+        artist: Option<String>,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Write the fetches and pool torrents to CSV or JSON
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        /// Output file for JSON (default: stdout) or directory for CSV
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Merge a file or directory written by export into the pool
+    Import {
+        /// JSON file or CSV directory to import
+        path: PathBuf,
+        /// Input format (default: CSV for a directory, JSON otherwise)
+        #[arg(short, long, value_enum)]
+        format: Option<Format>,
+        /// What to do with torrents already in the pool, also under another
+        /// torrent id
+        #[arg(long, value_enum, default_value_t = ConflictPolicy::Skip)]
+        on_conflict: ConflictPolicy,
+    },
+    /// Maintain the pool database
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// Browse, search and mark pool torrents interactively
+    Tui,
+    /// Download the album art of pool torrents to the covers directory
+    Covers,
+    /// Manage the API key stored in the OS keyring
+    #[cfg(feature = "keyring")]
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommands,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Ask the client which downloads finished and hand them to beets, hooks and Plex
+    Check {
+        #[command(flatten)]
+        transmission: TransmissionArgs,
+    },
+    /// Show the ratio and seeding time of added torrents and whether they
+    /// were seeded long enough
+    Seeding {
+        /// Only list torrents that still need seeding
+        #[arg(long)]
+        unmet: bool,
+        #[command(flatten)]
+        transmission: TransmissionArgs,
+    },
+    /// Remove torrents from the client that were seeded enough and reached Plex
+    Cleanup {
+        /// Path to the Plex database file [default: plex of the [watch] config]
+        #[arg(long, env = "REDMAN_PLEX")]
+        plex: Option<String>,
+        /// Only remove torrents with at least this ratio
+        #[arg(long, env = "REDMAN_CLEANUP_MIN_RATIO")]
+        min_ratio: Option<f64>,
+        /// Only remove torrents seeded at least this long (e.g. 30d)
+        #[arg(long, value_parser = humantime::parse_duration, env = "REDMAN_CLEANUP_MIN_SEED_TIME")]
+        min_seed_time: Option<Duration>,
+        /// Delete the downloaded files too
+        #[arg(long, env = "REDMAN_CLEANUP_DELETE_DATA", value_parser = BoolishValueParser::new())]
+        delete_data: bool,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        #[command(flatten)]
+        transmission: TransmissionArgs,
+    },
+    /// List downloads whose album never showed up in Plex
+    Verify {
+        /// Only check downloads added at least this long ago
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "7d")]
+        after: Duration,
+        /// Path to the Plex database file [default: plex of the [watch] config]
+        #[arg(long, env = "REDMAN_PLEX")]
+        plex: Option<String>,
+        /// Remove them from the client and their torrent files so the next watch run adds them again
+        #[arg(long)]
+        requeue: bool,
+        /// Directory where downloaded torrents are stored [default: torrent_dir of the [watch] config]
+        #[arg(long, env = "REDMAN_TORRENT_DIR")]
+        torrent_dir: Option<String>,
+        #[command(flatten)]
+        transmission: TransmissionArgs,
+    },
+    /// Compare download records, torrent files and the client, optionally fixing
+    /// what disagrees
+    Reconcile {
+        /// Delete orphaned torrent files, add missing torrents and update records
+        #[arg(long)]
+        fix: bool,
+        /// Directory where downloaded torrents are stored [default: torrent_dir of the [watch] config]
+        #[arg(long, env = "REDMAN_TORRENT_DIR")]
+        torrent_dir: Option<String>,
+        /// Directory where downloaded files are stored [default: download_dir of the [watch] config]
+        #[arg(long, env = "REDMAN_DOWNLOAD_DIR")]
+        download_dir: Option<String>,
+        #[command(flatten)]
+        transmission: TransmissionArgs,
+    },
+    /// Queue lossless replacements for albums Plex only has as MP3
+    Upgrade {
+        /// Path to the Plex database file [default: plex of the [watch] config]
+        #[arg(long, env = "REDMAN_PLEX")]
+        plex: Option<String>,
+        /// Queue at most this many replacements
+        #[arg(short, long, default_value_t = 10)]
+        number: usize,
+        /// List the recorded replacements and where the MP3 files are instead
+        #[arg(long)]
+        list: bool,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        #[command(flatten)]
+        queue: QueueArgs,
+    },
+    /// List studio albums of the artists in Plex that the library is missing
+    Gaps {
+        /// Path to the Plex database file [default: plex of the [watch] config]
+        #[arg(long, env = "REDMAN_PLEX")]
+        plex: Option<String>,
+        /// Only look at this artist, as Plex names it; repeat for more [default: every artist in Plex]
+        #[arg(long)]
+        artist: Vec<String>,
+        /// Store the discographies of artists with missing albums in the pool as artist fetches
+        #[arg(long)]
+        fetch: bool,
+        /// Relative weight of the stored fetches
+        #[arg(short, long, default_value = "10", env = "REDMAN_WEIGHT")]
+        weight: u32,
+    },
+    /// Refresh the Last.fm play counts from [lastfm] that boost pool weights
+    Lastfm {
+        /// How many of the most played artists and albums to show
+        #[arg(short, long, default_value = "20")]
+        number: usize,
+    },
+    /// Poll the [rss] feed once and pool, or download, new uploads matching its filter
+    Rss,
+    /// Listen to the [irc] announce channel until stopped, pooling or downloading matching uploads
+    Irc,
+    /// Try the [[rules]] deciding what to do with uploads of [rss] and [irc]
+    Rules {
+        #[command(subcommand)]
+        command: RulesCommands,
+    },
+    /// List torrents added to the client by previous runs
+    History {
+        /// Only show torrents added since a date (YYYY-MM-DD) or duration ago (e.g. 7d)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<NaiveDateTime>,
+    },
+}
+
+#[derive(clap::Args)]
+struct TransmissionArgs {
+    /// transmission-remote executable
+    #[arg(
+        long,
+        default_value = "transmission-remote",
+        env = "REDMAN_TRANSMISSION_REMOTE"
+    )]
+    transmission_remote: String,
+    /// Host and port of the Transmission RPC server
+    #[arg(
+        long,
+        default_value = "localhost:9091",
+        env = "REDMAN_TRANSMISSION_URL"
+    )]
+    transmission_url: String,
+    /// Transmission RPC credentials as USER:PASSWORD
+    #[arg(
+        long,
+        default_value = "transmission:transmission",
+        env = "REDMAN_TRANSMISSION_AUTH"
+    )]
+    transmission_auth: String,
+}
+
+impl TransmissionArgs {
+    fn client(&self) -> Transmission {
+        Transmission::new(
+            &self.transmission_remote,
+            &self.transmission_url,
+            &self.transmission_auth,
+        )
+    }
+}
+
+/// Where `queue` and `snatch` put torrents, defaulting to the `[watch]` config.
+#[derive(clap::Args)]
+struct QueueArgs {
+    /// Directory where downloaded torrents are stored [default: torrent_dir of the [watch] config]
+    #[arg(long, env = "REDMAN_TORRENT_DIR")]
+    torrent_dir: Option<String>,
+    /// Directory where downloaded files are stored [default: download_dir of the [watch] config]
+    #[arg(long, env = "REDMAN_DOWNLOAD_DIR")]
+    download_dir: Option<String>,
+    /// Do not try to use freeleech tokens
+    #[arg(long, env = "REDMAN_NO_FL", value_parser = BoolishValueParser::new())]
+    no_fl: bool,
+    #[command(flatten)]
+    transmission: TransmissionArgs,
+}
+
+impl QueueArgs {
+    /// Options adding exactly `ids`. The Plex library is not consulted for
+    /// torrents asked for by id.
+    fn options(self, config: &Config, ids: Vec<u32>) -> WatchOptions {
+        let watch = config.watch.as_ref();
+        let (Some(torrent_dir), Some(download_dir)) = (
+            self.torrent_dir
+                .or_else(|| watch.map(|w| w.torrent_dir.clone())),
+            self.download_dir
+                .or_else(|| watch.map(|w| w.download_dir.clone())),
+        ) else {
+            eprintln!(
+                "{} Pass --torrent-dir and --download-dir or set them in [watch]",
+                "✗".red().bold()
+            );
+            std::process::exit(2);
+        };
+        WatchOptions::new("", &torrent_dir, &download_dir)
+            .with_number(ids.len())
+            .with_transmission(
+                self.transmission
+                    .client()
+                    .with_paused(watch.is_some_and(|w| w.paused))
+                    .with_priority(watch.map(|w| w.priority).unwrap_or_default())
+                    .with_label(watch.and_then(|w| w.label.clone())),
+            )
+            .with_freeleech(!self.no_fl && !watch.is_some_and(|w| w.no_fl))
+            .with_hooks(config.hooks.clone())
+            .with_min_seed_time(watch.and_then(|w| w.min_seed_time))
+            .with_torrent_ids(ids)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// A single JSON document
+    Json,
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// Check integrity, reclaim unused space and refresh query statistics
+    Maintain,
+    /// Copy the pool to a backup file while it may be in use
+    Backup {
+        /// Backup file, or directory for a timestamped backup
+        /// (default: a backups directory next to the pool)
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// Delete all but the newest N timestamped backups in the directory
+        #[arg(long)]
+        keep: Option<usize>,
+    },
+    /// Replace the pool with a backup
+    Restore {
+        /// Backup file to restore
+        path: PathBuf,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum RulesCommands {
+    /// Judge recent uploads of the RSS feed and IRC announces with the current
+    /// filters and rules, without pooling or downloading anything
+    Test {
+        /// How many of the most recent uploads to judge
+        #[arg(short, long, default_value = "20")]
+        number: usize,
+    },
+}
+
+#[cfg(feature = "keyring")]
+#[derive(Subcommand)]
+enum AuthCommands {
+    /// Read the API key from stdin and store it in the OS keyring, for the
+    /// profile given with --tracker if any
+    Set,
+    /// Remove the stored API key from the OS keyring
+    Delete,
+}
+
+fn parse_since(s: &str) -> Result<NaiveDateTime, String> {
+    if let Ok(duration) = humantime::parse_duration(s) {
+        let duration = chrono::Duration::from_std(duration).map_err(|e| e.to_string())?;
+        return Ok((Utc::now() - duration).naive_utc());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|d| d.and_time(NaiveTime::MIN))
+        .map_err(|_| {
+            format!(
+                "expected a date (YYYY-MM-DD) or a duration (e.g. 7d): {}",
+                s
+            )
+        })
+}
+
+#[derive(Tabled)]
+struct FetchRow {
+    #[tabled(rename = "ID")]
+    id: u32,
+    #[tabled(rename = "Type")]
+    ftype: String,
+    #[tabled(rename = "Name")]
+    name: String,
+    #[tabled(rename = "Category")]
+    category: String,
+    #[tabled(rename = "Weight")]
+    weight: u32,
+    #[tabled(rename = "Fetched")]
+    created_at: String,
+    #[tabled(rename = "Torrents")]
+    torrents: u32,
+}
+
+#[derive(Tabled)]
+struct PoolRow {
+    #[tabled(rename = "ID")]
+    id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Year")]
+    year: String,
+    #[tabled(rename = "Media")]
+    media: String,
+    #[tabled(rename = "Encoding")]
+    encoding: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Weight")]
+    weight: u32,
+    #[tabled(rename = "Added")]
+    added: String,
+    #[tabled(rename = "Link")]
+    link: String,
+}
+
+impl PoolRow {
+    fn new(t: Torrent, base_url: &str) -> Self {
+        PoolRow {
+            link: t.permalink(base_url),
+            id: t.id,
+            artist: t.artist_names,
+            album: t.album_name,
+            year: format_year(t.year),
+            media: t.media,
+            encoding: t.encoding,
+            size: format_size(t.size),
+            weight: t.weight,
+            added: t.created_at.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+#[derive(Tabled)]
+struct HistoryRow {
+    #[tabled(rename = "Added")]
+    added_at: String,
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "FL")]
+    fl_token: &'static str,
+    #[tabled(rename = "Completed")]
+    completed_at: String,
+    #[tabled(rename = "Link")]
+    link: String,
+}
+
+#[derive(Tabled)]
+struct SeedingRow {
+    #[tabled(rename = "Added")]
+    added_at: String,
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Ratio")]
+    ratio: String,
+    #[tabled(rename = "Seeded")]
+    seeded: String,
+    #[tabled(rename = "Required")]
+    required: String,
+    #[tabled(rename = "Met")]
+    met: &'static str,
+}
+
+impl SeedingRow {
+    fn new(d: &Download) -> Self {
+        SeedingRow {
+            added_at: d.added_at.clone(),
+            torrent_id: d.torrent_id,
+            artist: d.artist_names.clone(),
+            album: d.album_name.clone(),
+            ratio: d
+                .ratio
+                .map_or_else(|| "-".to_string(), |r| format!("{:.2}", r)),
+            seeded: d
+                .seeding_time
+                .map_or_else(|| "-".to_string(), format_seed_time),
+            required: d
+                .min_seed_time
+                .map_or_else(|| "-".to_string(), format_seed_time),
+            met: if d.seeded_enough() { "yes" } else { "no" },
+        }
+    }
+}
+
+/// A seed time in days and hours, e.g. `3d 4h`, or hours and minutes when
+/// shorter than a day.
+fn format_seed_time(time: Duration) -> String {
+    let minutes = time.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
+}
+
+#[derive(Tabled)]
+struct ReconcileRow {
+    #[tabled(rename = "Issue")]
+    kind: &'static str,
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Fix")]
+    fix: String,
+}
+
+#[derive(Tabled)]
+struct GapRow {
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Year")]
+    year: String,
+    #[tabled(rename = "ID")]
+    torrent_id: String,
+    #[tabled(rename = "Link")]
+    link: String,
+}
+
+#[derive(Tabled)]
+struct UpgradeRow {
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Edition")]
+    edition: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "MP3 files")]
+    old_path: String,
+}
+
+#[derive(Tabled)]
+struct UpgradeRecordRow {
+    #[tabled(rename = "Queued")]
+    created_at: String,
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "MP3 files")]
+    old_path: String,
+    #[tabled(rename = "Completed")]
+    completed_at: String,
+}
+
+#[derive(Tabled)]
+struct ScrobbleRow {
+    #[tabled(rename = "Artist / Album")]
+    name: String,
+    #[tabled(rename = "Plays")]
+    plays: u64,
+}
+
+#[derive(Tabled)]
+struct CompletedRow {
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Path")]
+    path: String,
+    #[tabled(rename = "Beets")]
+    imported: &'static str,
+}
+
+#[derive(Tabled)]
+struct WatchRow {
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Size")]
+    size: String,
+}
+
+impl From<&Torrent> for WatchRow {
+    fn from(t: &Torrent) -> Self {
+        WatchRow {
+            torrent_id: t.id,
+            artist: t.artist_names.clone(),
+            album: t.album_name.clone(),
+            size: format_size(t.size),
+        }
+    }
+}
+
+#[derive(Tabled)]
+struct MatchRow {
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Action")]
+    action: String,
+}
+
+/// An action with the rule that decided it, e.g. "download (jazz)".
+fn describe_action(action: FeedAction, rule: Option<&str>) -> String {
+    match rule {
+        Some(rule) => format!("{} ({})", action, rule),
+        None => action.to_string(),
+    }
+}
+
+impl From<&Match> for MatchRow {
+    fn from(m: &Match) -> Self {
+        MatchRow {
+            torrent_id: m.torrent.id,
+            artist: m.torrent.artist_names.clone(),
+            album: m.torrent.album_name.clone(),
+            size: format_size(m.torrent.size),
+            action: describe_action(m.action, m.rule.as_deref()),
+        }
+    }
+}
+
+#[derive(Tabled)]
+struct VerdictRow {
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "From")]
+    source: String,
+    #[tabled(rename = "Upload")]
+    title: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Freeload")]
+    freeload: String,
+    #[tabled(rename = "Decision")]
+    decision: String,
+}
+
+impl From<&Verdict> for VerdictRow {
+    fn from(v: &Verdict) -> Self {
+        VerdictRow {
+            torrent_id: v.item.torrent_id,
+            source: v.source.to_string(),
+            title: v.item.title.clone(),
+            size: v
+                .upload
+                .as_ref()
+                .map(|u| format_size(u.torrent.size))
+                .unwrap_or_default(),
+            freeload: match &v.upload {
+                Some(u) if u.is_freeload => "yes",
+                Some(_) => "no",
+                None => "",
+            }
+            .to_string(),
+            decision: match (&v.upload, &v.decision) {
+                (None, _) => "not on the tracker".to_string(),
+                (Some(_), None) => "filtered out".to_string(),
+                (Some(_), Some((action, rule))) => describe_action(*action, rule.as_deref()),
+            },
+        }
+    }
+}
+
+#[derive(Tabled)]
+struct SkippedRow {
+    #[tabled(rename = "ID")]
+    torrent_id: u32,
+    #[tabled(rename = "Artist")]
+    artist: String,
+    #[tabled(rename = "Album")]
+    album: String,
+    #[tabled(rename = "Reason")]
+    reason: String,
+}
+
+impl From<&SkippedTorrent> for SkippedRow {
+    fn from(s: &SkippedTorrent) -> Self {
+        SkippedRow {
+            torrent_id: s.torrent.id,
+            artist: s.torrent.artist_names.clone(),
+            album: s.torrent.album_name.clone(),
+            reason: s.reason.clone(),
+        }
+    }
+}
+
+fn print_distribution(title: &str, counts: &[(String, i64)], total: i64) {
+    println!("\n{}", format!("{} Distribution:", title).bold());
+    for (value, count) in counts {
+        let percentage = (*count as f64 / total as f64) * 100.0;
+        println!(
+            "  {}: {} ({:.1}%)",
+            value.bright_white(),
+            count.to_string().cyan(),
+            percentage
+        );
+    }
+}
+
+/// Ask a yes/no question on the terminal. Without a terminal the answer is
+/// no, so scripts have to pass `--yes`.
+fn confirm(prompt: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("Refusing to go on without confirmation; pass --yes");
+    }
+    eprint!("{} [y/N] ", prompt);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Delete all but the newest `keep` timestamped backups of `stem` in `dir`,
+/// returning how many were deleted.
+fn rotate_backups(dir: &Path, stem: &str, keep: usize) -> Result<usize> {
+    let prefix = format!("{}-", stem);
+    let mut backups: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".db"))
+        })
+        .collect();
+    // Timestamps sort lexically, newest last
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for path in &backups[..excess] {
+        fs::remove_file(path)?;
+    }
+    Ok(excess)
+}
+
+/// The key from the tracker profile in the config file, or else from
+/// [`auth::api_key`].
+fn api_key(tracker: Option<&str>, profile_key: Option<String>, offline: bool) -> Result<String> {
+    match profile_key.or_else(|| auth::api_key(tracker)) {
+        Some(key) => Ok(key),
+        None if offline => Ok(String::new()),
+        None if auth::keyring_enabled() => anyhow::bail!(
+            "No API key found; set {} or run `redman auth set`",
+            auth::env_var(tracker)
+        ),
+        None => anyhow::bail!(
+            "API key environment variable {} not set",
+            auth::env_var(tracker)
+        ),
+    }
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Print status chatter to stderr unless `--quiet` was given, keeping stdout
+/// free for command results.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+fn print_watch_report(report: &WatchReport, offline: bool) {
+    for (title, torrents) in [("Skipped", &report.skipped), ("Failed", &report.failed)] {
+        if !torrents.is_empty() {
+            status!("\n{} {}:", "!".yellow().bold(), title);
+            let rows = torrents.iter().map(SkippedRow::from);
+            println!("{}", Table::new(rows).with(Style::sharp()));
+        }
+    }
+    if report.interrupted {
+        status!("\n{} Interrupted, completed torrents:", "!".yellow().bold());
+    }
+    if !report.added.is_empty() {
+        let rows = report.added.iter().map(WatchRow::from);
+        println!("{}", Table::new(rows).with(Style::sharp()));
+    }
+    status!(
+        "{} {} torrent files {}, {} total, {} freeleech tokens spent",
+        "✓".green().bold(),
+        report.added.len().to_string().bright_white(),
+        if offline { "selected" } else { "downloaded" },
+        format_size(report.total_bytes).bright_white(),
+        report.fl_tokens.to_string().bright_white()
+    );
+}
+
+fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+fn init_logging(level: Level, file: Option<&PathBuf>) -> Result<()> {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_target(false);
+    match file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            builder
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .init();
+        }
+        None => builder
+            .with_ansi(!no_color() && std::io::stderr().is_terminal())
+            .with_writer(std::io::stderr)
+            .init(),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "keyring")]
+fn run_auth(command: &AuthCommands, tracker: Option<&str>) -> Result<()> {
+    match command {
+        AuthCommands::Set => {
+            if std::io::stdin().is_terminal() {
+                eprint!("API key: ");
+            }
+            let mut key = String::new();
+            std::io::stdin().read_line(&mut key)?;
+            let key = key.trim();
+            if key.is_empty() {
+                anyhow::bail!("No API key given");
+            }
+            auth::store(tracker, key)?;
+            status!("{} API key stored in the OS keyring", "✓".green().bold());
+        }
+        AuthCommands::Delete => {
+            if auth::delete(tracker)? {
+                status!("{} API key removed from the OS keyring", "✓".green().bold());
+            } else {
+                status!("No API key stored in the OS keyring");
+            }
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    if let Commands::Completions { shell } = args.command {
+        clap_complete::generate(
+            shell,
+            &mut Args::command(),
+            "redman",
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
     dotenv().ok();
 
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    set_progress_enabled(!args.quiet && std::io::stderr().is_terminal());
+    if no_color() || !std::io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+
+    let verbose = matches!(args.command, Commands::Fetch { verbose: true, .. });
+    let level = if args.quiet {
+        Level::ERROR
+    } else if verbose {
+        args.log_level.max(Level::DEBUG)
+    } else {
+        args.log_level
+    };
+    init_logging(level, args.log_file.as_ref())?;
+    shutdown::install_handler();
+
+    #[cfg(feature = "keyring")]
+    if let Commands::Auth { command } = &args.command {
+        return run_auth(command, args.tracker.as_deref());
+    }
+
+    let config_path = args
+        .config
+        .clone()
+        .or_else(|| config::default_config_path().filter(|p| p.exists()));
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let tracker = args
+        .tracker
+        .as_deref()
+        .map(|name| config.tracker(name))
+        .transpose()?;
+
+    let base_url = args
+        .base_url
+        .clone()
+        .or_else(|| tracker.as_ref().map(|t| t.base_url.clone()))
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
     // Validate base URL
-    if let Err(_) = Url::parse(&args.base_url) {
+    if Url::parse(&base_url).is_err() {
         eprintln!("{}", "Error: Invalid base URL provided".red());
         std::process::exit(1);
     }
 
-    let db = Database::new(&args.pool)?;
+    let pool = match (&args.pool, tracker.as_ref().and_then(|t| t.pool.as_ref())) {
+        (Some(pool), _) => pool.clone(),
+        (None, Some(pool)) => pool.to_string_lossy().into_owned(),
+        (None, None) => {
+            let Some(path) = config::default_pool_path(args.tracker.as_deref()) else {
+                eprintln!("{} Pass --pool or set HOME", "✗".red().bold());
+                std::process::exit(2);
+            };
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            path.to_string_lossy().into_owned()
+        }
+    };
+    let db = Database::new(&pool)?;
+    let covers_dir = args.covers_dir.clone().or_else(config::default_covers_dir);
+    let api_client = || -> Result<GazelleClient> {
+        let profile_key = tracker.as_ref().and_then(|t| t.api_key.clone());
+        // Without a profile, stay within what the default tracker allows
+        let (requests, window) = tracker
+            .as_ref()
+            .map_or((DEFAULT_RATE_LIMIT, DEFAULT_RATE_WINDOW), |t| {
+                (t.rate_limit, t.rate_window)
+            });
+        let compat = tracker.as_ref().map(|t| t.compat).unwrap_or_default();
+        let jobs = args.jobs.or(tracker.as_ref().map(|t| t.jobs)).unwrap_or(1);
+        Ok(GazelleClient::new(
+            &base_url,
+            &api_key(args.tracker.as_deref(), profile_key, args.offline)?,
+            args.user_agent.as_deref(),
+        )?
+        .with_rate_limit(requests, window)
+        .with_jobs(jobs)
+        .with_compat(compat)
+        .with_dump_dir(args.dump_json.clone())
+        .with_cache(
+            args.cache_dir.clone(),
+            Duration::from_secs(args.cache_max_age),
+        )
+        .with_offline(args.offline))
+    };
 
     match args.command {
         Commands::Fetch {
-            id,
+            from_plex: true,
+            weight,
+            plex,
+            top,
+            ..
+        } => {
+            let Some(plex) = plex.or_else(|| config.watch.as_ref().map(|w| w.plex.clone())) else {
+                eprintln!("{} Pass --plex or set it in [watch]", "✗".red().bold());
+                std::process::exit(2);
+            };
+            let api = api_client()?;
+            let weight = weight.unwrap_or(DEFAULT_WEIGHT);
+            let summary = fetch_plex_artists(&api, &db, &plex, top, weight).await?;
+            status!(
+                "{} Torrents stored successfully: {}",
+                "✓".green().bold(),
+                summary.to_string().bright_white()
+            );
+        }
+        Commands::Fetch {
+            category: Some(category),
+            limit,
+            weight,
+            ..
+        } => {
+            let api = api_client()?;
+            let summary =
+                fetch_category(&api, &db, category, limit, weight, &config.category_weights)
+                    .await?;
+            status!(
+                "{} Torrents stored successfully: {}",
+                "✓".green().bold(),
+                summary.to_string().bright_white()
+            );
+        }
+        Commands::Fetch {
+            ids,
+            names,
             ftype,
             weight,
+            spotify_playlist,
             verbose,
+            ..
         } => {
-            println!(
-                "{} {} {}...",
-                "Fetching".green().bold(),
-                ftype.to_string().bright_white(),
-                id.to_string().cyan()
-            );
-
-            let api_key = std::env::var("API_KEY").expect("API key environment variable not set");
-            match fetch_data(&api_key, &args.base_url, id, ftype, verbose).await {
-                Ok(group_data) => {
-                    match group_data {
-                        GroupData::CollageData(ref collage_data) => {
-                            if verbose {
-                                println!(
-                                    "{}: {}",
-                                    "Collage name".cyan(),
-                                    collage_data.name.bright_white()
-                                );
-                                println!(
-                                    "{}: {}",
-                                    "Category".cyan(),
-                                    collage_data.collage_category_name
-                                );
-                                println!(
-                                    "{}: {}",
-                                    "Total groups".cyan(),
-                                    collage_data.torrent_groups.len()
-                                );
+            let api = api_client()?;
+            let requests: Vec<(Type, u32, Option<String>)> = match (ftype, spotify_playlist) {
+                (_, Some(playlist)) => {
+                    let playlist = spotify::playlist_id(&playlist)?;
+                    let id = db.source_fetch_id(Type::Spotify, &playlist)?;
+                    vec![(Type::Spotify, id, Some(playlist))]
+                }
+                (Some(Type::Artist), None) if !names.is_empty() => {
+                    let mut requests = Vec::new();
+                    for name in &names {
+                        match api.fetch_artist_by_name(name).await {
+                            Ok(artist) => {
+                                info!("Artist {:?} has id {}", name, artist.id);
+                                requests.push((Type::Artist, artist.id, None));
                             }
-                        }
-                        GroupData::ArtistData(ref artist_data) => {
-                            if verbose {
-                                println!(
-                                    "{}: {}",
-                                    "Artist name".cyan(),
-                                    artist_data.name.bright_white()
-                                );
-                                println!(
-                                    "{}: {}",
-                                    "Total groups".cyan(),
-                                    artist_data.torrent_groups.len()
+                            Err(e) if e.action() == ErrorAction::Skip => {
+                                eprintln!(
+                                    "{} No artist named {:?} on the tracker: {}",
+                                    "!".yellow().bold(),
+                                    name,
+                                    e
                                 );
                             }
+                            Err(e) => return Err(e.into()),
                         }
                     }
-                    match db.store_data(&group_data, weight) {
-                        Ok(stored_count) => {
-                            println!(
-                                "{} {} torrents stored successfully!",
-                                "✓".green().bold(),
-                                stored_count.to_string().bright_white()
-                            );
+                    requests
+                }
+                (Some(_), None) if !names.is_empty() => Args::command()
+                    .error(ErrorKind::ArgumentConflict, "--name only looks up artists")
+                    .exit(),
+                (Some(Type::Artist), None) if ids.is_empty() => Args::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "fetch artist takes ids or --name",
+                    )
+                    .exit(),
+                (Some(ftype), None) if ids.is_empty() => vec![(ftype, 0, None)],
+                (Some(ftype), None) => {
+                    let mut requests = Vec::new();
+                    for id in &ids {
+                        let id = match id.parse() {
+                            Ok(id) => id,
+                            Err(_) => {
+                                let link = PageLink::parse(&base_url, id)?;
+                                resolve_page_link(&api, ftype, link).await?
+                            }
+                        };
+                        requests.push((ftype, id, None));
+                    }
+                    requests
+                }
+                (None, None) => unreachable!("clap requires a type without --from-plex"),
+            };
+
+            // Fetched concurrently, but stored and reported in the order given
+            let mut results = stream::iter(&requests)
+                .map(|(ftype, id, source)| {
+                    info!("Fetching {} {}", ftype, id);
+                    retry_rate_limited(|| {
+                        fetch_data(&api, *id, *ftype, source.as_deref(), &config.sources)
+                    })
+                })
+                .buffered(api.jobs());
+            for &(ftype, id, _) in &requests {
+                if shutdown::requested() {
+                    eprintln!("{} Fetch interrupted", "!".yellow().bold());
+                    break;
+                }
+                let result = results.next().await.expect("a result for every request");
+                match result {
+                    Ok(group_data) => {
+                        match group_data {
+                            GroupData::CollageData(ref collage_data) => {
+                                if verbose {
+                                    status!(
+                                        "{}: {}",
+                                        "Collage name".cyan(),
+                                        collage_data.name.bright_white()
+                                    );
+                                    status!(
+                                        "{}: {}",
+                                        "Category".cyan(),
+                                        collage_data.collage_category_name
+                                    );
+                                    status!(
+                                        "{}: {}",
+                                        "Total groups".cyan(),
+                                        collage_data.torrent_groups.len()
+                                    );
+                                }
+                            }
+                            GroupData::ArtistData(ref artist_data) => {
+                                if verbose {
+                                    status!(
+                                        "{}: {}",
+                                        "Artist name".cyan(),
+                                        artist_data.name.bright_white()
+                                    );
+                                    status!(
+                                        "{}: {}",
+                                        "Total groups".cyan(),
+                                        artist_data.torrent_groups.len()
+                                    );
+                                }
+                            }
+                            GroupData::Resolved(ref resolved) => {
+                                if verbose {
+                                    status!("{}: {}", "Name".cyan(), resolved.name.bright_white());
+                                    status!(
+                                        "{}: {}",
+                                        "Matched torrents".cyan(),
+                                        resolved.torrents.len()
+                                    );
+                                }
+                            }
                         }
-                        Err(e) => {
-                            eprintln!("{} Failed to store data: {}", "✗".red().bold(), e);
-                            std::process::exit(1);
+                        let weight = config.category_weights.weight(weight, &group_data);
+                        match db.store_data(&group_data, weight) {
+                            Ok(summary) => {
+                                status!(
+                                    "{} Torrents stored successfully: {}",
+                                    "✓".green().bold(),
+                                    summary.to_string().bright_white()
+                                );
+                                db.drop_alias_fetch(&group_data, id)?;
+                            }
+                            Err(e) => {
+                                eprintln!("{} Failed to store data: {}", "✗".red().bold(), e);
+                                std::process::exit(1);
+                            }
                         }
                     }
-                }
-                Err(e) => {
-                    eprintln!("{} Failed to fetch : {}", "✗".red().bold(), e);
-                    std::process::exit(1);
+                    Err(e) if e.action() == ErrorAction::Skip => {
+                        eprintln!(
+                            "{} Nothing to fetch for {} {}: {}",
+                            "!".yellow().bold(),
+                            ftype,
+                            id,
+                            e
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("{} Failed to fetch : {}", "✗".red().bold(), e);
+                        std::process::exit(1);
+                    }
                 }
             }
         }
@@ -154,39 +1416,150 @@ async fn main() -> Result<()> {
             plex,
             torrent_dir,
             download_dir,
-            transmission_remote,
+            transmission,
             no_fl,
             freeload_only,
+            resume,
+            max_failures,
+            strategy,
+            half_life,
+            weight_formula,
+            max_per_artist,
+            max_per_fetch,
+            min_weight,
+            unknown_year,
+            covers,
+            paused,
+            priority,
+            label,
+            min_seed_time,
         } => {
-            let api_key = std::env::var("API_KEY").expect("API key environment variable not set");
-            let torrs = add_new_torrents_for_download(
-                &api_key,
-                &args.base_url,
-                &args.pool,
-                &plex,
-                &torrent_dir,
-                number,
-                &transmission_remote,
-                &download_dir,
-                !no_fl,
-                freeload_only,
-            )
-            .await?;
-            println!(
-                "\n{} {} torrent files downloaded",
-                "✓".green().bold(),
-                torrs.len().to_string().bright_white()
-            );
-            for t in &torrs {
-                println!(
-                    "{} | {} | {}",
-                    t.id.to_string().bright_white(),
-                    t.artist_names.bright_cyan(),
-                    t.album_name.bright_yellow()
+            let api = api_client()?;
+            let options = WatchOptions::new(&plex, &torrent_dir, &download_dir)
+                .with_number(number)
+                .with_transmission(
+                    transmission
+                        .client()
+                        .with_paused(paused)
+                        .with_priority(priority)
+                        .with_label(label),
+                )
+                .with_freeleech(!no_fl)
+                .with_freeload_only(freeload_only)
+                .with_resume(resume)
+                .with_max_failures(max_failures)
+                .with_strategy(strategy, half_life)
+                .with_weight_formula(weight_formula)
+                .with_caps(max_per_artist, max_per_fetch)
+                .with_min_weight(min_weight)
+                .with_unknown_year(unknown_year)
+                .with_covers(covers.then(|| covers_dir.clone()).flatten())
+                .with_hooks(config.hooks.clone())
+                .with_min_seed_time(min_seed_time)
+                .with_scrobble_boost(config.lastfm.as_ref().map(|l| l.boost));
+            let report = match add_new_torrents_for_download(&api, &pool, &options).await {
+                Ok(report) => report,
+                Err(error) => {
+                    if !args.offline {
+                        let event = Event::Failed {
+                            run: Run::Watch,
+                            error: &error,
+                        };
+                        notify_all(&config.notify, &event).await;
+                    }
+                    return Err(error.into());
+                }
+            };
+            print_watch_report(&report, args.offline);
+            if !args.offline {
+                notify_all(&config.notify, &Event::Watch(&report)).await;
+            }
+        }
+        Commands::Queue { ids, queue } => {
+            let api = api_client()?;
+            let options = queue.options(&config, ids);
+            let report = add_new_torrents_for_download(&api, &pool, &options).await?;
+            print_watch_report(&report, args.offline);
+            if !args.offline {
+                notify_all(&config.notify, &Event::Watch(&report)).await;
+            }
+        }
+        Commands::Snatch { url, queue } => {
+            let link = Permalink::parse(&base_url, &url)?;
+            let api = api_client()?;
+            let id = resolve_permalink(&api, link).await?;
+            let options = queue.options(&config, vec![id]);
+            let report = add_new_torrents_for_download(&api, &pool, &options).await?;
+            print_watch_report(&report, args.offline);
+            if !args.offline {
+                notify_all(&config.notify, &Event::Watch(&report)).await;
+            }
+        }
+        Commands::Sync => {
+            let api = api_client()?;
+            let result = async {
+                let mut summary = sync_fetches(&api, &db, &config.sources).await?;
+                if let Some(subscriptions) = &config.subscriptions {
+                    summary += sync_subscriptions(
+                        &api,
+                        &db,
+                        subscriptions.weight,
+                        &config.category_weights,
+                    )
+                    .await?;
+                }
+                Ok::<_, RedmanError>(summary)
+            }
+            .await;
+            match result {
+                Ok(summary) => {
+                    status!(
+                        "{} Torrents stored successfully: {}",
+                        "✓".green().bold(),
+                        summary.to_string().bright_white()
+                    );
+                    if !args.offline {
+                        notify_all(&config.notify, &Event::Sync(&summary)).await;
+                    }
+                    if let Some(lastfm) = &config.lastfm
+                        && let Err(e) = lastfm::refresh(&pool, lastfm).await
+                    {
+                        eprintln!(
+                            "{} Could not refresh Last.fm play counts: {}",
+                            "!".yellow().bold(),
+                            e
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{} Failed to sync: {}", "✗".red().bold(), e);
+                    if !args.offline {
+                        let event = Event::Failed {
+                            run: Run::Sync,
+                            error: &e,
+                        };
+                        notify_all(&config.notify, &event).await;
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Daemon => {
+            if config_path.is_none() {
+                eprintln!(
+                    "{} The daemon requires --config or ~/.config/redman/config.toml",
+                    "✗".red().bold()
                 );
+                std::process::exit(1);
             }
+            daemon::run(Arc::new(api_client()?), &pool, Arc::new(config)).await?;
         }
-        Commands::Stats => match db.get_stats() {
+        Commands::Stats {
+            format: OutputFormat::Json,
+        } => println!("{}", serde_json::to_string_pretty(&db.get_stats()?)?),
+        Commands::Stats {
+            format: OutputFormat::Text,
+        } => match db.get_stats() {
             Ok(stats) => {
                 println!("\n{}", "Database Statistics".cyan().bold().underline());
                 println!(
@@ -204,23 +1577,775 @@ async fn main() -> Result<()> {
                     "Unique Albums".bold(),
                     stats.unique_albums.to_string().bright_white()
                 );
+                println!(
+                    "{}: {}",
+                    "Total Size".bold(),
+                    format_size(stats.total_size).bright_white()
+                );
+                println!(
+                    "{}: {}",
+                    "Average Size".bold(),
+                    format_size(stats.average_size).bright_white()
+                );
 
-                println!("\n{}", "Format Distribution:".bold());
-                for (format, count) in stats.format_counts {
-                    let percentage = (count as f64 / stats.total_torrents as f64) * 100.0;
+                print_distribution("Format", &stats.format_counts, stats.total_torrents);
+                print_distribution("Media", &stats.media_counts, stats.total_torrents);
+                print_distribution("Encoding", &stats.encoding_counts, stats.total_torrents);
+                print_distribution(
+                    "Release Type",
+                    &stats.release_type_counts,
+                    stats.total_torrents,
+                );
+
+                println!("\n{}", "Release Decades:".bold());
+                let max = stats
+                    .decade_counts
+                    .iter()
+                    .map(|(_, c)| *c)
+                    .max()
+                    .unwrap_or(1);
+                for (decade, count) in &stats.decade_counts {
+                    let bar = "█".repeat(((*count * 30) as f64 / max as f64).ceil() as usize);
+                    println!("  {:>7}: {} {}", decade.bright_white(), bar.cyan(), count);
+                }
+
+                println!("\n{}", "Weight Distribution:".bold());
+                for w in stats.weight_counts {
+                    let percentage = (w.count as f64 / stats.total_torrents as f64) * 100.0;
                     println!(
-                        "  {}: {} ({:.1}%)",
-                        format.bright_white(),
-                        count.to_string().cyan(),
-                        percentage
+                        "  {}: {} ({:.1}%), {}",
+                        w.weight.to_string().bright_white(),
+                        w.count.to_string().cyan(),
+                        percentage,
+                        format_size(w.size)
                     );
                 }
+
+                if !stats.fetch_stats.is_empty() {
+                    println!("\n{}", "Fetch Sources:".bold());
+                    for f in stats.fetch_stats {
+                        println!(
+                            "  {} {} {} (weight {}): {} torrents, {}, {} downloaded",
+                            f.ftype,
+                            f.id,
+                            f.name.bright_white(),
+                            f.weight,
+                            f.torrents.to_string().cyan(),
+                            format_size(f.size),
+                            f.downloaded.to_string().green()
+                        );
+                    }
+                }
             }
             Err(e) => {
                 eprintln!("{} Failed to get stats: {}", "✗".red().bold(), e);
                 std::process::exit(1);
             }
         },
+        Commands::Fetches => {
+            let fetches = db.get_fetches()?;
+            if fetches.is_empty() {
+                status!("No fetches stored");
+            } else {
+                let rows = fetches.into_iter().map(|f| FetchRow {
+                    id: f.id,
+                    ftype: f.ftype.to_string(),
+                    name: f.name,
+                    category: f.category.unwrap_or_default(),
+                    weight: f.weight,
+                    created_at: f.created_at,
+                    torrents: f.torrents,
+                });
+                println!("{}", Table::new(rows).with(Style::sharp()));
+            }
+        }
+        Commands::RemoveFetch { ftype, id, purge } => {
+            let id = id.unwrap_or_default();
+            match db.remove_fetch(ftype, id, purge)? {
+                Some(purged) => {
+                    status!("{} Removed {} {}", "✓".green().bold(), ftype, id);
+                    if purge {
+                        status!(
+                            "{} {} torrents purged from the pool",
+                            "✓".green().bold(),
+                            purged.to_string().bright_white()
+                        );
+                    }
+                }
+                None => {
+                    eprintln!("{} No stored fetch for {} {}", "✗".red().bold(), ftype, id);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Reweight { ftype, id, weight } => {
+            let id = id.unwrap_or_default();
+            match db.reweight_fetch(ftype, id, weight)? {
+                Some(updated) => status!(
+                    "{} {} torrents of {} {} now have weight {}",
+                    "✓".green().bold(),
+                    updated.to_string().bright_white(),
+                    ftype,
+                    id,
+                    weight.to_string().bright_white()
+                ),
+                None => {
+                    eprintln!("{} No stored fetch for {} {}", "✗".red().bold(), ftype, id);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::List {
+            artist,
+            year,
+            encoding,
+            min_weight,
+            sort,
+            reverse,
+            limit,
+            page,
+        } => {
+            let filter = PoolFilter {
+                artist,
+                year,
+                encoding,
+                min_weight,
+                query: None,
+            };
+            let limit = limit as usize;
+            let offset = (page as usize - 1) * limit;
+            let (torrents, total) = db.list_torrents(&filter, sort, reverse, limit, offset)?;
+            if torrents.is_empty() {
+                status!("No torrents match ({} in total)", total);
+            } else {
+                let shown = torrents.len();
+                let rows = torrents.into_iter().map(|t| PoolRow::new(t, &base_url));
+                println!("{}", Table::new(rows).with(Style::sharp()));
+                status!(
+                    "Showing {}-{} of {} torrents (page {} of {})",
+                    offset + 1,
+                    offset + shown,
+                    total.to_string().bright_white(),
+                    page,
+                    total.div_ceil(limit)
+                );
+            }
+        }
+        Commands::Search { query, limit } => {
+            let torrents = db.search_torrents(&query.join(" "), limit)?;
+            if torrents.is_empty() {
+                status!(
+                    "No torrents in the pool match {}",
+                    query.join(" ").bright_white()
+                );
+            } else {
+                let rows = torrents.into_iter().map(|t| PoolRow::new(t, &base_url));
+                println!("{}", Table::new(rows).with(Style::sharp()));
+            }
+        }
+        Commands::Rm { id, artist, yes } => {
+            let torrents = db.find_torrents(&id, artist.as_deref())?;
+            if torrents.is_empty() {
+                status!("No torrents in the pool match");
+                return Ok(());
+            }
+            let ids: Vec<u32> = torrents.iter().map(|t| t.id).collect();
+            println!(
+                "{}",
+                Table::new(torrents.into_iter().map(|t| PoolRow::new(t, &base_url)))
+                    .with(Style::sharp())
+            );
+            if !yes
+                && !confirm(&format!(
+                    "Delete these {} torrents from the pool?",
+                    ids.len()
+                ))?
+            {
+                status!("Nothing deleted");
+                return Ok(());
+            }
+            let removed = db.remove_torrents(&ids)?;
+            status!(
+                "{} {} torrents removed from the pool",
+                "✓".green().bold(),
+                removed.to_string().bright_white()
+            );
+        }
+        Commands::Export { format, out } => {
+            let export = db.export_pool()?;
+            portable::write(&export, format, out.as_deref())?;
+            status!(
+                "{} Exported {} fetches and {} torrents",
+                "✓".green().bold(),
+                export.fetches.len().to_string().bright_white(),
+                export.torrents.len().to_string().bright_white()
+            );
+        }
+        Commands::Import {
+            path,
+            format,
+            on_conflict,
+        } => {
+            let export = portable::read(&path, format)?;
+            let summary = db.import_pool(&export, on_conflict)?;
+            status!(
+                "{} Imported {} fetches; {} torrents added, {} replaced, {} skipped, {} duplicates",
+                "✓".green().bold(),
+                summary.fetches.to_string().bright_white(),
+                summary.inserted.to_string().bright_white(),
+                summary.replaced.to_string().bright_white(),
+                summary.skipped.to_string().bright_white(),
+                summary.duplicates.to_string().bright_white()
+            );
+        }
+        Commands::Db {
+            command: DbCommands::Maintain,
+        } => {
+            let report = db.maintain()?;
+            if !report.problems.is_empty() {
+                for problem in &report.problems {
+                    eprintln!("{} {}", "✗".red().bold(), problem);
+                }
+                anyhow::bail!("Integrity check failed; database left untouched");
+            }
+            status!("{} Integrity check passed", "✓".green().bold());
+            status!(
+                "{} {} indexes: {}",
+                "✓".green().bold(),
+                report.indexes.len().to_string().bright_white(),
+                report
+                    .indexes
+                    .iter()
+                    .map(|(name, table)| format!("{} ({})", name, table))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            status!(
+                "{} Vacuumed {} free pages: {} -> {}",
+                "✓".green().bold(),
+                report.free_pages.to_string().bright_white(),
+                format_size(report.size_before),
+                format_size(report.size_after).bright_white()
+            );
+        }
+        Commands::Db {
+            command: DbCommands::Backup { out, keep },
+        } => {
+            let pool = Path::new(&pool);
+            let stem = pool
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "pool".to_string());
+            let out =
+                out.unwrap_or_else(|| pool.parent().unwrap_or(Path::new(".")).join("backups"));
+            let path = if out.is_dir() || out.extension().is_none() {
+                fs::create_dir_all(&out)?;
+                out.join(format!(
+                    "{}-{}.db",
+                    stem,
+                    Local::now().format("%Y%m%d-%H%M%S")
+                ))
+            } else {
+                out
+            };
+            db.backup(&path)?;
+            status!(
+                "{} Backed up to {}",
+                "✓".green().bold(),
+                path.display().to_string().bright_white()
+            );
+            if let Some(keep) = keep {
+                let dir = path.parent().unwrap_or(Path::new("."));
+                let removed = rotate_backups(dir, &stem, keep)?;
+                if removed > 0 {
+                    status!("{} Removed {} old backups", "✓".green().bold(), removed);
+                }
+            }
+        }
+        Commands::Db {
+            command: DbCommands::Restore { path, yes },
+        } => {
+            let mut db = db;
+            if !yes && !confirm(&format!("Replace {} with {}?", pool, path.display()))? {
+                status!("Nothing restored");
+                return Ok(());
+            }
+            db.restore(&path)?;
+            status!(
+                "{} Restored {} from {}",
+                "✓".green().bold(),
+                pool,
+                path.display()
+            );
+        }
+        Commands::Tui => {
+            if let tui::Outcome::Watch =
+                tui::run(&db, config.watch.is_some(), covers_dir.as_deref())?
+                && config.watch.is_some()
+            {
+                let report = daemon::run_watch(&api_client()?, &pool, &config).await?;
+                print_watch_report(&report, args.offline);
+                if !args.offline {
+                    notify_all(&config.notify, &Event::Watch(&report)).await;
+                }
+            }
+        }
+        Commands::Covers => {
+            let Some(dir) = &covers_dir else {
+                eprintln!("{} Pass --covers-dir or set HOME", "✗".red().bold());
+                std::process::exit(2);
+            };
+            if args.offline {
+                eprintln!("{} Covers cannot be downloaded offline", "✗".red().bold());
+                std::process::exit(1);
+            }
+            let torrents = db.torrents_with_covers()?;
+            let summary = covers::download_covers(&reqwest::Client::new(), &torrents, dir).await;
+            status!(
+                "{} {} covers saved to {}, {} already there, {} failed",
+                "✓".green().bold(),
+                summary.saved.to_string().bright_white(),
+                dir.display(),
+                summary.cached,
+                summary.failed
+            );
+        }
+        Commands::Completions { .. } => unreachable!("handled before opening the pool"),
+        #[cfg(feature = "keyring")]
+        Commands::Auth { .. } => unreachable!("handled before opening the pool"),
+        Commands::Check { transmission } => {
+            let options = config.check_options(transmission.client());
+            let completed = check_downloads(&pool, &options).await?;
+            if completed.is_empty() {
+                status!("No downloads completed since the last check");
+            } else {
+                let rows = completed.iter().map(|c| CompletedRow {
+                    torrent_id: c.download.torrent_id,
+                    artist: c.download.artist_names.clone(),
+                    album: c.download.album_name.clone(),
+                    path: c.path.display().to_string(),
+                    imported: match c.imported {
+                        Some(true) => "yes",
+                        Some(false) => "failed",
+                        None => "",
+                    },
+                });
+                println!("{}", Table::new(rows).with(Style::sharp()));
+                status!(
+                    "{} downloads completed",
+                    completed.len().to_string().bright_white()
+                );
+            }
+        }
+        Commands::Seeding {
+            unmet,
+            transmission,
+        } => {
+            let downloads = poll_seeding(&pool, &transmission.client())?;
+            let total = downloads.len();
+            let met = downloads.iter().filter(|d| d.seeded_enough()).count();
+            let rows: Vec<SeedingRow> = downloads
+                .into_iter()
+                .filter(|d| !unmet || !d.seeded_enough())
+                .map(|d| SeedingRow::new(&d))
+                .collect();
+            if rows.is_empty() {
+                status!("No torrents to show");
+            } else {
+                println!("{}", Table::new(rows).with(Style::sharp()));
+            }
+            status!(
+                "{} of {} torrents seeded long enough",
+                met.to_string().bright_white(),
+                total.to_string().bright_white()
+            );
+        }
+        Commands::Cleanup {
+            plex,
+            min_ratio,
+            min_seed_time,
+            delete_data,
+            yes,
+            transmission,
+        } => {
+            let Some(plex) = plex.or_else(|| config.watch.as_ref().map(|w| w.plex.clone())) else {
+                eprintln!("{} Pass --plex or set it in [watch]", "✗".red().bold());
+                std::process::exit(2);
+            };
+            let delete_data = delete_data || config.cleanup.delete_data;
+            let options = CleanupOptions::new(transmission.client(), &plex)
+                .with_thresholds(
+                    min_ratio.or(config.cleanup.min_ratio),
+                    min_seed_time.or(config.cleanup.min_seed_time),
+                )
+                .with_delete_data(delete_data);
+            let candidates = cleanup_candidates(&pool, &options)?;
+            if candidates.is_empty() {
+                status!("No torrents to remove");
+                return Ok(());
+            }
+            println!(
+                "{}",
+                Table::new(candidates.iter().map(SeedingRow::new)).with(Style::sharp())
+            );
+            let what = if delete_data {
+                "and delete their files"
+            } else {
+                "and keep their files"
+            };
+            if !yes
+                && !confirm(&format!(
+                    "Remove these {} torrents from the client {}?",
+                    candidates.len(),
+                    what
+                ))?
+            {
+                status!("Nothing removed");
+                return Ok(());
+            }
+            let removed = remove_downloads(&pool, &options, &candidates)?;
+            status!(
+                "{} {} torrents removed from the client",
+                "✓".green().bold(),
+                removed.to_string().bright_white()
+            );
+        }
+        Commands::Verify {
+            after,
+            plex,
+            requeue,
+            torrent_dir,
+            transmission,
+        } => {
+            let Some(plex) = plex.or_else(|| config.watch.as_ref().map(|w| w.plex.clone())) else {
+                eprintln!("{} Pass --plex or set it in [watch]", "✗".red().bold());
+                std::process::exit(2);
+            };
+            let missing = missing_from_plex(&pool, &plex, after)?;
+            if missing.is_empty() {
+                status!(
+                    "{} Every download older than {} is in Plex",
+                    "✓".green().bold(),
+                    humantime::format_duration(after)
+                );
+                return Ok(());
+            }
+            let rows = missing.iter().map(|d| HistoryRow {
+                added_at: d.added_at.clone(),
+                torrent_id: d.torrent_id,
+                artist: d.artist_names.clone(),
+                album: d.album_name.clone(),
+                size: format_size(d.size),
+                fl_token: if d.fl_token { "yes" } else { "" },
+                completed_at: d.completed_at.clone().unwrap_or_default(),
+                link: permalink(&base_url, d.group_id, d.torrent_id),
+            });
+            println!("{}", Table::new(rows).with(Style::sharp()));
+            status!(
+                "{} {} downloads never reached Plex",
+                "!".yellow().bold(),
+                missing.len().to_string().bright_white()
+            );
+            if requeue {
+                let Some(torrent_dir) =
+                    torrent_dir.or_else(|| config.watch.as_ref().map(|w| w.torrent_dir.clone()))
+                else {
+                    eprintln!(
+                        "{} Pass --torrent-dir or set it in [watch]",
+                        "✗".red().bold()
+                    );
+                    std::process::exit(2);
+                };
+                let requeued =
+                    downloads::requeue(&pool, &torrent_dir, &transmission.client(), &missing)?;
+                status!(
+                    "{} {} downloads queued for the next watch run",
+                    "✓".green().bold(),
+                    requeued.to_string().bright_white()
+                );
+            }
+        }
+        Commands::Upgrade {
+            plex,
+            number,
+            list,
+            yes,
+            queue,
+        } => {
+            if list {
+                let rows = db.get_upgrades()?.into_iter().map(|u| UpgradeRecordRow {
+                    created_at: u.created_at,
+                    torrent_id: u.torrent_id,
+                    artist: u.artist_names,
+                    album: u.album_name,
+                    old_path: u.old_path.unwrap_or_default(),
+                    completed_at: u.completed_at.unwrap_or_default(),
+                });
+                println!("{}", Table::new(rows).with(Style::sharp()));
+                return Ok(());
+            }
+            let Some(plex) = plex.or_else(|| config.watch.as_ref().map(|w| w.plex.clone())) else {
+                eprintln!("{} Pass --plex or set it in [watch]", "✗".red().bold());
+                std::process::exit(2);
+            };
+            let api = api_client()?;
+            let candidates = upgrade::find_upgrades(&api, &pool, &plex, number).await?;
+            if candidates.is_empty() {
+                status!("No lossless replacements found");
+                return Ok(());
+            }
+            let rows = candidates.iter().map(|c| UpgradeRow {
+                torrent_id: c.torrent.id,
+                artist: c.artist.clone(),
+                album: c.album.clone(),
+                edition: format!("{} {}", c.torrent.media, c.torrent.encoding),
+                size: format_size(c.torrent.size),
+                old_path: c
+                    .old_path
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default(),
+            });
+            println!("{}", Table::new(rows).with(Style::sharp()));
+            if !yes
+                && !confirm(&format!(
+                    "Queue these {} lossless torrents?",
+                    candidates.len()
+                ))?
+            {
+                status!("Nothing queued");
+                return Ok(());
+            }
+            let ids = candidates.iter().map(|c| c.torrent.id).collect();
+            let options = queue.options(&config, ids);
+            let report = add_new_torrents_for_download(&api, &pool, &options).await?;
+            print_watch_report(&report, args.offline);
+            let recorded = upgrade::record_upgrades(&pool, &candidates, &report.added)?;
+            status!(
+                "{} {} upgrades recorded, see redman upgrade --list",
+                "✓".green().bold(),
+                recorded.to_string().bright_white()
+            );
+            if !args.offline {
+                notify_all(&config.notify, &Event::Watch(&report)).await;
+            }
+        }
+        Commands::Gaps {
+            plex,
+            artist,
+            fetch,
+            weight,
+        } => {
+            let Some(plex) = plex.or_else(|| config.watch.as_ref().map(|w| w.plex.clone())) else {
+                eprintln!("{} Pass --plex or set it in [watch]", "✗".red().bold());
+                std::process::exit(2);
+            };
+            let api = api_client()?;
+            let found = gaps::find_gaps(&api, &plex, &artist).await?;
+            if found.is_empty() {
+                status!("{} No missing albums found", "✓".green().bold());
+                return Ok(());
+            }
+            let rows = found.iter().flat_map(|a| {
+                a.gaps.iter().map(|g| GapRow {
+                    artist: a.artist.clone(),
+                    album: g.album.clone(),
+                    year: format_year(g.year),
+                    torrent_id: g
+                        .torrent
+                        .as_ref()
+                        .map(|t| t.id.to_string())
+                        .unwrap_or_default(),
+                    link: match (&g.torrent, g.group_id) {
+                        (Some(t), group_id) => permalink(&base_url, group_id, t.id),
+                        (None, Some(group_id)) => {
+                            format!("{}torrents.php?id={}", base_url, group_id)
+                        }
+                        (None, None) => String::new(),
+                    },
+                })
+            });
+            println!("{}", Table::new(rows).with(Style::sharp()));
+            let missing: usize = found.iter().map(|a| a.gaps.len()).sum();
+            if !fetch {
+                status!(
+                    "{} {} missing albums by {} artists, pass --fetch to pool them",
+                    "!".yellow().bold(),
+                    missing.to_string().bright_white(),
+                    found.len().to_string().bright_white()
+                );
+                return Ok(());
+            }
+            let mut summary = StoreSummary::default();
+            for a in &found {
+                summary += db.store_data(&a.data, weight)?;
+            }
+            status!(
+                "{} {} artists pooled: {}",
+                "✓".green().bold(),
+                found.len().to_string().bright_white(),
+                summary.to_string().bright_white()
+            );
+        }
+        Commands::Lastfm { number } => {
+            let Some(lastfm) = &config.lastfm else {
+                eprintln!("{} Set user and api_key in [lastfm]", "✗".red().bold());
+                std::process::exit(2);
+            };
+            let (artists, albums) = lastfm::refresh(&pool, lastfm).await?;
+            let rows = db
+                .top_scrobbles(number)?
+                .into_iter()
+                .map(|(name, plays)| ScrobbleRow { name, plays });
+            println!("{}", Table::new(rows).with(Style::sharp()));
+            status!(
+                "{} Stored play counts of {} artists and {} albums of {}",
+                "✓".green().bold(),
+                artists.to_string().bright_white(),
+                albums.to_string().bright_white(),
+                lastfm.user
+            );
+        }
+        Commands::Rss => {
+            if config.rss.is_none() {
+                eprintln!("{} Set url in [rss]", "✗".red().bold());
+                std::process::exit(2);
+            }
+            config.check_feed_downloads()?;
+            let (feed, added) = daemon::run_rss(&api_client()?, &pool, &config).await?;
+            if !feed.matched.is_empty() {
+                let rows = feed.matched.iter().map(MatchRow::from);
+                println!("{}", Table::new(rows).with(Style::sharp()));
+            }
+            status!(
+                "{} {} new uploads, {} matching, {} inserted, {} replaced",
+                "✓".green().bold(),
+                feed.new_items.to_string().bright_white(),
+                feed.matched.len().to_string().bright_white(),
+                feed.summary.inserted.to_string().bright_white(),
+                feed.summary.replaced.to_string().bright_white()
+            );
+            if let Some(report) = added {
+                print_watch_report(&report, args.offline);
+                if !args.offline {
+                    notify_all(&config.notify, &Event::Watch(&report)).await;
+                }
+            }
+        }
+        Commands::Irc => {
+            if config.irc.is_none() {
+                eprintln!("{} Set server, nick and channel in [irc]", "✗".red().bold());
+                std::process::exit(2);
+            }
+            config.check_feed_downloads()?;
+            daemon::listen_announces(&api_client()?, &pool, &config).await;
+        }
+        Commands::Rules {
+            command: RulesCommands::Test { number },
+        } => {
+            let verdicts = rules::test_rules(&api_client()?, &pool, &config, number).await?;
+            if verdicts.is_empty() {
+                status!(
+                    "{} No uploads seen yet, run `redman rss` or `redman irc` first",
+                    "!".yellow().bold()
+                );
+                return Ok(());
+            }
+            let rows = verdicts.iter().map(VerdictRow::from);
+            println!("{}", Table::new(rows).with(Style::sharp()));
+            let downloads = verdicts
+                .iter()
+                .filter(|v| matches!(v.decision, Some((FeedAction::Download, _))))
+                .count();
+            status!(
+                "{} {} of {} recent uploads would be downloaded right away",
+                "✓".green().bold(),
+                downloads.to_string().bright_white(),
+                verdicts.len().to_string().bright_white()
+            );
+        }
+        Commands::Reconcile {
+            fix,
+            torrent_dir,
+            download_dir,
+            transmission,
+        } => {
+            let watch = config.watch.as_ref();
+            let Some(torrent_dir) = torrent_dir.or_else(|| watch.map(|w| w.torrent_dir.clone()))
+            else {
+                eprintln!(
+                    "{} Pass --torrent-dir or set it in [watch]",
+                    "✗".red().bold()
+                );
+                std::process::exit(2);
+            };
+            // Torrents added again get the label of the ones redman adds
+            let client = transmission
+                .client()
+                .with_label(watch.and_then(|w| w.label.clone()));
+            let found = reconcile::find_inconsistencies(&pool, &torrent_dir, &client)?;
+            if found.is_empty() {
+                status!(
+                    "{} Records, torrent files and client agree",
+                    "✓".green().bold()
+                );
+                return Ok(());
+            }
+            let rows = found.iter().map(|i| ReconcileRow {
+                kind: i.kind(),
+                torrent_id: i.torrent_id(),
+                fix: i.fix_description(),
+            });
+            println!("{}", Table::new(rows).with(Style::sharp()));
+            if !fix {
+                status!(
+                    "{} {} inconsistencies, pass --fix to fix them",
+                    "!".yellow().bold(),
+                    found.len().to_string().bright_white()
+                );
+                return Ok(());
+            }
+            let Some(download_dir) = download_dir.or_else(|| watch.map(|w| w.download_dir.clone()))
+            else {
+                eprintln!(
+                    "{} Pass --download-dir or set it in [watch]",
+                    "✗".red().bold()
+                );
+                std::process::exit(2);
+            };
+            let fixed = reconcile::fix(&pool, &client, &download_dir, &found)?;
+            status!(
+                "{} {} of {} inconsistencies fixed",
+                "✓".green().bold(),
+                fixed.to_string().bright_white(),
+                found.len()
+            );
+        }
+        Commands::History { since } => {
+            let downloads = db.get_downloads(since)?;
+            if downloads.is_empty() {
+                status!("No downloads recorded");
+            } else {
+                let total: u64 = downloads.iter().map(|d| d.size).sum();
+                let rows = downloads.into_iter().map(|d| HistoryRow {
+                    added_at: d.added_at,
+                    torrent_id: d.torrent_id,
+                    artist: d.artist_names,
+                    album: d.album_name,
+                    size: format_size(d.size),
+                    fl_token: if d.fl_token { "yes" } else { "" },
+                    completed_at: d.completed_at.unwrap_or_default(),
+                    link: permalink(&base_url, d.group_id, d.torrent_id),
+                });
+                let count = rows.len();
+                println!("{}", Table::new(rows).with(Style::sharp()));
+                status!(
+                    "{} torrents, {} total",
+                    count.to_string().bright_white(),
+                    format_size(total).bright_white()
+                );
+            }
+        }
     }
 
     Ok(())
@@ -0,0 +1,86 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A counter exported on the daemon's `/metrics` endpoint.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Counter {
+    ApiRequests,
+    RateLimitWaits,
+    TorrentsAdded,
+    DownloadedBytes,
+    RunErrors,
+    TorrentErrors,
+}
+
+const COUNTERS: [Counter; 6] = [
+    Counter::ApiRequests,
+    Counter::RateLimitWaits,
+    Counter::TorrentsAdded,
+    Counter::DownloadedBytes,
+    Counter::RunErrors,
+    Counter::TorrentErrors,
+];
+
+static VALUES: [AtomicU64; COUNTERS.len()] = [const { AtomicU64::new(0) }; COUNTERS.len()];
+
+impl Counter {
+    /// Metric name, labels and help text.
+    fn describe(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Counter::ApiRequests => (
+                "redman_api_requests_total",
+                "",
+                "Requests sent to the tracker API.",
+            ),
+            Counter::RateLimitWaits => (
+                "redman_rate_limit_waits_total",
+                "",
+                "Times a request waited for the tracker rate limit.",
+            ),
+            Counter::TorrentsAdded => (
+                "redman_torrents_added_total",
+                "",
+                "Torrents added to the client.",
+            ),
+            Counter::DownloadedBytes => (
+                "redman_downloaded_bytes_total",
+                "",
+                "Total size of the torrents added to the client.",
+            ),
+            Counter::RunErrors => (
+                "redman_errors_total",
+                r#"{kind="run"}"#,
+                "Failed scheduled runs and torrents that could not be added.",
+            ),
+            Counter::TorrentErrors => (
+                "redman_errors_total",
+                r#"{kind="torrent"}"#,
+                "Failed scheduled runs and torrents that could not be added.",
+            ),
+        }
+    }
+}
+
+/// Count `n` more of `counter`.
+pub(crate) fn add(counter: Counter, n: u64) {
+    VALUES[counter as usize].fetch_add(n, Ordering::Relaxed);
+}
+
+/// All counters in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let mut last_name = "";
+    for counter in COUNTERS {
+        let (name, labels, help) = counter.describe();
+        if name != last_name {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            last_name = name;
+        }
+        let value = VALUES[counter as usize].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+    out
+}
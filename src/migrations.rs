@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+
+use rusqlite::{Connection, Transaction, params};
+use tracing::{debug, info};
+
+use crate::{
+    db::release_key,
+    error::{RedmanError, Result},
+};
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Schema changes in order; the pool's `user_version` is the number already
+/// applied. Only append to this list. Databases created before versioning
+/// start at 0, so every step has to tolerate its change already being there.
+const MIGRATIONS: &[Migration] = &[
+    create_pool,
+    create_journal,
+    create_downloads,
+    create_failures,
+    create_torrent_fetches,
+    add_fetch_weight,
+    create_torrents_fts,
+    add_release_key,
+    create_selection_indexes,
+    create_torrent_marks,
+    make_year_nullable,
+    add_group_ids,
+    add_cover_url,
+    track_completions,
+    track_seeding,
+    track_removals,
+    track_requeues,
+    index_downloads,
+    create_upgrades,
+    create_scrobbles,
+    add_fetch_source,
+    create_feed_items,
+    add_feed_item_details,
+    add_fetch_checkpoint,
+    create_artist_aliases,
+    add_fetch_category,
+    add_journal_torrents,
+];
+
+/// Bring the database schema up to date.
+pub(crate) fn migrate(conn: &Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(RedmanError::UnsupportedSchema {
+            found: version,
+            known: MIGRATIONS.len(),
+        });
+    }
+    if version < MIGRATIONS.len() {
+        info!(
+            from = version,
+            to = MIGRATIONS.len(),
+            "Migrating pool database schema"
+        );
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        debug!(version = i + 1, "Applying migration");
+        let tx = conn.unchecked_transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn create_pool(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS torrents (
+            id INTEGER PRIMARY KEY,
+            album_name TEXT NOT NULL,
+            artist_names TEXT NOT NULL,
+            year INTEGER NOT NULL,
+            release_type INTEGER NOT NULL,
+            media TEXT NOT NULL,
+            format TEXT NOT NULL,
+            encoding TEXT NOT NULL,
+            file_count INTEGER NOT NULL,
+            size_bytes INTEGER NOT NULL,
+            weight INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS fetches (
+            id INTEGER NOT NULL,
+            type INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            created_at datetime DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (id, type)
+        );
+        "#,
+    )
+}
+
+fn create_journal(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS journal (
+            run_id INTEGER NOT NULL,
+            torrent_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            state TEXT NOT NULL,
+            path TEXT,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (run_id, torrent_id)
+        );
+        "#,
+    )
+}
+
+fn create_downloads(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS downloads (
+            torrent_id INTEGER NOT NULL,
+            artist_names TEXT NOT NULL,
+            album_name TEXT NOT NULL,
+            size_bytes INTEGER NOT NULL,
+            fl_token INTEGER NOT NULL,
+            added_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        "#,
+    )
+}
+
+fn create_failures(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS failures (
+            torrent_id INTEGER NOT NULL,
+            reason TEXT NOT NULL,
+            failed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        "#,
+    )
+}
+
+fn create_torrent_fetches(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS torrent_fetches (
+            torrent_id INTEGER NOT NULL,
+            fetch_id INTEGER NOT NULL,
+            fetch_type INTEGER NOT NULL,
+            PRIMARY KEY (torrent_id, fetch_id, fetch_type)
+        );
+        "#,
+    )
+}
+
+fn add_fetch_weight(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "fetches", "weight")? {
+        tx.execute(
+            "ALTER TABLE fetches ADD COLUMN weight INTEGER NOT NULL DEFAULT 10",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Full-text index over artist and album names, kept in sync by triggers.
+fn create_torrents_fts(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS torrents_fts USING fts5(
+            artist_names, album_name, content='torrents', content_rowid='id'
+        );
+        CREATE TRIGGER IF NOT EXISTS torrents_fts_insert AFTER INSERT ON torrents BEGIN
+            INSERT INTO torrents_fts (rowid, artist_names, album_name)
+            VALUES (new.id, new.artist_names, new.album_name);
+        END;
+        CREATE TRIGGER IF NOT EXISTS torrents_fts_delete AFTER DELETE ON torrents BEGIN
+            INSERT INTO torrents_fts (torrents_fts, rowid, artist_names, album_name)
+            VALUES ('delete', old.id, old.artist_names, old.album_name);
+        END;
+        CREATE TRIGGER IF NOT EXISTS torrents_fts_update AFTER UPDATE ON torrents BEGIN
+            INSERT INTO torrents_fts (torrents_fts, rowid, artist_names, album_name)
+            VALUES ('delete', old.id, old.artist_names, old.album_name);
+            INSERT INTO torrents_fts (rowid, artist_names, album_name)
+            VALUES (new.id, new.artist_names, new.album_name);
+        END;
+        INSERT INTO torrents_fts (torrents_fts) VALUES ('rebuild');
+        "#,
+    )
+}
+
+/// Normalized artist/album key, unique so a record fetched under two torrent
+/// ids is stored once. Existing duplicates keep the heaviest entry.
+fn add_release_key(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "torrents", "release_key")? {
+        tx.execute("ALTER TABLE torrents ADD COLUMN release_key TEXT", [])?;
+    }
+    let rows = tx
+        .prepare("SELECT id, artist_names, album_name FROM torrents ORDER BY weight DESC, id")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<Vec<(u32, String, String)>>>()?;
+    let mut kept: HashMap<String, u32> = HashMap::new();
+    for (id, artist_names, album_name) in rows {
+        let key = release_key(&artist_names, &album_name);
+        match kept.get(&key) {
+            // Not `replace_duplicate`, as that also moves marks, which come
+            // in a later migration
+            Some(&keep_id) => {
+                tx.execute(
+                    "UPDATE OR IGNORE torrent_fetches SET torrent_id = ? WHERE torrent_id = ?",
+                    [keep_id, id],
+                )?;
+                tx.execute("DELETE FROM torrent_fetches WHERE torrent_id = ?", [id])?;
+                tx.execute("DELETE FROM torrents WHERE id = ?", [id])?;
+            }
+            None => {
+                tx.execute(
+                    "UPDATE torrents SET release_key = ? WHERE id = ?",
+                    params![key, id],
+                )?;
+                kept.insert(key, id);
+            }
+        }
+    }
+    tx.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS torrents_release_key ON torrents (release_key)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Indexes for the orderings and filters of selection, `list` and the
+/// provenance joins.
+fn create_selection_indexes(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS torrents_weight ON torrents (weight);
+        CREATE INDEX IF NOT EXISTS torrents_artist_names ON torrents (artist_names COLLATE NOCASE);
+        CREATE INDEX IF NOT EXISTS torrents_year ON torrents (year);
+        CREATE INDEX IF NOT EXISTS torrents_created_at ON torrents (created_at);
+        CREATE INDEX IF NOT EXISTS torrent_fetches_fetch ON torrent_fetches (fetch_id, fetch_type);
+        "#,
+    )
+}
+
+fn create_torrent_marks(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS torrent_marks (
+            torrent_id INTEGER PRIMARY KEY,
+            mark TEXT NOT NULL,
+            marked_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        "#,
+    )
+}
+
+/// Unknown years were stored as 0; store them as NULL instead. SQLite cannot
+/// drop a NOT NULL constraint, so the table is rebuilt along with its indexes
+/// and full-text triggers.
+fn make_year_nullable(tx: &Transaction) -> rusqlite::Result<()> {
+    let not_null: bool = tx.query_row(
+        "SELECT \"notnull\" FROM pragma_table_info('torrents') WHERE name = 'year'",
+        [],
+        |row| row.get(0),
+    )?;
+    if not_null {
+        tx.execute_batch(
+            r#"
+            CREATE TABLE torrents_new (
+                id INTEGER PRIMARY KEY,
+                album_name TEXT NOT NULL,
+                artist_names TEXT NOT NULL,
+                year INTEGER,
+                release_type INTEGER NOT NULL,
+                media TEXT NOT NULL,
+                format TEXT NOT NULL,
+                encoding TEXT NOT NULL,
+                file_count INTEGER NOT NULL,
+                size_bytes INTEGER NOT NULL,
+                weight INTEGER NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                release_key TEXT
+            );
+            INSERT INTO torrents_new (
+                id, album_name, artist_names, year, release_type, media, format, encoding,
+                file_count, size_bytes, weight, created_at, release_key
+            )
+            SELECT id, album_name, artist_names, year, release_type, media, format, encoding,
+                file_count, size_bytes, weight, created_at, release_key
+            FROM torrents;
+            DROP TABLE torrents;
+            ALTER TABLE torrents_new RENAME TO torrents;
+            CREATE UNIQUE INDEX torrents_release_key ON torrents (release_key);
+            "#,
+        )?;
+        create_torrents_fts(tx)?;
+        create_selection_indexes(tx)?;
+    }
+    tx.execute("UPDATE torrents SET year = NULL WHERE year = 0", [])?;
+    Ok(())
+}
+
+/// Tracker group of pool torrents and downloads, for permalinks.
+fn add_group_ids(tx: &Transaction) -> rusqlite::Result<()> {
+    for table in ["torrents", "downloads"] {
+        if !has_column(tx, table, "group_id")? {
+            tx.execute(
+                &format!("ALTER TABLE {} ADD COLUMN group_id INTEGER", table),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn add_cover_url(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "torrents", "cover_url")? {
+        tx.execute("ALTER TABLE torrents ADD COLUMN cover_url TEXT", [])?;
+    }
+    Ok(())
+}
+
+/// Info hash to find downloads in the client and when they finished there.
+fn track_completions(tx: &Transaction) -> rusqlite::Result<()> {
+    for (column, kind) in [("info_hash", "TEXT"), ("completed_at", "DATETIME")] {
+        if !has_column(tx, "downloads", column)? {
+            tx.execute(
+                &format!("ALTER TABLE downloads ADD COLUMN {} {}", column, kind),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Seed requirement at add time and what the client last reported.
+fn track_seeding(tx: &Transaction) -> rusqlite::Result<()> {
+    for (column, kind) in [
+        ("min_seed_secs", "INTEGER"),
+        ("ratio", "REAL"),
+        ("seeding_secs", "INTEGER"),
+        ("seeding_polled_at", "DATETIME"),
+    ] {
+        if !has_column(tx, "downloads", column)? {
+            tx.execute(
+                &format!("ALTER TABLE downloads ADD COLUMN {} {}", column, kind),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// When `cleanup` removed a download from the client.
+fn track_removals(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "downloads", "removed_at")? {
+        tx.execute("ALTER TABLE downloads ADD COLUMN removed_at DATETIME", [])?;
+    }
+    Ok(())
+}
+
+/// When `verify` queued a download again because it never reached Plex.
+fn track_requeues(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "downloads", "requeued_at")? {
+        tx.execute("ALTER TABLE downloads ADD COLUMN requeued_at DATETIME", [])?;
+    }
+    Ok(())
+}
+
+/// Downloads are looked up by torrent id when selecting and by info hash when
+/// polling the client.
+fn index_downloads(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS downloads_torrent_id ON downloads (torrent_id);
+        CREATE INDEX IF NOT EXISTS downloads_info_hash ON downloads (info_hash);
+        "#,
+    )
+}
+
+/// Lossless downloads replacing an MP3 album in Plex, kept so the old files
+/// can be retired once the new ones arrived.
+fn create_upgrades(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS upgrades (
+            torrent_id INTEGER PRIMARY KEY,
+            plex_album_id INTEGER NOT NULL,
+            artist_names TEXT NOT NULL,
+            album_name TEXT NOT NULL,
+            old_path TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        "#,
+    )
+}
+
+/// Last.fm play counts by normalized artist and album name; artist totals
+/// have an empty album.
+fn create_scrobbles(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS scrobbles (
+            artist_key TEXT NOT NULL,
+            album_key TEXT NOT NULL,
+            name TEXT NOT NULL,
+            playcount INTEGER NOT NULL,
+            PRIMARY KEY (artist_key, album_key)
+        );
+        "#,
+    )
+}
+
+/// What fetches of services besides the tracker are looked up by, such as a
+/// Spotify playlist id.
+fn add_fetch_source(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "fetches", "source")? {
+        tx.execute("ALTER TABLE fetches ADD COLUMN source TEXT", [])?;
+    }
+    Ok(())
+}
+
+/// Torrent ids of RSS feed items already looked at.
+fn create_feed_items(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS feed_items (
+            torrent_id INTEGER PRIMARY KEY,
+            seen_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        "#,
+    )
+}
+
+/// What a feed item announced, for dry-running rules with `rules test`.
+fn add_feed_item_details(tx: &Transaction) -> rusqlite::Result<()> {
+    for (column, kind) in [("title", "TEXT"), ("tags", "TEXT"), ("type", "INTEGER")] {
+        if !has_column(tx, "feed_items", column)? {
+            tx.execute(
+                &format!("ALTER TABLE feed_items ADD COLUMN {} {}", column, kind),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Set while a fetch is partly stored, so a re-run can go on with the groups
+/// it does not have yet.
+fn add_fetch_checkpoint(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "fetches", "checkpoint")? {
+        tx.execute("ALTER TABLE fetches ADD COLUMN checkpoint INTEGER", [])?;
+    }
+    Ok(())
+}
+
+/// Other names of fetched artists, for matching albums Plex files under them.
+fn create_artist_aliases(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS artist_aliases (
+            artist TEXT NOT NULL,
+            alias TEXT NOT NULL,
+            PRIMARY KEY (artist, alias)
+        );
+        "#,
+    )
+}
+
+/// Category of collage fetches, such as "Staff picks", to weigh them by.
+fn add_fetch_category(tx: &Transaction) -> rusqlite::Result<()> {
+    if !has_column(tx, "fetches", "category")? {
+        tx.execute("ALTER TABLE fetches ADD COLUMN category TEXT", [])?;
+    }
+    Ok(())
+}
+
+/// The torrents of a watch run as selected, so a resume finds the ones that
+/// were looked up on the tracker or have left the pool since.
+fn add_journal_torrents(tx: &Transaction) -> rusqlite::Result<()> {
+    for (column, kind) in [
+        ("group_id", "INTEGER"),
+        ("album_name", "TEXT"),
+        ("artist_names", "TEXT"),
+        ("year", "INTEGER"),
+        ("release_type", "INTEGER"),
+        ("media", "TEXT"),
+        ("format", "TEXT"),
+        ("encoding", "TEXT"),
+        ("file_count", "INTEGER"),
+        ("weight", "INTEGER"),
+        ("size_bytes", "INTEGER"),
+        ("created_at", "DATETIME"),
+        ("cover_url", "TEXT"),
+    ] {
+        if !has_column(tx, "journal", column)? {
+            tx.execute(
+                &format!("ALTER TABLE journal ADD COLUMN {} {}", column, kind),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn has_column(tx: &Transaction, table: &str, column: &str) -> rusqlite::Result<bool> {
+    tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?) WHERE name = ?)",
+        [table, column],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pool from before versioning, with some later columns already added
+    /// by the code of the time.
+    const UNVERSIONED: &str = r#"
+        CREATE TABLE torrents (
+            id INTEGER PRIMARY KEY,
+            album_name TEXT NOT NULL,
+            artist_names TEXT NOT NULL,
+            year INTEGER NOT NULL,
+            release_type INTEGER NOT NULL,
+            media TEXT NOT NULL,
+            format TEXT NOT NULL,
+            encoding TEXT NOT NULL,
+            file_count INTEGER NOT NULL,
+            size_bytes INTEGER NOT NULL,
+            weight INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE fetches (
+            id INTEGER NOT NULL,
+            type INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            created_at datetime DEFAULT CURRENT_TIMESTAMP,
+            weight INTEGER NOT NULL DEFAULT 10,
+            PRIMARY KEY (id, type)
+        );
+        CREATE TABLE torrent_fetches (
+            torrent_id INTEGER NOT NULL,
+            fetch_id INTEGER NOT NULL,
+            fetch_type INTEGER NOT NULL,
+            PRIMARY KEY (torrent_id, fetch_id, fetch_type)
+        );
+        INSERT INTO torrents VALUES
+            (1, 'Album', 'Artist', 2001, 1, 'CD', 'MP3', 'V0 (VBR)', 10, 100, 5, NULL),
+            (2, 'album', 'ARTIST', 0, 1, 'WEB', 'MP3', '320', 10, 100, 8, NULL),
+            (3, 'Other', 'Artist', 0, 1, 'CD', 'MP3', '320', 10, 100, 5, NULL);
+        INSERT INTO fetches (id, type, name, weight) VALUES (7, 0, 'Collage', 5);
+        INSERT INTO torrent_fetches VALUES (1, 7, 0), (3, 7, 0);
+    "#;
+
+    fn version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_unversioned_pools() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(UNVERSIONED).unwrap();
+        assert_eq!(version(&conn), 0);
+        migrate(&conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len());
+
+        let torrents: Vec<(u32, Option<u32>)> = conn
+            .prepare("SELECT id, year FROM torrents ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        // The lighter of the two releases with the same key is merged into
+        // the heavier one, fetches included
+        assert_eq!(torrents, [(2, None), (3, None)]);
+        let linked: Vec<u32> = conn
+            .prepare("SELECT torrent_id FROM torrent_fetches ORDER BY torrent_id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(linked, [2, 3]);
+        let weight: u32 = conn
+            .query_row("SELECT weight FROM fetches WHERE id = 7", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(weight, 5);
+        let found: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM torrents_fts WHERE torrents_fts MATCH 'other'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(found, 1);
+
+        // Up to date, so nothing changes
+        migrate(&conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn refuses_newer_schemas() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(matches!(
+            migrate(&conn),
+            Err(RedmanError::UnsupportedSchema { found, known })
+                if found == MIGRATIONS.len() + 1 && known == MIGRATIONS.len()
+        ));
+    }
+}
@@ -0,0 +1,385 @@
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::header::ContentType,
+    transport::smtp::authentication::Credentials,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    api::USER_AGENT,
+    db::{StoreSummary, format_size},
+    error::{RedmanError, Result},
+    watch::WatchReport,
+};
+
+/// Torrents listed by name in a summary; the rest are only counted.
+const MAX_LISTED: usize = 10;
+
+/// Discord rejects messages longer than 2000 characters.
+const DISCORD_LIMIT: usize = 2000;
+
+/// Telegram rejects messages longer than 4096 characters.
+const TELEGRAM_LIMIT: usize = 4096;
+
+const TELEGRAM_API_URL: &str = "https://api.telegram.org/";
+
+const NTFY_URL: &str = "https://ntfy.sh/";
+
+/// A channel run summaries are posted to, configured as a `[[notify]]` entry
+/// with a `type` of "discord", "slack", "telegram", "ntfy", "gotify" or
+/// "email".
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Notifier {
+    /// Discord channel webhook
+    Discord { url: String },
+    /// Slack incoming webhook
+    Slack { url: String },
+    /// Message from a Telegram bot to a chat, group or channel
+    Telegram {
+        token: String,
+        chat_id: ChatId,
+        /// Bot API server, for self-hosted ones
+        #[serde(default = "default_telegram_api_url")]
+        api_url: String,
+    },
+    /// Push to an ntfy topic
+    Ntfy {
+        #[serde(default = "default_ntfy_url")]
+        url: String,
+        topic: String,
+        /// Access token for protected topics
+        token: Option<String>,
+    },
+    /// Push through a Gotify server with an application token
+    Gotify { url: String, token: String },
+    /// Digest mailed through an SMTP server after the chosen runs
+    Email {
+        smtp_host: String,
+        /// Defaults to the standard port of `tls`
+        smtp_port: Option<u16>,
+        #[serde(default)]
+        tls: SmtpTls,
+        username: Option<String>,
+        password: Option<String>,
+        /// Sender, e.g. "redman <redman@example.com>"
+        from: String,
+        to: Vec<String>,
+        /// Runs that send a digest
+        #[serde(default = "all_runs")]
+        runs: Vec<Run>,
+    },
+}
+
+/// How the SMTP connection is secured.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade a plain connection, port 587
+    #[default]
+    Starttls,
+    /// TLS from the start, port 465
+    Tls,
+    /// Unencrypted, port 25; only for a relay on the same machine
+    None,
+}
+
+/// The kind of run an event reports on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Run {
+    Sync,
+    Watch,
+    /// Polling the client for finished downloads
+    Check,
+    /// Polling the `[rss]` feed for new uploads
+    Rss,
+    /// Removing torrents that seeded long enough
+    Cleanup,
+}
+
+impl std::fmt::Display for Run {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Run::Sync => write!(f, "sync"),
+            Run::Watch => write!(f, "watch"),
+            Run::Check => write!(f, "check"),
+            Run::Rss => write!(f, "rss"),
+            Run::Cleanup => write!(f, "cleanup"),
+        }
+    }
+}
+
+fn all_runs() -> Vec<Run> {
+    vec![Run::Sync, Run::Watch, Run::Check, Run::Rss, Run::Cleanup]
+}
+
+/// A Telegram chat: a numeric id or the `@name` of a public channel.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ChatId {
+    Id(i64),
+    Name(String),
+}
+
+fn default_telegram_api_url() -> String {
+    TELEGRAM_API_URL.to_string()
+}
+
+fn default_ntfy_url() -> String {
+    NTFY_URL.to_string()
+}
+
+/// A finished run to report.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    Watch(&'a WatchReport),
+    Sync(&'a StoreSummary),
+    /// `run` ended with `error`
+    Failed {
+        run: Run,
+        error: &'a RedmanError,
+    },
+}
+
+impl Event<'_> {
+    fn run(&self) -> Run {
+        match self {
+            Event::Watch(_) => Run::Watch,
+            Event::Sync(_) => Run::Sync,
+            Event::Failed { run, .. } => *run,
+        }
+    }
+
+    fn title(&self) -> String {
+        match self {
+            Event::Watch(r) => format!(
+                "redman watch: {} torrents added ({}){}",
+                r.added.len(),
+                format_size(r.total_bytes),
+                if r.interrupted { ", interrupted" } else { "" }
+            ),
+            Event::Sync(s) => format!("redman sync: {}", s),
+            Event::Failed { run, .. } => format!("redman {} failed", run),
+        }
+    }
+
+    /// The details, naming at most `max_listed` torrents per list.
+    fn lines(&self, max_listed: usize) -> Vec<String> {
+        let r = match self {
+            Event::Watch(r) => r,
+            Event::Sync(_) => return Vec::new(),
+            Event::Failed { error, .. } => return vec![error.to_string()],
+        };
+        let mut lines = listed(
+            r.added.iter().map(|t| {
+                format!(
+                    "{} - {} ({})",
+                    t.artist_names,
+                    t.album_name,
+                    format_size(t.size)
+                )
+            }),
+            max_listed,
+        );
+        if r.fl_tokens > 0 {
+            lines.push(format!("{} freeleech tokens spent", r.fl_tokens));
+        }
+        if !r.failed.is_empty() {
+            lines.push(format!("{} failed:", r.failed.len()));
+            lines.extend(listed(
+                r.failed.iter().map(|s| {
+                    format!(
+                        "{} - {}: {}",
+                        s.torrent.artist_names, s.torrent.album_name, s.reason
+                    )
+                }),
+                max_listed,
+            ));
+        }
+        lines
+    }
+}
+
+/// One bullet per item, the ones past `max` summed up.
+fn listed(items: impl ExactSizeIterator<Item = String>, max: usize) -> Vec<String> {
+    let total = items.len();
+    let mut lines: Vec<String> = items.take(max).map(|i| format!("• {}", i)).collect();
+    if total > max {
+        lines.push(format!("… and {} more", total - max));
+    }
+    lines
+}
+
+impl Notifier {
+    fn name(&self) -> &'static str {
+        match self {
+            Notifier::Discord { .. } => "Discord",
+            Notifier::Slack { .. } => "Slack",
+            Notifier::Telegram { .. } => "Telegram",
+            Notifier::Ntfy { .. } => "ntfy",
+            Notifier::Gotify { .. } => "Gotify",
+            Notifier::Email { .. } => "email",
+        }
+    }
+
+    /// Whether `event` is worth sending here: push channels only hear about
+    /// queued albums and failures, email about its chosen runs and chat
+    /// channels get every summary.
+    fn wants(&self, event: &Event<'_>) -> bool {
+        match self {
+            Notifier::Ntfy { .. } | Notifier::Gotify { .. } => match event {
+                Event::Watch(r) => !r.added.is_empty() || !r.failed.is_empty(),
+                Event::Sync(_) => false,
+                Event::Failed { .. } => true,
+            },
+            Notifier::Email { runs, .. } => runs.contains(&event.run()),
+            _ => true,
+        }
+    }
+
+    /// Post `event` to this channel.
+    pub async fn send(&self, client: &Client, event: &Event<'_>) -> Result<()> {
+        if let Notifier::Email { .. } = self {
+            return self.send_email(event).await;
+        }
+        let title = event.title();
+        let body = event.lines(MAX_LISTED).join("\n");
+        let urgent = matches!(event, Event::Failed { .. });
+        let request = match self {
+            Notifier::Telegram {
+                token,
+                chat_id,
+                api_url,
+            } => {
+                let text: String = format!("{}\n{}", title, body)
+                    .trim_end()
+                    .chars()
+                    .take(TELEGRAM_LIMIT)
+                    .collect();
+                let url = format!("{}/bot{}/sendMessage", api_url.trim_end_matches('/'), token);
+                client
+                    .post(url)
+                    .json(&json!({ "chat_id": chat_id, "text": text }))
+            }
+            Notifier::Discord { url } => {
+                let text: String = format!("**{}**\n{}", title, body)
+                    .trim_end()
+                    .chars()
+                    .take(DISCORD_LIMIT)
+                    .collect();
+                client.post(url).json(&json!({ "content": text }))
+            }
+            Notifier::Slack { url } => client
+                .post(url)
+                .json(&json!({ "text": format!("*{}*\n{}", title, body).trim_end() })),
+            Notifier::Ntfy { url, topic, token } => {
+                let request = client
+                    .post(format!("{}/{}", url.trim_end_matches('/'), topic))
+                    .header("Title", title)
+                    .header("Priority", if urgent { "high" } else { "default" })
+                    .body(if body.is_empty() {
+                        "-".to_string()
+                    } else {
+                        body
+                    });
+                match token {
+                    Some(token) => request.bearer_auth(token),
+                    None => request,
+                }
+            }
+            Notifier::Email { .. } => unreachable!("sent over SMTP above"),
+            Notifier::Gotify { url, token } => client
+                .post(format!("{}/message", url.trim_end_matches('/')))
+                .header("X-Gotify-Key", token)
+                .json(&json!({
+                    "title": title,
+                    "message": body,
+                    "priority": if urgent { 8 } else { 5 },
+                })),
+        };
+        // Telegram URLs carry the bot token, keep them out of the logs
+        let response = request
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(RedmanError::NotifyError(format!(
+                "{} answered {}",
+                self.name(),
+                status
+            )));
+        }
+        Ok(())
+    }
+
+    /// Mail a digest of `event` listing every torrent.
+    async fn send_email(&self, event: &Event<'_>) -> Result<()> {
+        let Notifier::Email {
+            smtp_host,
+            smtp_port,
+            tls,
+            username,
+            password,
+            from,
+            to,
+            ..
+        } = self
+        else {
+            return Ok(());
+        };
+        let mut message = Message::builder()
+            .from(from.parse().map_err(address_error(from))?)
+            .subject(event.title())
+            .header(ContentType::TEXT_PLAIN);
+        for recipient in to {
+            message = message.to(recipient.parse().map_err(address_error(recipient))?);
+        }
+        let message = message
+            .body(event.lines(usize::MAX).join("\n"))
+            .map_err(|e| RedmanError::NotifyError(format!("Could not build the email: {}", e)))?;
+
+        let smtp_error = |e: lettre::transport::smtp::Error| {
+            RedmanError::NotifyError(format!("SMTP server {}: {}", smtp_host, e))
+        };
+        let mut mailer = match tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(smtp_host)
+                .map_err(smtp_error)?,
+            SmtpTls::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(smtp_host).map_err(smtp_error)?
+            }
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(smtp_host),
+        };
+        if let Some(port) = smtp_port {
+            mailer = mailer.port(*port);
+        }
+        if let (Some(username), Some(password)) = (username, password) {
+            mailer = mailer.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        mailer.build().send(message).await.map_err(smtp_error)?;
+        Ok(())
+    }
+}
+
+fn address_error(address: &str) -> impl FnOnce(lettre::address::AddressError) -> RedmanError {
+    move |e| RedmanError::NotifyError(format!("Invalid email address {}: {}", address, e))
+}
+
+/// Post `event` to all `notifiers`. Failures are logged, never returned, so a
+/// broken webhook cannot fail the run it reports on.
+pub async fn notify_all(notifiers: &[Notifier], event: &Event<'_>) {
+    if notifiers.is_empty() {
+        return;
+    }
+    let client = Client::new();
+    for notifier in notifiers.iter().filter(|n| n.wants(event)) {
+        match notifier.send(&client, event).await {
+            Ok(()) => info!("Notified {}", notifier.name()),
+            Err(e) => warn!("Could not notify {}: {}", notifier.name(), e),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use reqwest::Client;
+use serde::Deserialize;
+
+use crate::{
+    api::USER_AGENT,
+    error::{RedmanError, Result},
+};
+
+/// A Plex Media Server whose music section is rescanned after downloads
+/// complete, configured in `[plex]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlexServer {
+    /// e.g. "http://localhost:32400"
+    pub url: String,
+    /// X-Plex-Token of an account that may manage the server
+    pub token: String,
+    /// Id of the music library section, as in `/library/sections/<id>`
+    pub section: u32,
+}
+
+impl PlexServer {
+    /// Ask Plex to scan the section for new files. The scan itself runs in the
+    /// background on the server.
+    pub async fn scan(&self, client: &Client) -> Result<()> {
+        let url = format!(
+            "{}/library/sections/{}/refresh",
+            self.url.trim_end_matches('/'),
+            self.section
+        );
+        let response = client
+            .get(url)
+            .header("X-Plex-Token", &self.token)
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(RedmanError::ClientError(format!(
+                "Plex could not scan section {}: {}",
+                self.section, status
+            )));
+        }
+        Ok(())
+    }
+}
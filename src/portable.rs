@@ -0,0 +1,180 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    api::Type,
+    error::{RedmanError, Result},
+};
+
+/// The pool in a form that can be moved between machines or opened in a
+/// spreadsheet.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PoolExport {
+    pub fetches: Vec<FetchRecord>,
+    pub torrents: Vec<TorrentRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FetchRecord {
+    pub id: u32,
+    /// "collage", "artist", "listenbrainz", "spotify", "lidarr", "discogs", "rss" or "irc"
+    #[serde(rename = "type")]
+    pub ftype: String,
+    pub name: String,
+    pub weight: u32,
+    pub created_at: String,
+    /// Spotify playlist id of "spotify" fetches
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Collage category of "collage" fetches, e.g. "Staff picks"
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TorrentRecord {
+    pub id: u32,
+    #[serde(default)]
+    pub group_id: Option<u32>,
+    pub album_name: String,
+    pub artist_names: String,
+    pub year: Option<u32>,
+    pub release_type: u32,
+    pub media: String,
+    pub format: String,
+    pub encoding: String,
+    pub file_count: u32,
+    pub size_bytes: u64,
+    pub weight: u32,
+    pub created_at: String,
+    #[serde(default)]
+    pub cover_url: Option<String>,
+    /// Fetches that contributed the torrent, e.g. "collage:7;artist:1"
+    pub sources: String,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    /// A directory holding fetches.csv and torrents.csv
+    Csv,
+    /// A single JSON document
+    Json,
+}
+
+/// What to do with an imported torrent that is already in the pool.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ConflictPolicy {
+    /// Keep the existing entry
+    Skip,
+    /// Overwrite the existing entry
+    Replace,
+    /// Keep whichever entry has the higher weight
+    HighestWeight,
+}
+
+/// Counts reported after an import.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub fetches: usize,
+    pub inserted: usize,
+    pub replaced: usize,
+    pub skipped: usize,
+    /// Torrents for a record the pool holds under another torrent id
+    pub duplicates: usize,
+}
+
+const FETCHES_CSV: &str = "fetches.csv";
+const TORRENTS_CSV: &str = "torrents.csv";
+
+/// Write `export` to `out`. JSON goes to stdout without `out`; CSV needs a
+/// directory because fetches and torrents are separate tables.
+pub fn write(export: &PoolExport, format: Format, out: Option<&Path>) -> Result<()> {
+    match format {
+        Format::Json => {
+            let json = serde_json::to_string_pretty(export).map_err(io::Error::from)?;
+            match out {
+                Some(path) => fs::write(path, json).map_err(RedmanError::file(path))?,
+                None => writeln!(io::stdout(), "{}", json)?,
+            }
+        }
+        Format::Csv => {
+            let dir = out.ok_or_else(|| {
+                RedmanError::InvalidInput(
+                    "CSV export writes two files; pass --out <DIR>".to_string(),
+                )
+            })?;
+            fs::create_dir_all(dir).map_err(RedmanError::file(dir))?;
+            write_csv(&dir.join(FETCHES_CSV), &export.fetches)?;
+            write_csv(&dir.join(TORRENTS_CSV), &export.torrents)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read an export written by [`write`]. Without `format`, a directory is read
+/// as CSV and a file as JSON.
+pub fn read(path: &Path, format: Option<Format>) -> Result<PoolExport> {
+    let format = format.unwrap_or(if path.is_dir() {
+        Format::Csv
+    } else {
+        Format::Json
+    });
+    match format {
+        Format::Json => {
+            let content = fs::read_to_string(path).map_err(RedmanError::file(path))?;
+            serde_json::from_str(&content).map_err(|e| invalid_export(path, e))
+        }
+        Format::Csv => Ok(PoolExport {
+            fetches: read_csv(&path.join(FETCHES_CSV))?,
+            torrents: read_csv(&path.join(TORRENTS_CSV))?,
+        }),
+    }
+}
+
+/// Parse the `sources` column back into `(type, id)` pairs.
+pub fn parse_sources(sources: &str) -> Result<Vec<(Type, u32)>> {
+    sources
+        .split(';')
+        .filter(|s| !s.is_empty())
+        .map(|source| {
+            let invalid = || RedmanError::InvalidInput(format!("Invalid source {:?}", source));
+            let (ftype, id) = source.split_once(':').ok_or_else(invalid)?;
+            let ftype = Type::from_str(ftype, true).map_err(|_| {
+                RedmanError::InvalidInput(format!("Invalid fetch type {:?}", ftype))
+            })?;
+            Ok((ftype, id.parse().map_err(|_| invalid())?))
+        })
+        .collect()
+}
+
+fn read_csv<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let mut reader =
+        csv::Reader::from_path(path).map_err(|e| RedmanError::file(path)(io::Error::from(e)))?;
+    let records = reader
+        .deserialize()
+        .collect::<Result<Vec<T>, _>>()
+        .map_err(|e| invalid_export(path, e))?;
+    Ok(records)
+}
+
+fn write_csv<T: Serialize>(path: &Path, records: &[T]) -> Result<()> {
+    let file = File::create(path).map_err(RedmanError::file(path))?;
+    let mut writer = csv::Writer::from_writer(file);
+    for record in records {
+        writer
+            .serialize(record)
+            .map_err(|e| RedmanError::file(path)(io::Error::from(e)))?;
+    }
+    writer.flush().map_err(RedmanError::file(path))?;
+    Ok(())
+}
+
+fn invalid_export(path: &Path, e: impl std::fmt::Display) -> RedmanError {
+    RedmanError::InvalidInput(format!("Invalid pool export {}: {}", path.display(), e))
+}
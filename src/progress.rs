@@ -0,0 +1,46 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Enable progress bars for long-running operations (drawn on stderr).
+pub fn set_progress_enabled(enabled: bool) {
+    PROGRESS.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn progress_bar(len: u64, prefix: &str) -> ProgressBar {
+    if !PROGRESS.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(len).with_prefix(prefix.to_string());
+    bar.set_style(
+        ProgressStyle::with_template("{prefix:.bold} [{bar:30}] {pos}/{len} ({per_sec}) {msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar
+}
+
+pub(crate) fn progress_spinner(prefix: &str) -> ProgressBar {
+    if !PROGRESS.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let spinner = ProgressBar::new_spinner().with_prefix(prefix.to_string());
+    spinner.set_style(ProgressStyle::with_template("{spinner} {prefix:.bold} {elapsed}").unwrap());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+pub(crate) fn bytes_progress_bar(total: u64, prefix: &str) -> ProgressBar {
+    let bar = progress_bar(total, prefix);
+    bar.set_style(
+        ProgressStyle::with_template("{prefix:.bold} [{bar:30}] {bytes}/{total_bytes} {msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar
+}
@@ -0,0 +1,172 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use tracing::{info, instrument, warn};
+
+use crate::{
+    clients::{Transmission, info_hash},
+    db::{Database, Download},
+    error::{RedmanError, Result},
+    library::torrent_files,
+};
+
+/// A disagreement between the download records, the torrent files in the
+/// torrent directory and the client.
+#[derive(Debug, Clone)]
+pub enum Inconsistency {
+    /// A torrent file redman has no record of and the client does not have;
+    /// it keeps the torrent from being picked again
+    OrphanedFile { torrent_id: u32, path: PathBuf },
+    /// A recorded download whose torrent file is there but that the client
+    /// does not have
+    MissingAdd { download: Download, path: PathBuf },
+    /// A recorded download with neither a torrent file nor a torrent in the
+    /// client
+    StaleRecord { download: Download },
+    /// A download recorded before info hashes were kept, found in the client
+    /// by the hash of its torrent file
+    UnknownHash { download: Download, hash: String },
+}
+
+impl Inconsistency {
+    pub fn torrent_id(&self) -> u32 {
+        match self {
+            Inconsistency::OrphanedFile { torrent_id, .. } => *torrent_id,
+            Inconsistency::MissingAdd { download, .. }
+            | Inconsistency::StaleRecord { download }
+            | Inconsistency::UnknownHash { download, .. } => download.torrent_id,
+        }
+    }
+
+    /// Short name of the problem.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Inconsistency::OrphanedFile { .. } => "orphaned file",
+            Inconsistency::MissingAdd { .. } => "missing add",
+            Inconsistency::StaleRecord { .. } => "stale record",
+            Inconsistency::UnknownHash { .. } => "unknown hash",
+        }
+    }
+
+    /// What `fix` does about it.
+    pub fn fix_description(&self) -> String {
+        match self {
+            Inconsistency::OrphanedFile { path, .. } => format!("delete {}", path.display()),
+            Inconsistency::MissingAdd { path, .. } => {
+                format!("add {} to the client", path.display())
+            }
+            Inconsistency::StaleRecord { .. } => "mark as removed from the client".to_string(),
+            Inconsistency::UnknownHash { hash, .. } => format!("record info hash {}", hash),
+        }
+    }
+}
+
+/// Compare the download records in `pool_db`, the torrent files in
+/// `torrent_dir` and the torrents in the client. Downloads recorded without
+/// an info hash and without a torrent file cannot be looked up and are
+/// skipped.
+#[instrument(skip_all)]
+pub fn find_inconsistencies(
+    pool_db: &str,
+    torrent_dir: &str,
+    transmission: &Transmission,
+) -> Result<Vec<Inconsistency>> {
+    let db = Database::new(pool_db)?;
+    let files: HashMap<u32, PathBuf> = torrent_files(torrent_dir)?.into_iter().collect();
+    let file_hash = |id: u32| {
+        files
+            .get(&id)
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|bytes| info_hash(&bytes))
+    };
+    let mut found = Vec::new();
+
+    for download in db.active_downloads()? {
+        let id = download.torrent_id;
+        let (hash, recorded) = match &download.info_hash {
+            Some(hash) => (hash.clone(), true),
+            None => match file_hash(id) {
+                Some(hash) => (hash, false),
+                None => continue,
+            },
+        };
+        let in_client = transmission.status(&hash)?.is_some();
+        match (in_client, files.get(&id)) {
+            (true, _) if !recorded => found.push(Inconsistency::UnknownHash { download, hash }),
+            (true, _) => {}
+            (false, Some(path)) => found.push(Inconsistency::MissingAdd {
+                path: path.clone(),
+                download,
+            }),
+            (false, None) => found.push(Inconsistency::StaleRecord { download }),
+        }
+    }
+
+    let recorded = db.downloaded_ids()?;
+    let mut orphans: Vec<_> = files
+        .iter()
+        .filter(|(id, _)| !recorded.contains(id))
+        .collect();
+    orphans.sort();
+    for (&torrent_id, path) in orphans {
+        let in_client = match file_hash(torrent_id) {
+            Some(hash) => transmission.status(&hash)?.is_some(),
+            None => false,
+        };
+        if !in_client {
+            found.push(Inconsistency::OrphanedFile {
+                torrent_id,
+                path: path.clone(),
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// Fix each of `found`, adding missing torrents with `download_dir` as the
+/// download directory. Returns how many were fixed; failures are logged.
+#[instrument(skip_all)]
+pub fn fix(
+    pool_db: &str,
+    transmission: &Transmission,
+    download_dir: &str,
+    found: &[Inconsistency],
+) -> Result<usize> {
+    let db = Database::new(pool_db)?;
+    let mut fixed = 0;
+    for inconsistency in found {
+        let result = match inconsistency {
+            Inconsistency::OrphanedFile { path, .. } => {
+                fs::remove_file(path).map_err(RedmanError::file(path))
+            }
+            Inconsistency::MissingAdd { path, download } => {
+                transmission.add(path, download_dir).and_then(|()| {
+                    match fs::read(path).ok().and_then(|bytes| info_hash(&bytes)) {
+                        Some(hash) => db.set_info_hash(download.torrent_id, &hash),
+                        None => Ok(()),
+                    }
+                })
+            }
+            Inconsistency::StaleRecord { download } => db.mark_removed(download.torrent_id),
+            Inconsistency::UnknownHash { download, hash } => {
+                db.set_info_hash(download.torrent_id, hash)
+            }
+        };
+        match result {
+            Ok(()) => {
+                info!(
+                    id = inconsistency.torrent_id(),
+                    "Fixed {}",
+                    inconsistency.kind()
+                );
+                fixed += 1;
+            }
+            Err(e) => warn!(
+                id = inconsistency.torrent_id(),
+                "Could not fix {}: {}",
+                inconsistency.kind(),
+                e
+            ),
+        }
+    }
+    Ok(fixed)
+}
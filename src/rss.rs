@@ -0,0 +1,552 @@
+use std::collections::{HashMap, HashSet};
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Deserializer};
+use tracing::{debug, info, instrument, warn};
+use url::Url;
+
+use crate::{
+    api::{GroupData, ResolvedData, Type, USER_AGENT},
+    db::{Database, StoreSummary, Torrent, release_type_name},
+    error::{ErrorAction, RedmanError, Result},
+    library::normalize,
+    rules::Rule,
+    shutdown,
+    tracker::TrackerClient,
+};
+
+const MIB: u64 = 1024 * 1024;
+
+/// The tracker's RSS feed of new uploads, polled by `redman rss` and every
+/// `rss_interval` by the daemon, configured in `[rss]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RssConfig {
+    /// A personal feed from the tracker's notifications page, passkey included
+    pub url: String,
+    #[serde(flatten)]
+    pub settings: FeedSettings,
+}
+
+/// What the RSS feed and IRC announces do with new uploads, set in their
+/// sections.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeedSettings {
+    /// For uploads passing the filter that no rule decides on
+    #[serde(default)]
+    pub action: FeedAction,
+    /// Weight of the pooled uploads
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub filter: FeedFilter,
+}
+
+impl Default for FeedSettings {
+    fn default() -> Self {
+        FeedSettings {
+            action: FeedAction::default(),
+            weight: default_weight(),
+            filter: FeedFilter::default(),
+        }
+    }
+}
+
+fn default_weight() -> u32 {
+    10
+}
+
+/// What to do with uploads that pass the filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedAction {
+    /// Store them in the pool for the next watch run
+    #[default]
+    Pool,
+    /// Also add the ones not in the Plex library to the client right away
+    Download,
+    /// Leave them alone; for rules making exceptions
+    Skip,
+}
+
+impl std::fmt::Display for FeedAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FeedAction::Pool => write!(f, "pool"),
+            FeedAction::Download => write!(f, "download"),
+            FeedAction::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Which uploads to act on. Empty lists and unset bounds allow anything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FeedFilter {
+    /// Only artists that already have torrents in the pool
+    pub pool_artists: bool,
+    /// Only uploads that do not count against the ratio
+    pub freeload_only: bool,
+    /// Case-insensitive pattern the artist names must match
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub artist: Option<Regex>,
+    pub min_size_mb: Option<u64>,
+    pub max_size_mb: Option<u64>,
+    /// At least one of these tags
+    pub tags: Vec<String>,
+    /// Release types by name, e.g. "Album" or "EP"
+    pub release_types: Vec<String>,
+    /// e.g. "MP3" or "FLAC"
+    pub formats: Vec<String>,
+    /// e.g. "V0 (VBR)", "320" or "Lossless"
+    pub encodings: Vec<String>,
+    /// e.g. "CD" or "WEB"
+    pub media: Vec<String>,
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Regex>, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+impl FeedFilter {
+    fn tags_match(&self, tags: &[String]) -> bool {
+        self.tags.is_empty()
+            || self
+                .tags
+                .iter()
+                .any(|want| tags.iter().any(|t| t.eq_ignore_ascii_case(want)))
+    }
+
+    /// Whether `upload` passes; `pool_artists` holds the normalized artist
+    /// names of the pool when `pool_artists` is set.
+    pub(crate) fn matches(&self, upload: &Upload, pool_artists: &HashSet<String>) -> bool {
+        let t = &upload.torrent;
+        let allowed = |list: &[String], value: &str| {
+            list.is_empty() || list.iter().any(|v| v.eq_ignore_ascii_case(value))
+        };
+        self.tags_match(&upload.tags)
+            && (!self.freeload_only || upload.is_freeload)
+            && (!self.pool_artists || pool_artists.contains(&normalize(&t.artist_names)))
+            && self
+                .artist
+                .as_ref()
+                .is_none_or(|artist| artist.is_match(&t.artist_names))
+            && self.min_size_mb.is_none_or(|min| t.size >= min * MIB)
+            && self.max_size_mb.is_none_or(|max| t.size <= max * MIB)
+            && allowed(
+                &self.release_types,
+                release_type_name(t.release_type).unwrap_or_default(),
+            )
+            && allowed(&self.formats, &t.format)
+            && allowed(&self.encodings, &t.encoding)
+            && allowed(&self.media, &t.media)
+    }
+}
+
+/// A new upload as looked up on the tracker.
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub torrent: Torrent,
+    /// Tags as the feed or announce gave them
+    pub tags: Vec<String>,
+    pub is_freeload: bool,
+}
+
+/// What happens to `upload`: `None` when the filter of `settings` drops it,
+/// else the action of the first of `rules` it matches, or that of `settings`,
+/// with the name of the deciding rule.
+pub(crate) fn judge<'a>(
+    settings: &FeedSettings,
+    rules: &'a [Rule],
+    upload: &Upload,
+    pool_artists: &HashSet<String>,
+) -> Option<(FeedAction, Option<&'a str>)> {
+    if !settings.filter.matches(upload, pool_artists) {
+        return None;
+    }
+    Some(
+        match rules
+            .iter()
+            .find(|rule| rule.filter.matches(upload, pool_artists))
+        {
+            Some(rule) => (rule.action, Some(rule.name.as_str())),
+            None => (settings.action, None),
+        },
+    )
+}
+
+/// Normalized artist names of the pool, if `settings` or `rules` filter by
+/// them.
+pub(crate) fn pool_artists(
+    db: &Database,
+    settings: &FeedSettings,
+    rules: &[Rule],
+) -> Result<HashSet<String>> {
+    if !settings.filter.pool_artists && !rules.iter().any(|r| r.filter.pool_artists) {
+        return Ok(HashSet::new());
+    }
+    Ok(db
+        .pool_artist_names()?
+        .iter()
+        .map(|a| normalize(a))
+        .collect())
+}
+
+/// Look up the upload of `item` on the tracker. `None` if the tracker does
+/// not know it or the answer lacked parts.
+pub(crate) async fn look_up(api: &impl TrackerClient, item: &FeedItem) -> Result<Option<Upload>> {
+    match api.torrent_info(item.torrent_id).await {
+        Ok(info) => Ok(info.torrent.map(|torrent| Upload {
+            torrent,
+            tags: item.tags.clone(),
+            is_freeload: info.is_freeload,
+        })),
+        Err(e) if e.action() == ErrorAction::Skip => {
+            warn!(id = item.torrent_id, "Skipping: {}", e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Feed {
+    channel: Channel,
+}
+
+#[derive(Debug, Deserialize)]
+struct Channel {
+    #[serde(rename = "item", default)]
+    items: Vec<Item>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Item {
+    title: String,
+    link: String,
+    #[serde(rename = "category", default)]
+    categories: Vec<String>,
+}
+
+/// An upload announced in the feed.
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    pub torrent_id: u32,
+    pub title: String,
+    pub tags: Vec<String>,
+}
+
+/// A new upload that passed the filter, with what happens to it.
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub torrent: Torrent,
+    pub action: FeedAction,
+    /// The rule that decided the action, if any
+    pub rule: Option<String>,
+}
+
+/// What a poll of the feed found.
+#[derive(Debug, Default)]
+pub struct FeedReport {
+    /// Uploads not seen in an earlier poll
+    pub new_items: usize,
+    /// New uploads that passed the filter, except those a rule skips
+    pub matched: Vec<Match>,
+    /// How storing the matches in the pool went
+    pub summary: StoreSummary,
+}
+
+impl std::fmt::Display for FeedReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} new uploads, {} matching ({})",
+            self.new_items,
+            self.matched.len(),
+            self.summary
+        )
+    }
+}
+
+/// The torrent id in a feed link: `torrentid`, or `id` of a download link.
+pub(crate) fn torrent_id(link: &str) -> Option<u32> {
+    let url = Url::parse(link).ok()?;
+    let query: HashMap<_, _> = url.query_pairs().collect();
+    let id = match query.get("torrentid") {
+        Some(id) => id,
+        None if query.get("action").is_some_and(|a| a == "download") => query.get("id")?,
+        None => return None,
+    };
+    id.parse().ok()
+}
+
+/// Tags in a list separated by commas or spaces.
+pub(crate) fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Download and parse the feed at `url`. Items without a torrent id are
+/// left out.
+pub async fn read_feed(url: &str) -> Result<Vec<FeedItem>> {
+    let response = reqwest::Client::new()
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        // The URL carries the passkey
+        .map_err(|e| e.without_url())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(RedmanError::ApiError(format!(
+            "The RSS feed answered {}",
+            status
+        )));
+    }
+    let body = response.text().await?;
+    let feed: Feed = quick_xml::de::from_str(&body)
+        .map_err(|e| RedmanError::ApiError(format!("Could not read the RSS feed: {}", e)))?;
+    Ok(feed
+        .channel
+        .items
+        .into_iter()
+        .filter_map(|item| {
+            Some(FeedItem {
+                torrent_id: torrent_id(&item.link)?,
+                tags: item.categories.iter().flat_map(|c| split_tags(c)).collect(),
+                title: item.title,
+            })
+        })
+        .collect())
+}
+
+/// Look up the uploads in the `rss` feed that earlier polls did not see and
+/// store the ones passing its filter and `rules` in `pool_db` as an rss fetch.
+#[instrument(skip_all)]
+pub async fn poll_feed(
+    api: &impl TrackerClient,
+    pool_db: &str,
+    rss: &RssConfig,
+    rules: &[Rule],
+) -> Result<FeedReport> {
+    let items = read_feed(&rss.url).await?;
+    let report = pool_uploads(api, pool_db, items, &rss.settings, rules, Type::Rss).await?;
+    info!(
+        new = report.new_items,
+        matched = report.matched.len(),
+        "Polled the RSS feed"
+    );
+    Ok(report)
+}
+
+/// Look up the `items` not seen before and store the ones `settings` and
+/// `rules` let through in `pool_db`, as a fetch of `ftype` (rss or irc).
+pub(crate) async fn pool_uploads(
+    api: &impl TrackerClient,
+    pool_db: &str,
+    items: Vec<FeedItem>,
+    settings: &FeedSettings,
+    rules: &[Rule],
+    ftype: Type,
+) -> Result<FeedReport> {
+    let db = Database::new(pool_db)?;
+    let pool_artists = pool_artists(&db, settings, rules)?;
+    let mut report = FeedReport::default();
+    for item in items {
+        if shutdown::requested() {
+            warn!("Lookup interrupted");
+            break;
+        }
+        if db.feed_item_seen(item.torrent_id)? {
+            continue;
+        }
+        report.new_items += 1;
+        if !settings.filter.tags_match(&item.tags) {
+            debug!(id = item.torrent_id, title = %item.title, "Tags do not match");
+            db.mark_feed_item_seen(&item, ftype)?;
+            continue;
+        }
+        // Rate limits end the poll before the item is marked, so the next
+        // poll tries it again. The items marked before it are stored first,
+        // or their matches would be lost
+        let upload = match look_up(api, &item).await {
+            Ok(upload) => upload,
+            Err(e) => {
+                store_matches(&db, &mut report, settings, ftype)?;
+                return Err(e);
+            }
+        };
+        db.mark_feed_item_seen(&item, ftype)?;
+        let Some(upload) = upload else {
+            continue;
+        };
+        let (action, rule) = match judge(settings, rules, &upload, &pool_artists) {
+            Some((FeedAction::Skip, rule)) => {
+                debug!(id = item.torrent_id, title = %item.title, rule, "Skipped by rule");
+                continue;
+            }
+            Some(decision) => decision,
+            None => {
+                debug!(id = item.torrent_id, title = %item.title, "Filtered out");
+                continue;
+            }
+        };
+        info!(
+            id = upload.torrent.id,
+            artist = %upload.torrent.artist_names,
+            album = %upload.torrent.album_name,
+            %action,
+            rule,
+            "Matching upload"
+        );
+        report.matched.push(Match {
+            torrent: upload.torrent,
+            action,
+            rule: rule.map(str::to_string),
+        });
+    }
+    store_matches(&db, &mut report, settings, ftype)?;
+    Ok(report)
+}
+
+/// Store the uploads `report` matched as a fetch of `ftype`.
+fn store_matches(
+    db: &Database,
+    report: &mut FeedReport,
+    settings: &FeedSettings,
+    ftype: Type,
+) -> Result<()> {
+    if !report.matched.is_empty() {
+        report.summary = db.store_data(
+            &GroupData::Resolved(ResolvedData {
+                ftype,
+                id: 0,
+                name: match ftype {
+                    Type::Irc => "IRC announces",
+                    _ => "RSS feed",
+                }
+                .to_string(),
+                source: None,
+                torrents: report.matched.iter().map(|m| m.torrent.clone()).collect(),
+            }),
+            settings.weight,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::torrent;
+
+    fn upload(tags: &[&str], is_freeload: bool) -> Upload {
+        Upload {
+            torrent: torrent(1, "The Artist", "Album", 10),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            is_freeload,
+        }
+    }
+
+    fn filter(toml: &str) -> FeedFilter {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn reads_torrent_ids_of_links() {
+        assert_eq!(
+            torrent_id("https://tracker/torrents.php?id=1&torrentid=2"),
+            Some(2)
+        );
+        assert_eq!(
+            torrent_id("https://tracker/torrents.php?action=download&id=3&authkey=a"),
+            Some(3)
+        );
+        assert_eq!(torrent_id("https://tracker/torrents.php?id=1"), None);
+        assert_eq!(torrent_id("https://tracker/torrents.php?torrentid=x"), None);
+        assert_eq!(torrent_id("not a link"), None);
+    }
+
+    #[test]
+    fn filters_uploads() {
+        let none = HashSet::new();
+        let up = upload(&["Rock", "jazz"], false);
+        assert!(filter("").matches(&up, &none));
+        assert!(filter(r#"tags = ["rock"]"#).matches(&up, &none));
+        assert!(!filter(r#"tags = ["pop"]"#).matches(&up, &none));
+        assert!(!filter("freeload_only = true").matches(&up, &none));
+        assert!(filter("freeload_only = true").matches(&upload(&[], true), &none));
+        assert!(filter(r#"artist = "^the a""#).matches(&up, &none));
+        assert!(!filter(r#"artist = "^artist""#).matches(&up, &none));
+        assert!(filter("min_size_mb = 100\nmax_size_mb = 100").matches(&up, &none));
+        assert!(!filter("min_size_mb = 101").matches(&up, &none));
+        assert!(!filter("max_size_mb = 99").matches(&up, &none));
+        assert!(filter(r#"release_types = ["EP", "album"]"#).matches(&up, &none));
+        assert!(!filter(r#"release_types = ["EP"]"#).matches(&up, &none));
+        assert!(filter(r#"formats = ["mp3"]"#).matches(&up, &none));
+        assert!(!filter(r#"encodings = ["Lossless"]"#).matches(&up, &none));
+        assert!(!filter(r#"media = ["WEB"]"#).matches(&up, &none));
+
+        let pool = HashSet::from(["theartist".to_string()]);
+        assert!(filter("pool_artists = true").matches(&up, &pool));
+        assert!(!filter("pool_artists = true").matches(&up, &none));
+    }
+
+    #[test]
+    fn judges_by_the_first_matching_rule() {
+        let none = HashSet::new();
+        let settings: FeedSettings = toml::from_str(
+            r#"
+            action = "pool"
+            [filter]
+            formats = ["MP3"]
+            "#,
+        )
+        .unwrap();
+        let rules: Vec<Rule> = toml::from_str::<HashMap<String, Vec<Rule>>>(
+            r#"
+            [[rule]]
+            name = "no pop"
+            action = "skip"
+            tags = ["pop"]
+
+            [[rule]]
+            name = "jazz"
+            tags = ["jazz"]
+
+            [[rule]]
+            name = "any jazz"
+            action = "pool"
+            tags = ["jazz"]
+            "#,
+        )
+        .unwrap()
+        .remove("rule")
+        .unwrap();
+
+        assert_eq!(
+            judge(&settings, &rules, &upload(&["jazz", "pop"], false), &none),
+            Some((FeedAction::Skip, Some("no pop")))
+        );
+        assert_eq!(
+            judge(&settings, &rules, &upload(&["jazz"], false), &none),
+            Some((FeedAction::Download, Some("jazz")))
+        );
+        assert_eq!(
+            judge(&settings, &rules, &upload(&["rock"], false), &none),
+            Some((FeedAction::Pool, None))
+        );
+
+        let mut flac = upload(&["jazz"], false);
+        flac.torrent.format = "FLAC".to_string();
+        assert_eq!(judge(&settings, &rules, &flac, &none), None);
+    }
+}
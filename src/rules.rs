@@ -0,0 +1,89 @@
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{
+    api::Type,
+    config::Config,
+    db::Database,
+    error::Result,
+    rss::{FeedAction, FeedFilter, FeedItem, Upload, judge, look_up, pool_artists},
+    shutdown,
+    tracker::TrackerClient,
+};
+
+/// A rule of `[[rules]]`. Uploads of the RSS feed and IRC announces that pass
+/// the filter of their section and match this one's get `action`; the first
+/// matching rule wins, and without one the section's action applies.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default = "default_action")]
+    pub action: FeedAction,
+    #[serde(flatten)]
+    pub filter: FeedFilter,
+}
+
+fn default_action() -> FeedAction {
+    FeedAction::Download
+}
+
+/// What the current filters and rules make of a recent upload.
+#[derive(Debug)]
+pub struct Verdict {
+    pub item: FeedItem,
+    /// rss or irc
+    pub source: Type,
+    /// `None` if the tracker does not know the upload
+    pub upload: Option<Upload>,
+    /// `None` if the filter of the section drops the upload, else the action
+    /// with the deciding rule
+    pub decision: Option<(FeedAction, Option<String>)>,
+}
+
+/// Judge the `number` uploads the RSS feed and IRC announces brought most
+/// recently with the current `[rss]` and `[irc]` filters and `[[rules]]` of
+/// `config`, without pooling or downloading anything.
+#[instrument(skip_all)]
+pub async fn test_rules(
+    api: &impl TrackerClient,
+    pool_db: &str,
+    config: &Config,
+    number: usize,
+) -> Result<Vec<Verdict>> {
+    let db = Database::new(pool_db)?;
+    let rss = config
+        .rss
+        .as_ref()
+        .map(|rss| rss.settings.clone())
+        .unwrap_or_default();
+    let irc = config
+        .irc
+        .as_ref()
+        .map(|irc| irc.settings.clone())
+        .unwrap_or_default();
+    let rss_artists = pool_artists(&db, &rss, &config.rules)?;
+    let irc_artists = pool_artists(&db, &irc, &config.rules)?;
+    let mut verdicts = Vec::new();
+    for (item, source) in db.recent_feed_items(number)? {
+        if shutdown::requested() {
+            warn!("Rules test interrupted");
+            break;
+        }
+        let (settings, pool_artists) = match source {
+            Type::Irc => (&irc, &irc_artists),
+            _ => (&rss, &rss_artists),
+        };
+        let upload = look_up(api, &item).await?;
+        let decision = upload.as_ref().and_then(|upload| {
+            judge(settings, &config.rules, upload, pool_artists)
+                .map(|(action, rule)| (action, rule.map(str::to_string)))
+        });
+        verdicts.push(Verdict {
+            item,
+            source,
+            upload,
+            decision,
+        });
+    }
+    Ok(verdicts)
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use rand::{Rng, seq::SliceRandom};
+use serde::Deserialize;
+
+use crate::db::Torrent;
+
+/// How the pool candidates of a watch run are ordered before taking the first N.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Exhaust the highest weight before touching lower ones, shuffled within a weight
+    #[default]
+    Strict,
+    /// Random picks with a probability proportional to the weight
+    Weighted,
+    /// Oldest pool entries first
+    Fifo,
+    /// Newest pool entries first
+    Lifo,
+    /// Uniformly random, ignoring weights
+    Random,
+}
+
+/// Where pool candidates without a known release year go in a watch run.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownYear {
+    /// Wherever the strategy puts them
+    #[default]
+    Keep,
+    /// After every candidate with a known year
+    Last,
+    /// Never pick them unless prioritized
+    Skip,
+}
+
+/// The torrent of a release worth keeping: CD before WEB, V0 before 320,
+/// MP3 only. Ties go to the first one.
+pub fn preferred_torrent<'a>(
+    torrents: impl IntoIterator<Item = &'a Torrent>,
+) -> Option<&'a Torrent> {
+    torrents
+        .into_iter()
+        .filter_map(|t| {
+            let rank = match (t.media.as_str(), t.format.as_str(), t.encoding.as_str()) {
+                ("CD", "MP3", "V0 (VBR)") => 0,
+                ("WEB", "MP3", "V0 (VBR)") => 1,
+                ("CD", "MP3", "320") => 2,
+                ("WEB", "MP3", "320") => 3,
+                _ => return None,
+            };
+            Some((rank, t))
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, t)| t)
+}
+
+/// The lossless torrent of a release to replace an MP3 copy with: CD before
+/// WEB, 16 bit before 24 bit. Ties go to the first one.
+pub fn preferred_lossless_torrent<'a>(
+    torrents: impl IntoIterator<Item = &'a Torrent>,
+) -> Option<&'a Torrent> {
+    torrents
+        .into_iter()
+        .filter_map(|t| {
+            let rank = match (t.media.as_str(), t.format.as_str(), t.encoding.as_str()) {
+                ("CD", "FLAC", "Lossless") => 0,
+                ("WEB", "FLAC", "Lossless") => 1,
+                ("CD", "FLAC", "24bit Lossless") => 2,
+                ("WEB", "FLAC", "24bit Lossless") => 3,
+                _ => return None,
+            };
+            Some((rank, t))
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, t)| t)
+}
+
+/// Move the torrents without a year behind the others, preserving the order.
+pub fn unknown_years_last(torrents: Vec<Torrent>) -> Vec<Torrent> {
+    let (known, unknown): (Vec<_>, Vec<_>) = torrents.into_iter().partition(|t| t.year.is_some());
+    known.into_iter().chain(unknown).collect()
+}
+
+/// Order `torrents` according to `strategy`, by the selection weight
+/// `weight` gives each.
+pub fn order(
+    mut torrents: Vec<Torrent>,
+    strategy: Strategy,
+    weight: impl Fn(&Torrent) -> f64,
+) -> Vec<Torrent> {
+    match strategy {
+        Strategy::Strict => {
+            let weight = |t: &Torrent| weight(t).round() as u32;
+            torrents.sort_by_cached_key(|t| std::cmp::Reverse(weight(t)));
+            torrents
+                .into_iter()
+                .chunk_by(weight)
+                .into_iter()
+                .flat_map(|(_, group)| {
+                    let mut shuffled: Vec<Torrent> = group.collect();
+                    shuffled.shuffle(&mut rand::rng());
+                    shuffled
+                })
+                .collect()
+        }
+        Strategy::Weighted => {
+            // Efraimidis-Spirakis: sorting on u^(1/w) descending is a weighted
+            // sample without replacement.
+            let mut rng = rand::rng();
+            let mut keyed: Vec<(f64, Torrent)> = torrents
+                .into_iter()
+                .map(|t| {
+                    let w = weight(&t);
+                    let key = if w > 0.0 {
+                        rng.random::<f64>().powf(1.0 / w)
+                    } else {
+                        0.0
+                    };
+                    (key, t)
+                })
+                .collect();
+            keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+            keyed.into_iter().map(|(_, t)| t).collect()
+        }
+        Strategy::Fifo => {
+            torrents.sort_by_key(|t| t.created_at);
+            torrents
+        }
+        Strategy::Lifo => {
+            torrents.sort_by_key(|t| std::cmp::Reverse(t.created_at));
+            torrents
+        }
+        Strategy::Random => {
+            torrents.shuffle(&mut rand::rng());
+            torrents
+        }
+    }
+}
+
+/// Keep at most `max` torrents per artist, preserving the order.
+pub fn cap_per_artist(torrents: Vec<Torrent>, max: usize) -> Vec<Torrent> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    torrents
+        .into_iter()
+        .filter(|t| {
+            let count = counts.entry(t.artist_names.to_lowercase()).or_default();
+            *count += 1;
+            *count <= max
+        })
+        .collect()
+}
+
+/// Keep at most `max` torrents per source fetch and interleave the sources:
+/// the first pick of every fetch comes before the second pick of any, while
+/// the order within a round is preserved. Torrents without a known source
+/// are not capped.
+pub fn cap_per_fetch(
+    torrents: Vec<Torrent>,
+    sources: &HashMap<u32, (u32, u32)>,
+    max: usize,
+) -> Vec<Torrent> {
+    let mut counts: HashMap<(u32, u32), usize> = HashMap::new();
+    let mut rounds: Vec<(usize, Torrent)> = torrents
+        .into_iter()
+        .filter_map(|t| match sources.get(&t.id) {
+            Some(source) => {
+                let count = counts.entry(*source).or_default();
+                *count += 1;
+                (*count <= max).then_some((*count, t))
+            }
+            None => Some((1, t)),
+        })
+        .collect();
+    rounds.sort_by_key(|(round, _)| *round);
+    rounds.into_iter().map(|(_, t)| t).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::db::tests::torrent;
+
+    /// Torrents 1 to 6 with the weights 1, 5, 0, 5, 1, 9, pooled on days
+    /// 3, 1, 6, 2, 5, 4.
+    fn pool() -> Vec<Torrent> {
+        [(1, 3), (5, 1), (0, 6), (5, 2), (1, 5), (9, 4)]
+            .into_iter()
+            .zip(1..)
+            .map(|((weight, day), id)| {
+                let mut t = torrent(id, "Artist", &format!("Album {}", id), weight);
+                t.created_at += TimeDelta::days(day);
+                t
+            })
+            .collect()
+    }
+
+    fn ids(torrents: &[Torrent]) -> Vec<u32> {
+        torrents.iter().map(|t| t.id).collect()
+    }
+
+    #[test]
+    fn orders_strictly_by_weight() {
+        for _ in 0..10 {
+            let ordered = order(pool(), Strategy::Strict, |t| t.weight as f64);
+            let weights: Vec<u32> = ordered.iter().map(|t| t.weight).collect();
+            assert_eq!(weights, [9, 5, 5, 1, 1, 0]);
+        }
+        // Weights are rounded, so close ones share a rank
+        let ordered = order(pool(), Strategy::Strict, |t| {
+            if t.id == 3 { 9.4 } else { t.weight as f64 }
+        });
+        assert!(matches!(ids(&ordered)[..2], [3, 6] | [6, 3]));
+    }
+
+    #[test]
+    fn orders_by_weighted_chance() {
+        let mut first = HashMap::new();
+        for _ in 0..200 {
+            let ordered = order(pool(), Strategy::Weighted, |t| t.weight as f64);
+            assert_eq!(ordered.len(), 6);
+            // Without a weight it is never picked before the others
+            assert_eq!(ordered.last().unwrap().id, 3);
+            *first.entry(ordered[0].id).or_insert(0) += 1;
+        }
+        let count = |id| first.get(&id).copied().unwrap_or(0);
+        assert!(count(6) > count(1));
+    }
+
+    #[test]
+    fn orders_by_age() {
+        assert_eq!(
+            ids(&order(pool(), Strategy::Fifo, |_| 0.0)),
+            [2, 4, 1, 6, 5, 3]
+        );
+        assert_eq!(
+            ids(&order(pool(), Strategy::Lifo, |_| 0.0)),
+            [3, 5, 6, 1, 4, 2]
+        );
+        let mut random = ids(&order(pool(), Strategy::Random, |_| 0.0));
+        random.sort();
+        assert_eq!(random, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn caps_and_interleaves_fetches() {
+        let sources = HashMap::from([
+            (1, (10, 0)),
+            (2, (10, 0)),
+            (3, (10, 0)),
+            (4, (20, 0)),
+            (5, (20, 0)),
+        ]);
+        assert_eq!(ids(&cap_per_fetch(pool(), &sources, 2)), [1, 4, 6, 2, 5]);
+        assert_eq!(ids(&cap_per_fetch(pool(), &sources, 1)), [1, 4, 6]);
+        assert_eq!(
+            ids(&cap_per_fetch(pool(), &HashMap::new(), 1)),
+            [1, 2, 3, 4, 5, 6]
+        );
+        // The same id under another fetch type is another source
+        let sources = HashMap::from([(1, (10, 0)), (2, (10, 1))]);
+        assert_eq!(ids(&cap_per_fetch(pool(), &sources, 1)), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn prefers_v0_from_cd() {
+        let mut torrents = pool();
+        torrents[0].media = "WEB".to_string();
+        torrents[1].encoding = "320".to_string();
+        torrents[2].format = "FLAC".to_string();
+        torrents[2].encoding = "Lossless".to_string();
+        assert_eq!(preferred_torrent(&torrents).map(|t| t.id), Some(4));
+        assert_eq!(preferred_torrent(&torrents[..2]).map(|t| t.id), Some(1));
+        assert_eq!(preferred_torrent(&torrents[1..2]).map(|t| t.id), Some(2));
+        assert!(preferred_torrent(&torrents[2..3]).is_none());
+        assert_eq!(preferred_lossless_torrent(&torrents).map(|t| t.id), Some(3));
+    }
+}
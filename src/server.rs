@@ -0,0 +1,109 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use crate::error::{RedmanError, Result};
+
+/// Time a client gets to send its request and read the answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An answer to a monitoring request.
+pub(crate) struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Response {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    pub fn text(status: &'static str, body: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", body),
+        }
+    }
+}
+
+/// Listen on `addr` for the daemon's monitoring endpoints.
+pub(crate) async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| RedmanError::ConfigError(format!("Could not listen on {}: {}", addr, e)))?;
+    info!("Serving monitoring endpoints on http://{}", addr);
+    Ok(listener)
+}
+
+/// Answer GET requests on `listener` with `route`, which maps a path to a
+/// response or `None` for unknown paths. Each connection gets one response;
+/// this is meant for scrapers and health checks, not browsers.
+pub(crate) async fn serve<F>(listener: TcpListener, route: F)
+where
+    F: Fn(&str) -> Option<Response> + Send + Sync + 'static,
+{
+    let route = Arc::new(route);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Could not accept a monitoring connection: {}", e);
+                continue;
+            }
+        };
+        let route = route.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(TIMEOUT, respond(stream, &*route)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Monitoring request failed: {}", e),
+                Err(_) => debug!("Monitoring request timed out"),
+            }
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    route: &(impl Fn(&str) -> Option<Response> + ?Sized),
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // Headers are not needed, but must be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default();
+    let response = if method != "GET" {
+        Response::text("405 Method Not Allowed", "Method not allowed")
+    } else {
+        route(path).unwrap_or_else(|| Response::text("404 Not Found", "Not found"))
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,46 @@
+use std::sync::OnceLock;
+
+use tokio::sync::watch;
+use tracing::warn;
+
+static SHUTDOWN: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+fn sender() -> &'static watch::Sender<bool> {
+    SHUTDOWN.get_or_init(|| watch::channel(false).0)
+}
+
+/// Listen for Ctrl-C and SIGTERM. The first signal asks running operations to
+/// wind down at the next safe point; a second one exits immediately.
+pub fn install_handler() {
+    tokio::spawn(async {
+        wait_for_signal().await;
+        warn!("Shutdown requested, finishing the current step (press Ctrl-C again to force)");
+        sender().send_replace(true);
+        wait_for_signal().await;
+        std::process::exit(130);
+    });
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut term = signal(SignalKind::terminate()).expect("Could not listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+pub fn requested() -> bool {
+    *sender().borrow()
+}
+
+/// Resolves once a shutdown has been requested.
+pub async fn signal() {
+    let mut rx = sender().subscribe();
+    let _ = rx.wait_for(|requested| *requested).await;
+}
@@ -0,0 +1,63 @@
+use serde::Deserialize;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    db::Torrent,
+    discogs::Discogs,
+    error::{ErrorAction, Result},
+    library::same_release,
+    lidarr::Lidarr,
+    listenbrainz::ListenBrainz,
+    shutdown,
+    spotify::Spotify,
+    tracker::TrackerClient,
+};
+
+/// Services besides the tracker that suggest releases to pool, each in its
+/// own config section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Sources {
+    pub listenbrainz: Option<ListenBrainz>,
+    pub spotify: Option<Spotify>,
+    pub lidarr: Option<Lidarr>,
+    pub discogs: Option<Discogs>,
+}
+
+/// Search the tracker for each of `releases`, given as artist and album name,
+/// and collect the torrents of the groups that match. Releases the search
+/// does not find are logged and left out.
+#[instrument(skip_all, fields(releases = releases.len()))]
+pub async fn resolve_releases(
+    api: &impl TrackerClient,
+    releases: &[(String, String)],
+) -> Result<Vec<Torrent>> {
+    let mut torrents = Vec::new();
+    let mut found = 0;
+    for (artist, album) in releases {
+        if shutdown::requested() {
+            warn!("Search interrupted");
+            break;
+        }
+        let results = match api.search(artist, album).await {
+            Ok(results) => results,
+            Err(e) if e.action() == ErrorAction::Skip => {
+                warn!(artist, album, "Search failed: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let matching: Vec<Torrent> = results
+            .into_iter()
+            .filter(|t| same_release((&t.artist_names, &t.album_name), (artist, album)))
+            .collect();
+        if matching.is_empty() {
+            debug!(artist, album, "Not on the tracker");
+            continue;
+        }
+        found += 1;
+        torrents.extend(matching);
+    }
+    info!(found, "Matched releases on the tracker");
+    Ok(torrents)
+}
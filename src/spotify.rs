@@ -0,0 +1,209 @@
+use itertools::Itertools;
+use reqwest::Client;
+use serde::{Deserialize, de::DeserializeOwned};
+use tracing::{info, instrument};
+use url::Url;
+
+use crate::{
+    api::USER_AGENT,
+    error::{RedmanError, Result},
+};
+
+const SPOTIFY_API_URL: &str = "https://api.spotify.com/v1/";
+const SPOTIFY_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+
+/// A Spotify app whose client credentials read playlists for
+/// `redman fetch --spotify-playlist`, configured in `[spotify]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Spotify {
+    /// From https://developer.spotify.com/dashboard
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+}
+
+fn default_api_url() -> String {
+    SPOTIFY_API_URL.to_string()
+}
+
+fn default_token_url() -> String {
+    SPOTIFY_TOKEN_URL.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error: ApiErrorDetails,
+    error_description: Option<String>,
+}
+
+/// The token endpoint answers with a bare string, the API with an object.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ApiErrorDetails {
+    Message { message: String },
+    Code(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Playlist {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistItems {
+    items: Vec<PlaylistItem>,
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistItem {
+    /// Missing for tracks removed from Spotify
+    track: Option<Track>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Track {
+    /// Missing for podcast episodes
+    album: Option<Album>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Album {
+    name: String,
+    artists: Vec<Artist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Artist {
+    name: String,
+}
+
+/// The playlist id in a Spotify link (`https://open.spotify.com/playlist/<id>`),
+/// URI (`spotify:playlist:<id>`) or the id itself.
+pub fn playlist_id(playlist: &str) -> Result<String> {
+    let id = if let Some(id) = playlist.strip_prefix("spotify:playlist:") {
+        id.to_string()
+    } else if let Ok(url) = Url::parse(playlist) {
+        let mut segments = url.path_segments().into_iter().flatten();
+        match (segments.next(), segments.next()) {
+            (Some("playlist"), Some(id)) => id.to_string(),
+            _ => {
+                return Err(RedmanError::InvalidInput(format!(
+                    "{} is not a Spotify playlist link",
+                    playlist
+                )));
+            }
+        }
+    } else {
+        playlist.to_string()
+    };
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(RedmanError::InvalidInput(format!(
+            "Invalid Spotify playlist id {:?}",
+            id
+        )));
+    }
+    Ok(id)
+}
+
+/// Turn an error response into a readable message.
+fn error_message(status: reqwest::StatusCode, body: &str) -> RedmanError {
+    let message = match serde_json::from_str::<ApiError>(body) {
+        Ok(ApiError {
+            error_description: Some(message),
+            ..
+        })
+        | Ok(ApiError {
+            error: ApiErrorDetails::Message { message } | ApiErrorDetails::Code(message),
+            ..
+        }) => message,
+        Err(_) => status.to_string(),
+    };
+    RedmanError::ServiceError(format!("Spotify error: {}", message))
+}
+
+impl Spotify {
+    async fn token(&self, client: &Client) -> Result<String> {
+        let response = client
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("grant_type", "client_credentials")])
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(error_message(status, &body));
+        }
+        let token: Token = serde_json::from_str(&body).map_err(|e| {
+            RedmanError::ServiceError(format!("Could not read the Spotify token: {}", e))
+        })?;
+        Ok(token.access_token)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, client: &Client, token: &str, url: &str) -> Result<T> {
+        let response = client
+            .get(url)
+            .bearer_auth(token)
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(error_message(status, &body));
+        }
+        serde_json::from_str(&body).map_err(|e| {
+            RedmanError::ServiceError(format!("Could not read the Spotify response: {}", e))
+        })
+    }
+
+    /// The name of playlist `id` and the albums of its tracks, as first album
+    /// artist and album name in playlist order, without repeats.
+    #[instrument(skip(self))]
+    pub async fn playlist_albums(&self, id: &str) -> Result<(String, Vec<(String, String)>)> {
+        let client = Client::new();
+        let token = self.token(&client).await?;
+        let playlist: Playlist = self
+            .get(
+                &client,
+                &token,
+                &format!("{}playlists/{}?fields=name", self.api_url, id),
+            )
+            .await?;
+
+        let mut albums = Vec::new();
+        let mut tracks = 0;
+        let mut next = Some(format!(
+            "{}playlists/{}/tracks?limit=100&fields=next,items(track(album(name,artists(name))))",
+            self.api_url, id
+        ));
+        while let Some(url) = next {
+            let page: PlaylistItems = self.get(&client, &token, &url).await?;
+            tracks += page.items.len();
+            albums.extend(page.items.into_iter().filter_map(|item| {
+                let album = item.track?.album?;
+                let artist = album.artists.into_iter().next()?;
+                Some((artist.name, album.name))
+            }));
+            next = page.next;
+        }
+        let albums: Vec<_> = albums.into_iter().unique().collect();
+        info!(
+            playlist = %playlist.name,
+            tracks,
+            albums = albums.len(),
+            "Read playlist"
+        );
+        Ok((playlist.name, albums))
+    }
+}
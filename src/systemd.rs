@@ -0,0 +1,54 @@
+use std::{env, time::Duration};
+
+use tracing::{debug, warn};
+
+/// Tell systemd about the daemon, e.g. "READY=1" or "STATUS=Idle", when it
+/// runs as a `Type=notify` service. Does nothing otherwise.
+pub(crate) fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    debug!(state, "Notifying systemd");
+    if let Err(e) = send(&path, state) {
+        warn!("Could not notify systemd: {}", e);
+    }
+}
+
+/// The status line `systemctl status` shows for the service.
+pub(crate) fn status(text: &str) {
+    notify(&format!("STATUS={}", text.replace('\n', " ")));
+}
+
+/// How often to tell systemd the daemon is alive: half the `WatchdogSec=` of
+/// the service, or `None` if it has no watchdog.
+pub(crate) fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2)).filter(|interval| !interval.is_zero())
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    // Sockets starting with "@" live in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+        let addr = SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_path: &std::ffi::OsStr, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,642 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    future::Future,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::Client;
+use serde::{Deserialize, de::DeserializeOwned};
+use tracing::{debug, instrument};
+use url::form_urlencoded;
+
+use crate::{
+    api::{
+        ArtistData, CollageCategory, CollageData, CollagePage, CollageSubscriptions,
+        CollageSummary, GroupDetails, RawResponse, SearchResults, TorrentDetails, Type, USER_AGENT,
+        content_type, parse_json,
+    },
+    db::Torrent,
+    error::{RedmanError, Result},
+    metrics::{self, Counter},
+    progress::progress_spinner,
+};
+
+/// The tracker requests redman makes. Sites built on Gazelle with slightly
+/// different endpoints or payloads are supported by another implementation.
+pub trait TrackerClient: Sync {
+    /// Whether requests may only be served from a cache.
+    fn is_offline(&self) -> bool;
+
+    /// How many collages and artists may be fetched at once, all of them
+    /// still under one rate limit.
+    fn jobs(&self) -> usize {
+        1
+    }
+
+    fn fetch_collage(&self, id: u32) -> impl Future<Output = Result<CollageData>> + Send;
+
+    fn fetch_artist(&self, id: u32) -> impl Future<Output = Result<ArtistData>> + Send;
+
+    /// The artist called `name`, for when the id is not known.
+    fn fetch_artist_by_name(&self, name: &str) -> impl Future<Output = Result<ArtistData>> + Send;
+
+    fn torrent_info(&self, id: u32) -> impl Future<Output = Result<TorrentInfo>> + Send;
+
+    /// Torrents of the groups matching `artist` and `album` in a search.
+    fn search(
+        &self,
+        artist: &str,
+        album: &str,
+    ) -> impl Future<Output = Result<Vec<Torrent>>> + Send;
+
+    /// Page `page` of the collages in `category`, the biggest first.
+    fn browse_collages(
+        &self,
+        category: CollageCategory,
+        page: u32,
+    ) -> impl Future<Output = Result<CollagePage>> + Send;
+
+    /// The collages the user subscribed to on the site.
+    fn subscribed_collages(&self) -> impl Future<Output = Result<Vec<CollageSummary>>> + Send;
+
+    /// Every torrent of the group `id`.
+    fn fetch_torrent_group(&self, id: u32) -> impl Future<Output = Result<Vec<Torrent>>> + Send;
+
+    /// Request the .torrent file, spending a freeleech token if `use_token`.
+    fn download(
+        &self,
+        id: u32,
+        use_token: bool,
+    ) -> impl Future<Output = Result<reqwest::Response>> + Send;
+}
+
+/// Details of a single torrent beyond what collage and artist pages list.
+#[derive(Debug)]
+pub struct TorrentInfo {
+    /// Downloading does not count against the ratio
+    pub is_freeload: bool,
+    /// The torrent with its group, `None` if the answer lacked parts of it
+    pub torrent: Option<Torrent>,
+}
+
+/// Differences between Gazelle sites in how the API is called and answers.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compat {
+    /// The API key is sent as is and torrents report `isFreeload`
+    #[default]
+    Redacted,
+    /// The API key is sent as `token <KEY>` and torrents report
+    /// `freeTorrent`, where both freeleech and neutral leech count as freeload
+    Orpheus,
+}
+
+#[derive(Debug, Deserialize)]
+struct RedactedTorrent {
+    #[serde(rename = "isFreeload")]
+    is_freeload: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct OrpheusTorrent {
+    #[serde(rename = "freeTorrent", deserialize_with = "free_torrent")]
+    free_torrent: bool,
+}
+
+/// `freeTorrent` is a bool, or "0" for normal, "1" for freeleech and "2" for
+/// neutral leech depending on the site version.
+fn free_torrent<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Bool(bool),
+        Number(u8),
+        String(String),
+    }
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(b) => b,
+        Value::Number(n) => n != 0,
+        Value::String(s) => !matches!(s.as_str(), "" | "0" | "false"),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse<T> {
+    status: String,
+    response: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct TorrentResponse<T> {
+    torrent: T,
+}
+
+/// Sends the HTTP requests of a [`GazelleClient`]. A reqwest [`Client`] is used
+/// by default; another implementation can answer requests in-process, e.g. to
+/// exercise response parsing and retries without a tracker.
+pub trait Transport: Sync {
+    /// GET `url` with the given `Authorization` header.
+    fn get(
+        &self,
+        url: &str,
+        authorization: &str,
+    ) -> impl Future<Output = Result<reqwest::Response>> + Send;
+}
+
+impl Transport for Client {
+    async fn get(&self, url: &str, authorization: &str) -> Result<reqwest::Response> {
+        Ok(self
+            .get(url)
+            .header("Authorization", authorization)
+            .send()
+            .await?)
+    }
+}
+
+/// [`TrackerClient`] for Gazelle sites, talking to `ajax.php` with an API key.
+pub struct GazelleClient<T = Client> {
+    transport: T,
+    base_url: String,
+    api_key: String,
+    dump_dir: Option<PathBuf>,
+    cache: Option<ResponseCache>,
+    offline: bool,
+    rate_limit: Option<RateLimit>,
+    jobs: usize,
+    compat: Compat,
+}
+
+/// Requests in every [`DEFAULT_RATE_WINDOW`] when nothing else is configured,
+/// the limit of Redacted.
+pub const DEFAULT_RATE_LIMIT: usize = 10;
+pub const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// At most `requests` tracker requests in any `window`.
+struct RateLimit {
+    requests: usize,
+    window: Duration,
+    sent: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimit {
+    fn new(requests: usize, window: Duration) -> Self {
+        RateLimit {
+            requests,
+            window,
+            sent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Wait until another request fits in the window, then record it.
+    async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut sent = self.sent.lock().unwrap();
+                let now = Instant::now();
+                while sent
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) >= self.window)
+                {
+                    sent.pop_front();
+                }
+                if sent.len() < self.requests {
+                    sent.push_back(now);
+                    return;
+                }
+                self.window - now.duration_since(sent[0])
+            };
+            debug!(?wait, "Waiting for the tracker rate limit");
+            metrics::add(Counter::RateLimitWaits, 1);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// On-disk cache of collage and artist responses, keyed by type and id.
+struct ResponseCache {
+    dir: PathBuf,
+    max_age: Duration,
+}
+
+impl ResponseCache {
+    fn path(&self, ftype: Type, id: u32) -> PathBuf {
+        self.dir.join(format!("{}-{}.json", ftype, id))
+    }
+
+    fn load(&self, ftype: Type, id: u32, ignore_age: bool) -> Option<RawResponse> {
+        let path = self.path(ftype, id);
+        let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+        if age > self.max_age && !ignore_age {
+            return None;
+        }
+        Some(RawResponse {
+            status: reqwest::StatusCode::OK,
+            content_type: "application/json".to_string(),
+            body: fs::read_to_string(path).ok()?,
+        })
+    }
+
+    fn store(&self, ftype: Type, id: u32, body: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir).map_err(RedmanError::file(&self.dir))?;
+        let path = self.path(ftype, id);
+        fs::write(&path, body).map_err(RedmanError::file(path))?;
+        Ok(())
+    }
+}
+
+impl GazelleClient {
+    pub fn new(base_url: &str, api_key: &str, user_agent: Option<&str>) -> Result<Self> {
+        let client = Client::builder()
+            .user_agent(user_agent.unwrap_or(USER_AGENT))
+            .build()?;
+        Ok(Self {
+            transport: client,
+            base_url: base_url.to_string(),
+            api_key: api_key.to_string(),
+            dump_dir: None,
+            cache: None,
+            offline: false,
+            rate_limit: None,
+            jobs: 1,
+            compat: Compat::default(),
+        })
+    }
+}
+
+impl<T: Transport> GazelleClient<T> {
+    /// Send requests through `transport` instead of the default client. The
+    /// User-Agent given to [`GazelleClient::new`] belongs to that client, so a
+    /// transport talking to a tracker has to set its own.
+    pub fn with_transport<U: Transport>(self, transport: U) -> GazelleClient<U> {
+        GazelleClient {
+            transport,
+            base_url: self.base_url,
+            api_key: self.api_key,
+            dump_dir: self.dump_dir,
+            cache: self.cache,
+            offline: self.offline,
+            rate_limit: self.rate_limit,
+            jobs: self.jobs,
+            compat: self.compat,
+        }
+    }
+
+    /// Talk to the API the way `compat` expects.
+    pub fn with_compat(mut self, compat: Compat) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Send at most `requests` requests in any `window`, waiting as needed.
+    /// 0 requests disables the limit, unless several jobs run at once.
+    pub fn with_rate_limit(mut self, requests: usize, window: Duration) -> Self {
+        self.rate_limit = (requests > 0).then(|| RateLimit::new(requests, window));
+        self.limit_jobs()
+    }
+
+    /// Fetch up to `jobs` collages and artists at once. Above 1 they are
+    /// always rate limited, by default to [`DEFAULT_RATE_LIMIT`] requests in
+    /// [`DEFAULT_RATE_WINDOW`].
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self.limit_jobs()
+    }
+
+    fn limit_jobs(mut self) -> Self {
+        if self.jobs > 1 && self.rate_limit.is_none() {
+            self.rate_limit = Some(RateLimit::new(DEFAULT_RATE_LIMIT, DEFAULT_RATE_WINDOW));
+        }
+        self
+    }
+
+    /// Serve requests from the response cache only, regardless of its age.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Reuse collage and artist responses stored in `dir` for up to `max_age`.
+    pub fn with_cache(mut self, dir: Option<PathBuf>, max_age: Duration) -> Self {
+        self.cache = dir.map(|dir| ResponseCache { dir, max_age });
+        self
+    }
+
+    /// Write every raw JSON response to `dir`, with the API key redacted.
+    pub fn with_dump_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.dump_dir = dir;
+        self
+    }
+
+    /// A collage or artist response, served from the cache when it is fresh
+    /// enough and stored there otherwise.
+    async fn fetch_group<R: DeserializeOwned>(
+        &self,
+        ftype: Type,
+        id: u32,
+        query: &str,
+    ) -> Result<R> {
+        debug!(url = %self.url(query), "Fetching");
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|c| c.load(ftype, id, self.offline));
+        if self.offline && cached.is_none() {
+            return Err(RedmanError::Offline(format!(
+                "No cached response for {} {} available in offline mode",
+                ftype, id
+            )));
+        }
+        let from_cache = cached.is_some();
+        let response = match cached {
+            Some(response) => response,
+            None => {
+                let spinner = progress_spinner(&format!("Fetching {} {}", ftype, id));
+                let response = self.get_raw(query).await;
+                spinner.finish_and_clear();
+                response?
+            }
+        };
+
+        if from_cache {
+            debug!("Using cached response");
+        } else {
+            debug!(status = %response.status, "Received response");
+        }
+
+        let api_response = parse_json::<ApiResponse<R>>(&response)?;
+        if api_response.status != "success" {
+            return Err(RedmanError::ApiError(format!(
+                "API returned error status: {}",
+                api_response.status
+            )));
+        }
+        if !from_cache && let Some(cache) = &self.cache {
+            cache.store(ftype, id, &response.body)?;
+        }
+        Ok(api_response.response)
+    }
+
+    fn url(&self, query: &str) -> String {
+        format!("{}ajax.php?{}", self.base_url, query)
+    }
+
+    async fn get(&self, query: &str) -> Result<reqwest::Response> {
+        if self.offline {
+            return Err(RedmanError::Offline(format!(
+                "Network access is disabled in offline mode: {}",
+                query
+            )));
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.acquire().await;
+        }
+        let authorization = match self.compat {
+            Compat::Redacted => self.api_key.clone(),
+            Compat::Orpheus => format!("token {}", self.api_key),
+        };
+        metrics::add(Counter::ApiRequests, 1);
+        self.transport.get(&self.url(query), &authorization).await
+    }
+
+    async fn get_raw(&self, query: &str) -> Result<RawResponse> {
+        let response = self.get(query).await?;
+        let raw = RawResponse {
+            status: response.status(),
+            content_type: content_type(&response),
+            body: response.text().await?,
+        };
+        self.dump(query, &raw.body)?;
+        Ok(raw)
+    }
+
+    fn dump(&self, query: &str, body: &str) -> Result<()> {
+        let Some(dir) = &self.dump_dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir).map_err(RedmanError::file(dir))?;
+        let name = query
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+        let path = dir.join(format!("{}-{}.json", stamp, name));
+        // The offline client has no key, and replacing an empty one would
+        // put the marker between every character
+        let body = if self.api_key.is_empty() {
+            body.to_string()
+        } else {
+            body.replace(&self.api_key, "<redacted>")
+        };
+        fs::write(&path, body).map_err(RedmanError::file(path))?;
+        Ok(())
+    }
+}
+
+impl<T: Transport> TrackerClient for GazelleClient<T> {
+    fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    #[instrument(skip(self))]
+    async fn fetch_collage(&self, id: u32) -> Result<CollageData> {
+        self.fetch_group(Type::Collage, id, &format!("action=collage&id={}", id))
+            .await
+    }
+
+    #[instrument(skip(self))]
+    async fn fetch_artist(&self, id: u32) -> Result<ArtistData> {
+        let query = format!("action=artist&id={}&artistreleases=1", id);
+        self.fetch_group(Type::Artist, id, &query).await
+    }
+
+    #[instrument(skip(self))]
+    async fn fetch_artist_by_name(&self, name: &str) -> Result<ArtistData> {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("action", "artist")
+            .append_pair("artistname", name)
+            .append_pair("artistreleases", "1")
+            .finish();
+        let response = self.get_raw(&query).await?;
+        Ok(parse_json::<ApiResponse<ArtistData>>(&response)?.response)
+    }
+
+    async fn torrent_info(&self, id: u32) -> Result<TorrentInfo> {
+        let response = self.get_raw(&format!("action=torrent&id={}", id)).await?;
+        let is_freeload = match self.compat {
+            Compat::Redacted => {
+                parse_json::<ApiResponse<TorrentResponse<RedactedTorrent>>>(&response)?
+                    .response
+                    .torrent
+                    .is_freeload
+            }
+            Compat::Orpheus => {
+                parse_json::<ApiResponse<TorrentResponse<OrpheusTorrent>>>(&response)?
+                    .response
+                    .torrent
+                    .free_torrent
+            }
+        };
+        let torrent = parse_json::<ApiResponse<TorrentDetails>>(&response)
+            .ok()
+            .map(|r| r.response.into_torrent());
+        Ok(TorrentInfo {
+            is_freeload,
+            torrent,
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn fetch_torrent_group(&self, id: u32) -> Result<Vec<Torrent>> {
+        let response = self
+            .get_raw(&format!("action=torrentgroup&id={}", id))
+            .await?;
+        Ok(parse_json::<ApiResponse<GroupDetails>>(&response)?
+            .response
+            .into_torrents())
+    }
+
+    #[instrument(skip(self))]
+    async fn search(&self, artist: &str, album: &str) -> Result<Vec<Torrent>> {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("action", "browse")
+            .append_pair("artistname", artist)
+            .append_pair("groupname", album)
+            .finish();
+        let response = self.get_raw(&query).await?;
+        Ok(parse_json::<ApiResponse<SearchResults>>(&response)?
+            .response
+            .into_torrents())
+    }
+
+    #[instrument(skip(self))]
+    async fn browse_collages(&self, category: CollageCategory, page: u32) -> Result<CollagePage> {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("action", "collages")
+            .append_pair(&format!("cats[{}]", category.code()), "1")
+            .append_pair("order_by", "Torrents")
+            .append_pair("order_way", "Descending")
+            .append_pair("page", &page.to_string())
+            .finish();
+        let response = self.get_raw(&query).await?;
+        Ok(parse_json::<ApiResponse<CollagePage>>(&response)?.response)
+    }
+
+    #[instrument(skip(self))]
+    async fn subscribed_collages(&self) -> Result<Vec<CollageSummary>> {
+        let response = self.get_raw("action=subscriptions&type=collages").await?;
+        Ok(parse_json::<ApiResponse<CollageSubscriptions>>(&response)?
+            .response
+            .collages)
+    }
+
+    async fn download(&self, id: u32, use_token: bool) -> Result<reqwest::Response> {
+        let t = if use_token { 1 } else { 0 };
+        self.get(&format!("action=download&id={}&usetoken={}", id, t))
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::error::ErrorAction;
+
+    /// Answers requests in-process with `responses` in turn, as status and
+    /// JSON body.
+    pub(crate) struct Replay {
+        responses: Mutex<VecDeque<(u16, String)>>,
+        pub(crate) urls: Mutex<Vec<String>>,
+    }
+
+    impl Replay {
+        pub(crate) fn client(responses: &[(u16, &str)]) -> GazelleClient<Replay> {
+            let replay = Replay {
+                responses: Mutex::new(
+                    responses
+                        .iter()
+                        .map(|(status, body)| (*status, body.to_string()))
+                        .collect(),
+                ),
+                urls: Mutex::new(Vec::new()),
+            };
+            GazelleClient::new("https://tracker.test/", "key", None)
+                .unwrap()
+                .with_transport(replay)
+        }
+    }
+
+    impl<T> GazelleClient<T> {
+        pub(crate) fn transport(&self) -> &T {
+            &self.transport
+        }
+    }
+
+    impl Transport for Replay {
+        async fn get(&self, url: &str, _authorization: &str) -> Result<reqwest::Response> {
+            self.urls.lock().unwrap().push(url.to_string());
+            let (status, body) = self
+                .responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("no response left");
+            let response = http::Response::builder()
+                .status(status)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap();
+            Ok(response.into())
+        }
+    }
+
+    fn failure(error: &str) -> String {
+        format!(r#"{{"status": "failure", "error": "{}"}}"#, error)
+    }
+
+    #[tokio::test]
+    async fn maps_tracker_errors() {
+        let api = Replay::client(&[
+            (200, &failure("bad id parameter")),
+            (200, &failure("Rate limit exceeded")),
+            (429, ""),
+            (401, ""),
+            (200, &failure("You do not have any freeleech tokens left")),
+        ]);
+        assert!(matches!(
+            api.fetch_torrent_group(1).await,
+            Err(RedmanError::BadId(_))
+        ));
+        for _ in 0..2 {
+            let e = api.fetch_torrent_group(1).await.unwrap_err();
+            assert!(matches!(e, RedmanError::RateLimited));
+            assert_eq!(e.action(), ErrorAction::Retry);
+        }
+        assert!(matches!(
+            api.fetch_torrent_group(1).await,
+            Err(RedmanError::BadApiKey)
+        ));
+        assert!(matches!(
+            api.torrent_info(1).await,
+            Err(RedmanError::NoFreeleechTokens(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_rate_limit() {
+        let window = Duration::from_millis(200);
+        let api = Replay::client(&[(200, ""), (200, ""), (200, "")]).with_rate_limit(2, window);
+        let start = Instant::now();
+        for _ in 0..3 {
+            api.get("action=index").await.unwrap();
+        }
+        assert!(start.elapsed() >= window);
+        assert_eq!(api.transport.urls.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,302 @@
+use std::{collections::HashMap, path::Path};
+
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, TableState},
+};
+
+use crate::{
+    covers::cached_cover,
+    db::{Database, Fetch, Mark, PoolFilter, PoolSort, Torrent, format_size, format_year},
+    error::Result,
+};
+
+/// What the user asked for when leaving the browser.
+pub enum Outcome {
+    Quit,
+    /// Run a watch with the configured settings
+    Watch,
+}
+
+/// Rows loaded at once; narrow the search to see others.
+const MAX_ROWS: usize = 5000;
+
+const SORTS: [PoolSort; 6] = [
+    PoolSort::Weight,
+    PoolSort::Artist,
+    PoolSort::Album,
+    PoolSort::Year,
+    PoolSort::Size,
+    PoolSort::Added,
+];
+
+const HELP: &str =
+    "↑↓ move  / search  s sort  r reverse  x skip  o own  p prioritize  u unmark  w watch  q quit";
+
+/// Browse the pool interactively. `can_watch` tells whether a `[watch]`
+/// configuration is available to start a run from the browser; covers already
+/// saved in `covers_dir` are shown by path instead of URL.
+pub fn run(db: &Database, can_watch: bool, covers_dir: Option<&Path>) -> Result<Outcome> {
+    let mut terminal = ratatui::init();
+    let result = App::new(db, can_watch, covers_dir).and_then(|mut app| app.run(&mut terminal));
+    ratatui::restore();
+    result
+}
+
+struct App<'a> {
+    db: &'a Database,
+    can_watch: bool,
+    covers_dir: Option<&'a Path>,
+    torrents: Vec<Torrent>,
+    total: usize,
+    marks: HashMap<u32, Mark>,
+    /// Fetches that contributed the selected torrent
+    sources: Vec<Fetch>,
+    table: TableState,
+    sort: PoolSort,
+    reverse: bool,
+    query: String,
+    editing: bool,
+    message: Option<String>,
+}
+
+impl<'a> App<'a> {
+    fn new(db: &'a Database, can_watch: bool, covers_dir: Option<&'a Path>) -> Result<Self> {
+        let mut app = App {
+            db,
+            can_watch,
+            covers_dir,
+            torrents: Vec::new(),
+            total: 0,
+            marks: HashMap::new(),
+            sources: Vec::new(),
+            table: TableState::default().with_selected(0),
+            sort: PoolSort::Weight,
+            reverse: false,
+            query: String::new(),
+            editing: false,
+            message: None,
+        };
+        app.reload()?;
+        Ok(app)
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<Outcome> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && let Some(outcome) = self.handle_key(key)?
+            {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let filter = PoolFilter {
+            query: Some(self.query.clone()),
+            ..PoolFilter::default()
+        };
+        (self.torrents, self.total) =
+            self.db
+                .list_torrents(&filter, self.sort, self.reverse, MAX_ROWS, 0)?;
+        self.marks = self.db.get_marks()?;
+        let selected = self
+            .table
+            .selected()
+            .unwrap_or(0)
+            .min(self.torrents.len().saturating_sub(1));
+        self.table.select(Some(selected));
+        self.load_sources()
+    }
+
+    fn load_sources(&mut self) -> Result<()> {
+        self.sources = match self.selected() {
+            Some(t) => self.db.torrent_sources_of(t.id)?,
+            None => Vec::new(),
+        };
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&Torrent> {
+        self.table.selected().and_then(|i| self.torrents.get(i))
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Result<Option<Outcome>> {
+        self.message = None;
+        if self.editing {
+            match key.code {
+                KeyCode::Enter => self.editing = false,
+                KeyCode::Esc => {
+                    self.editing = false;
+                    self.query.clear();
+                }
+                KeyCode::Backspace => {
+                    self.query.pop();
+                }
+                KeyCode::Char(c) => self.query.push(c),
+                _ => return Ok(None),
+            }
+            self.table.select(Some(0));
+            self.reload()?;
+            return Ok(None);
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(Outcome::Quit)),
+            KeyCode::Char('w') if self.can_watch => return Ok(Some(Outcome::Watch)),
+            KeyCode::Char('w') => {
+                self.message =
+                    Some("Start with --config and a [watch] section to run a watch".into())
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1)?,
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1)?,
+            KeyCode::PageDown => self.move_by(20)?,
+            KeyCode::PageUp => self.move_by(-20)?,
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN)?,
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX)?,
+            KeyCode::Char('/') => self.editing = true,
+            KeyCode::Char('s') => {
+                let next = SORTS.iter().position(|s| *s == self.sort).unwrap_or(0) + 1;
+                self.sort = SORTS[next % SORTS.len()];
+                self.reload()?;
+            }
+            KeyCode::Char('r') => {
+                self.reverse = !self.reverse;
+                self.reload()?;
+            }
+            KeyCode::Char('x') => self.toggle_mark(Mark::Skip)?,
+            KeyCode::Char('o') => self.toggle_mark(Mark::Own)?,
+            KeyCode::Char('p') => self.toggle_mark(Mark::Prioritize)?,
+            KeyCode::Char('u') => self.set_mark(None)?,
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn move_by(&mut self, delta: isize) -> Result<()> {
+        let last = self.torrents.len().saturating_sub(1);
+        let current = self.table.selected().unwrap_or(0);
+        let next = current.saturating_add_signed(delta).min(last);
+        self.table.select(Some(next));
+        self.load_sources()
+    }
+
+    fn toggle_mark(&mut self, mark: Mark) -> Result<()> {
+        let current = self.selected().and_then(|t| self.marks.get(&t.id));
+        let mark = (current != Some(&mark)).then_some(mark);
+        self.set_mark(mark)
+    }
+
+    fn set_mark(&mut self, mark: Option<Mark>) -> Result<()> {
+        let Some(id) = self.selected().map(|t| t.id) else {
+            return Ok(());
+        };
+        self.db.set_mark(id, mark)?;
+        match mark {
+            Some(mark) => self.marks.insert(id, mark),
+            None => self.marks.remove(&id),
+        };
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [search, table, sources, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(6),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let title = format!(
+            " redman: {} of {} torrents, sorted by {:?}{} ",
+            self.torrents.len(),
+            self.total,
+            self.sort,
+            if self.reverse { " (reversed)" } else { "" }
+        );
+        let search_style = if self.editing {
+            Style::new().fg(Color::Yellow)
+        } else {
+            Style::new()
+        };
+        frame.render_widget(
+            Paragraph::new(format!("Search: {}", self.query))
+                .style(search_style)
+                .block(Block::bordered().title(title)),
+            search,
+        );
+
+        let rows = self.torrents.iter().map(|t| {
+            let mark = self.marks.get(&t.id);
+            let style = match mark {
+                Some(Mark::Prioritize) => Style::new().fg(Color::Green),
+                Some(Mark::Skip | Mark::Own) => Style::new().fg(Color::DarkGray),
+                None => Style::new(),
+            };
+            Row::new(vec![
+                t.id.to_string(),
+                t.artist_names.clone(),
+                t.album_name.clone(),
+                format_year(t.year),
+                t.encoding.clone(),
+                format_size(t.size),
+                t.weight.to_string(),
+                mark.map(|m| m.as_str()).unwrap_or_default().to_string(),
+            ])
+            .style(style)
+        });
+        let widths = [
+            Constraint::Length(9),
+            Constraint::Fill(2),
+            Constraint::Fill(3),
+            Constraint::Length(4),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Length(10),
+        ];
+        let header = Row::new([
+            "ID", "Artist", "Album", "Year", "Encoding", "Size", "Weight", "Mark",
+        ])
+        .add_modifier(Modifier::BOLD);
+        frame.render_stateful_widget(
+            Table::new(rows, widths)
+                .header(header)
+                .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+                .block(Block::bordered()),
+            table,
+            &mut self.table,
+        );
+
+        let cover = self.selected().and_then(|t| {
+            self.covers_dir
+                .and_then(|dir| cached_cover(dir, t))
+                .map(|path| path.display().to_string())
+                .or_else(|| t.cover_url.clone())
+        });
+        let lines: Vec<Line> = cover
+            .map(|c| Line::from(format!("Cover: {}", c)).dark_gray())
+            .into_iter()
+            .chain(self.sources.iter().map(|f| {
+                Line::from(format!(
+                    "{} {} {} (weight {}, {} torrents)",
+                    f.ftype, f.id, f.name, f.weight, f.torrents
+                ))
+            }))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Sources ")),
+            sources,
+        );
+
+        let footer_text = self.message.as_deref().unwrap_or(HELP);
+        frame.render_widget(Paragraph::new(footer_text).dark_gray(), footer);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    db::{Database, Torrent},
+    error::{ErrorAction, Result},
+    library::{lossy_albums, same_release},
+    selection::preferred_lossless_torrent,
+    shutdown,
+    tracker::TrackerClient,
+};
+
+/// An album Plex only has as MP3 and the lossless torrent to replace it with.
+#[derive(Debug, Clone)]
+pub struct UpgradeCandidate {
+    /// Plex's id of the MP3 album
+    pub plex_album_id: i64,
+    /// Artist and album as Plex names them
+    pub artist: String,
+    pub album: String,
+    /// Folder of the MP3 files
+    pub old_path: Option<PathBuf>,
+    pub torrent: Torrent,
+}
+
+/// Search the tracker for lossless copies of the MP3-only albums in the Plex
+/// database at `plex_db`, stopping after `max` are found. Albums with an
+/// upgrade recorded in `pool_db` are left out, as are torrents redman already
+/// added.
+#[instrument(skip(api))]
+pub async fn find_upgrades(
+    api: &impl TrackerClient,
+    pool_db: &str,
+    plex_db: &str,
+    max: usize,
+) -> Result<Vec<UpgradeCandidate>> {
+    let db = Database::new(pool_db)?;
+    let upgraded = db.upgraded_album_ids()?;
+    let added: HashSet<u32> = db.added_torrent_ids()?;
+    let albums = lossy_albums(plex_db)?;
+    info!(albums = albums.len(), "Found MP3-only albums in Plex");
+
+    let mut candidates = Vec::new();
+    for album in albums.into_iter().filter(|a| !upgraded.contains(&a.id)) {
+        if candidates.len() >= max {
+            break;
+        }
+        if shutdown::requested() {
+            warn!("Upgrade search interrupted");
+            break;
+        }
+        let torrents = match api.search(&album.artist, &album.name).await {
+            Ok(torrents) => torrents,
+            Err(e) if e.action() == ErrorAction::Skip => {
+                warn!(artist = %album.artist, album = %album.name, "Search failed: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let matching = torrents.iter().filter(|t| {
+            same_release(
+                (&t.artist_names, &t.album_name),
+                (&album.artist, &album.name),
+            )
+        });
+        let Some(torrent) = preferred_lossless_torrent(matching) else {
+            debug!(artist = %album.artist, album = %album.name, "No lossless torrent");
+            continue;
+        };
+        if added.contains(&torrent.id) {
+            debug!(id = torrent.id, "Already added");
+            continue;
+        }
+        info!(
+            id = torrent.id,
+            artist = %album.artist,
+            album = %album.name,
+            "Found {} {}",
+            torrent.media,
+            torrent.encoding
+        );
+        candidates.push(UpgradeCandidate {
+            plex_album_id: album.id,
+            artist: album.artist,
+            album: album.name,
+            old_path: album.path,
+            torrent: torrent.clone(),
+        });
+    }
+    Ok(candidates)
+}
+
+/// Record which MP3 album each of `added` replaces, for the candidates that
+/// made it into the client. Returns how many were recorded.
+#[instrument(skip_all)]
+pub fn record_upgrades(
+    pool_db: &str,
+    candidates: &[UpgradeCandidate],
+    added: &[Torrent],
+) -> Result<usize> {
+    let db = Database::new(pool_db)?;
+    let added: HashSet<u32> = added.iter().map(|t| t.id).collect();
+    let mut recorded = 0;
+    for c in candidates.iter().filter(|c| added.contains(&c.torrent.id)) {
+        db.record_upgrade(
+            c.torrent.id,
+            c.plex_album_id,
+            &c.artist,
+            &c.album,
+            c.old_path.as_deref().and_then(Path::to_str),
+        )?;
+        recorded += 1;
+    }
+    Ok(recorded)
+}
@@ -0,0 +1,724 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, remove_file},
+    io::copy,
+    path::PathBuf,
+    sync::LazyLock,
+    time::Duration,
+};
+
+use regex::Regex;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    api::{
+        ApiStatus, MAX_RATE_LIMIT_RETRIES, RATE_LIMIT_BACKOFF, content_type, retry_rate_limited,
+        snippet,
+    },
+    clients::{Transmission, info_hash},
+    covers::download_cover,
+    db::{Database, JournalState, Mark, Torrent, get_pool_torrents},
+    error::{ErrorAction, RedmanError, Result},
+    formula::{Factors, Formula},
+    hooks::{self, Hooks},
+    lastfm::Scrobbles,
+    library::{filter_torrents_not_in_plex_library, filter_torrents_not_in_torrent_dir},
+    metrics::{self, Counter},
+    progress::{bytes_progress_bar, progress_bar},
+    selection::{self, Strategy, UnknownYear},
+    shutdown,
+    tracker::TrackerClient,
+};
+
+/// Settings of a watch run: where to look for owned albums, how to pick pool
+/// torrents and how to hand them to Transmission. Built with
+/// [`WatchOptions::new`] and the `with_*` methods; everything else defaults to
+/// the values of the `download` command.
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    plex_db: String,
+    torrent_dir: String,
+    download_dir: String,
+    number: usize,
+    transmission: Transmission,
+    use_fl: bool,
+    freeload_only: bool,
+    resume: bool,
+    max_failures: u32,
+    strategy: Strategy,
+    half_life: Option<Duration>,
+    max_per_artist: Option<usize>,
+    max_per_fetch: Option<usize>,
+    min_weight: u32,
+    unknown_year: UnknownYear,
+    covers_dir: Option<PathBuf>,
+    hooks: Hooks,
+    min_seed_time: Option<Duration>,
+    torrent_ids: Vec<u32>,
+    scrobble_boost: Option<f64>,
+    weight_formula: Option<Formula>,
+}
+
+impl WatchOptions {
+    /// Options for a run checking `plex_db` and `torrent_dir` for owned
+    /// albums and downloading into `download_dir`.
+    pub fn new(plex_db: &str, torrent_dir: &str, download_dir: &str) -> Self {
+        Self {
+            plex_db: plex_db.to_string(),
+            torrent_dir: torrent_dir.to_string(),
+            download_dir: download_dir.to_string(),
+            number: 10,
+            transmission: Transmission::default(),
+            use_fl: true,
+            freeload_only: false,
+            resume: false,
+            max_failures: 3,
+            strategy: Strategy::default(),
+            half_life: None,
+            max_per_artist: None,
+            max_per_fetch: None,
+            min_weight: 0,
+            unknown_year: UnknownYear::default(),
+            covers_dir: None,
+            hooks: Hooks::default(),
+            min_seed_time: None,
+            torrent_ids: Vec::new(),
+            scrobble_boost: None,
+            weight_formula: None,
+        }
+    }
+
+    /// Number of torrents to add.
+    pub fn with_number(mut self, number: usize) -> Self {
+        self.number = number;
+        self
+    }
+
+    /// The client torrents are added to.
+    pub fn with_transmission(mut self, transmission: Transmission) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    /// Whether to spend freeleech tokens.
+    pub fn with_freeleech(mut self, use_fl: bool) -> Self {
+        self.use_fl = use_fl;
+        self
+    }
+
+    /// Only add torrents the tracker reports as freeload.
+    pub fn with_freeload_only(mut self, freeload_only: bool) -> Self {
+        self.freeload_only = freeload_only;
+        self
+    }
+
+    /// Continue the last interrupted run instead of selecting new torrents.
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Skip torrents that failed this many times.
+    pub fn with_max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Order pool candidates with `strategy`, decaying weights by `half_life`.
+    pub fn with_strategy(mut self, strategy: Strategy, half_life: Option<Duration>) -> Self {
+        self.strategy = strategy;
+        self.half_life = half_life;
+        self
+    }
+
+    /// Pick at most this many torrents per artist and per source fetch.
+    pub fn with_caps(mut self, per_artist: Option<usize>, per_fetch: Option<usize>) -> Self {
+        self.max_per_artist = per_artist;
+        self.max_per_fetch = per_fetch;
+        self
+    }
+
+    /// Ignore pool torrents with a lower weight unless prioritized.
+    pub fn with_min_weight(mut self, min_weight: u32) -> Self {
+        self.min_weight = min_weight;
+        self
+    }
+
+    /// Where torrents without a known release year go, see [`UnknownYear`].
+    pub fn with_unknown_year(mut self, unknown_year: UnknownYear) -> Self {
+        self.unknown_year = unknown_year;
+        self
+    }
+
+    /// Save the album art of added torrents to this directory.
+    pub fn with_covers(mut self, covers_dir: Option<PathBuf>) -> Self {
+        self.covers_dir = covers_dir;
+        self
+    }
+
+    /// Commands to run for every added torrent.
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// The tracker's minimum seed time, recorded with every added torrent.
+    pub fn with_min_seed_time(mut self, min_seed_time: Option<Duration>) -> Self {
+        self.min_seed_time = min_seed_time;
+        self
+    }
+
+    /// Add exactly these torrents, from the pool or else looked up on the
+    /// tracker, instead of selecting from the pool.
+    pub fn with_torrent_ids(mut self, ids: Vec<u32>) -> Self {
+        self.torrent_ids = ids;
+        self
+    }
+
+    /// Multiply weights by up to 1 + `boost` for the artists and albums with
+    /// the most Last.fm plays stored in the pool.
+    pub fn with_scrobble_boost(mut self, boost: Option<f64>) -> Self {
+        self.scrobble_boost = boost;
+        self
+    }
+
+    /// Order pool candidates by the weights `formula` works out instead of
+    /// the stored ones, boosted and decayed.
+    pub fn with_weight_formula(mut self, formula: Option<Formula>) -> Self {
+        self.weight_formula = formula;
+        self
+    }
+}
+
+/// What a watch run did. In offline mode `added` holds the torrents that would
+/// have been downloaded.
+#[derive(Debug, Default)]
+pub struct WatchReport {
+    pub added: Vec<Torrent>,
+    /// Candidates passed over while scanning, e.g. because they are not freeload
+    pub skipped: Vec<SkippedTorrent>,
+    /// Torrents that could not be downloaded or added to Transmission
+    pub failed: Vec<SkippedTorrent>,
+    /// Size of the added torrents
+    pub total_bytes: u64,
+    /// Freeleech tokens spent on the added torrents
+    pub fl_tokens: usize,
+    /// The run was stopped by a shutdown request before finishing
+    pub interrupted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedTorrent {
+    pub torrent: Torrent,
+    pub reason: String,
+}
+
+impl WatchReport {
+    fn add(&mut self, torrent: &Torrent, used_fl: bool) {
+        metrics::add(Counter::TorrentsAdded, 1);
+        metrics::add(Counter::DownloadedBytes, torrent.size);
+        self.total_bytes += torrent.size;
+        self.fl_tokens += usize::from(used_fl);
+        self.added.push(torrent.clone());
+    }
+
+    fn skip(&mut self, torrent: &Torrent, reason: impl ToString) {
+        self.skipped.push(SkippedTorrent {
+            torrent: torrent.clone(),
+            reason: reason.to_string(),
+        });
+    }
+
+    fn fail(&mut self, torrent: &Torrent, reason: impl ToString) {
+        metrics::add(Counter::TorrentErrors, 1);
+        self.failed.push(SkippedTorrent {
+            torrent: torrent.clone(),
+            reason: reason.to_string(),
+        });
+    }
+}
+
+#[instrument(skip_all, fields(num_torrents = options.number))]
+pub async fn add_new_torrents_for_download(
+    api: &impl TrackerClient,
+    pool_db: &str,
+    options: &WatchOptions,
+) -> Result<WatchReport> {
+    let db = Database::new(pool_db)?;
+    let mut report = WatchReport::default();
+    let torrent_dir = options.torrent_dir.as_str();
+    let resumed = match options.resume {
+        true => Some(db.unfinished_journal()?.ok_or_else(|| {
+            RedmanError::InvalidInput("No interrupted watch run to resume".to_string())
+        })?),
+        false => None,
+    };
+    let torrents = match &resumed {
+        Some((_, entries)) => entries.iter().map(|e| e.torrent.clone()).collect(),
+        None if options.torrent_ids.is_empty() => {
+            select_torrents(api, &db, pool_db, options, &mut report).await?
+        }
+        None => queued_torrents(api, &db, &options.torrent_ids).await?,
+    };
+    if api.is_offline() {
+        warn!("Offline mode: skipping downloads and transmission");
+        report.total_bytes = torrents.iter().map(|t| t.size).sum();
+        report.added = torrents;
+        return Ok(report);
+    }
+    let (run_id, entries) = match resumed {
+        Some((run_id, entries)) => {
+            info!(
+                run_id,
+                remaining = entries.len(),
+                "Resuming interrupted run"
+            );
+            (run_id, entries)
+        }
+        None => db.start_journal(&torrents)?,
+    };
+
+    let cover_client = reqwest::Client::new();
+    let progress = bytes_progress_bar(entries.iter().map(|e| e.torrent.size).sum(), "Downloading");
+    for entry in &entries {
+        let t = &entry.torrent;
+        if shutdown::requested() {
+            report.interrupted = true;
+            break;
+        }
+        progress.set_message(format!("{} - {}", t.artist_names, t.album_name));
+        let downloaded = match (&entry.state, &entry.path) {
+            (JournalState::Downloaded, Some(path)) if path.exists() => Some(path.clone()),
+            _ => None,
+        };
+        let mut retries = 0;
+        let download = loop {
+            if let Some(path) = &downloaded {
+                break Some((path.clone(), false));
+            }
+            let download = tokio::select! {
+                r = download_torrent(api, t.id, torrent_dir, options.use_fl) => r,
+                _ = shutdown::signal() => {
+                    let _ = remove_file(partial_path(torrent_dir, t.id));
+                    warn!(id = t.id, "Download interrupted, removed partial file");
+                    report.interrupted = true;
+                    break None;
+                }
+            };
+            match download {
+                Ok(download) => break Some(download),
+                Err(RedmanError::RateLimited) if retries < MAX_RATE_LIMIT_RETRIES => {
+                    retries += 1;
+                    warn!(id = t.id, "Rate limited, waiting before retry");
+                    progress.set_message("waiting for rate limit");
+                    metrics::add(Counter::RateLimitWaits, 1);
+                    tokio::time::sleep(RATE_LIMIT_BACKOFF).await;
+                }
+                // Problems with the account rather than this torrent end the run
+                Err(e) if e.is_account_error() => return Err(e),
+                Err(e) => {
+                    warn!(id = t.id, "Skipping torrent: {}", e);
+                    db.record_failure(t.id, &e.to_string())?;
+                    db.set_journal_state(run_id, t.id, JournalState::Skipped, None)?;
+                    report.fail(t, &e);
+                    break None;
+                }
+            }
+        };
+        let Some((path, used_fl)) = download else {
+            progress.inc(t.size);
+            continue;
+        };
+        if shutdown::requested() {
+            // A file from an earlier run is still journaled as downloaded,
+            // so it stays for the next resume
+            if downloaded.is_none() {
+                remove_file(&path)?;
+                warn!(
+                    id = t.id,
+                    "Interrupted before adding to transmission, removed {}",
+                    path.display()
+                );
+            }
+            report.interrupted = true;
+            break;
+        }
+        db.set_journal_state(run_id, t.id, JournalState::Downloaded, Some(&path))?;
+        tokio::time::sleep(Duration::from_millis(150)).await; // Do not spam redacted API
+        let hash = fs::read(&path).ok().and_then(|bytes| info_hash(&bytes));
+        // Catches torrents added outside redman; if the client cannot be
+        // asked, adding it will tell
+        if let Some(hash) = &hash
+            && options.transmission.status(hash).is_ok_and(|t| t.is_some())
+        {
+            remove_file(&path)?;
+            info!(id = t.id, "Already in the client, marked as owned");
+            db.set_mark(t.id, Some(Mark::Own))?;
+            db.set_journal_state(run_id, t.id, JournalState::Skipped, None)?;
+            report.skip(
+                t,
+                if used_fl {
+                    "already in the client (freeleech token spent)"
+                } else {
+                    "already in the client"
+                },
+            );
+            progress.inc(t.size);
+            continue;
+        }
+        if let Err(e) = options.transmission.add(&path, &options.download_dir) {
+            remove_file(&path)?;
+            let reason = e.to_string();
+            warn!(id = t.id, "{}", reason);
+            db.record_failure(t.id, &reason)?;
+            db.set_journal_state(run_id, t.id, JournalState::Skipped, None)?;
+            report.fail(t, reason);
+            progress.inc(t.size);
+            continue;
+        }
+        db.set_journal_state(run_id, t.id, JournalState::Added, None)?;
+        db.record_download(t, used_fl, hash.as_deref(), options.min_seed_time)?;
+        info!(id = t.id, artist = %t.artist_names, album = %t.album_name, "Added to transmission");
+        hooks::run(
+            &options.hooks.on_added,
+            &[
+                ("TORRENT_ID", t.id.to_string()),
+                ("ARTIST", t.artist_names.clone()),
+                ("ALBUM", t.album_name.clone()),
+                ("TORRENT_FILE", path.display().to_string()),
+                ("DOWNLOAD_DIR", options.download_dir.clone()),
+            ],
+        );
+        if let Some(dir) = &options.covers_dir
+            && let Err(e) = download_cover(&cover_client, t, dir).await
+        {
+            warn!(id = t.id, "Could not save the cover: {}", e);
+        }
+        progress.inc(t.size);
+        report.add(t, used_fl);
+    }
+    progress.finish_and_clear();
+    Ok(report)
+}
+
+async fn select_torrents(
+    api: &impl TrackerClient,
+    db: &Database,
+    pool_db: &str,
+    options: &WatchOptions,
+    report: &mut WatchReport,
+) -> Result<Vec<Torrent>> {
+    let blacklist = db.failed_torrent_ids(options.max_failures)?;
+    let added = db.added_torrent_ids()?;
+    let mut torrents = get_pool_torrents(pool_db)
+        .and_then(|ts| {
+            filter_torrents_not_in_plex_library(&ts, &options.plex_db, &db.artist_aliases()?)
+        })
+        .and_then(|ts| filter_torrents_not_in_torrent_dir(&ts, &options.torrent_dir))?;
+    let marks = db.get_marks()?;
+    let prioritized = |t: &Torrent| marks.get(&t.id) == Some(&Mark::Prioritize);
+    torrents.retain(|t| {
+        !blacklist.contains(&t.id)
+            && !added.contains(&t.id)
+            && !matches!(marks.get(&t.id), Some(Mark::Skip | Mark::Own))
+            && (t.weight >= options.min_weight || prioritized(t))
+            && (t.year.is_some()
+                || !matches!(options.unknown_year, UnknownYear::Skip)
+                || prioritized(t))
+    });
+    let scrobbles = match options.scrobble_boost {
+        Some(boost) => Some((Scrobbles::load(db)?, boost)).filter(|(s, _)| !s.is_empty()),
+        None => None,
+    };
+    let weight = |t: &Torrent| {
+        let boost = scrobbles
+            .as_ref()
+            .map_or(1.0, |(scrobbles, boost)| scrobbles.factor(t, *boost));
+        match &options.weight_formula {
+            Some(formula) => formula.eval(&Factors::of(t, boost, options.half_life)),
+            None => t.effective_weight(options.half_life) * boost,
+        }
+    };
+
+    torrents = selection::order(torrents, options.strategy, weight);
+    if let UnknownYear::Last = options.unknown_year {
+        torrents = selection::unknown_years_last(torrents);
+    }
+    // Stable sort: prioritized torrents first, each part keeping its order
+    torrents.sort_by_key(|t| !prioritized(t));
+    if let Some(max) = options.max_per_artist {
+        torrents = selection::cap_per_artist(torrents, max);
+    }
+    if let Some(max) = options.max_per_fetch {
+        torrents = selection::cap_per_fetch(torrents, &db.torrent_sources()?, max);
+    }
+
+    if options.freeload_only && !api.is_offline() {
+        torrents = filter_freeload_torrents(&torrents, api, options.number, report).await?;
+    } else {
+        torrents = torrents
+            .into_iter()
+            .take(options.number)
+            .collect::<Vec<_>>();
+    }
+    Ok(torrents)
+}
+
+/// The torrents with `ids` in that order, from the pool or else looked up on
+/// the tracker.
+async fn queued_torrents(
+    api: &impl TrackerClient,
+    db: &Database,
+    ids: &[u32],
+) -> Result<Vec<Torrent>> {
+    let pool: HashMap<u32, Torrent> = db
+        .find_torrents(ids, None)?
+        .into_iter()
+        .map(|t| (t.id, t))
+        .collect();
+    let mut torrents = Vec::new();
+    for &id in ids {
+        if let Some(t) = pool.get(&id) {
+            torrents.push(t.clone());
+            continue;
+        }
+        let info = retry_rate_limited(|| api.torrent_info(id)).await?;
+        let torrent = info.torrent.ok_or_else(|| {
+            RedmanError::InvalidInput(format!("The tracker did not describe torrent {}", id))
+        })?;
+        info!(id, artist = %torrent.artist_names, album = %torrent.album_name, "Found on the tracker");
+        torrents.push(torrent);
+    }
+    Ok(torrents)
+}
+
+/// Whether a download response carries a torrent file rather than an error page.
+fn is_torrent_file(response: &reqwest::Response) -> bool {
+    let content_type = content_type(response);
+    response.status().is_success()
+        && !content_type.contains("json")
+        && !content_type.contains("html")
+}
+
+async fn download_error(response: reqwest::Response) -> RedmanError {
+    let status = response.status();
+    if let Some(e) = RedmanError::from_status(status) {
+        return e;
+    }
+    let content_type = content_type(&response);
+    match response.text().await {
+        Ok(body) if content_type.contains("json") => {
+            match serde_json::from_str::<ApiStatus>(&body) {
+                Ok(s) => RedmanError::from_message(s.error.as_deref().unwrap_or(&s.status)),
+                Err(_) => RedmanError::ApiError(format!(
+                    "Error downloading torrent file ({}): {}",
+                    status,
+                    snippet(&body)
+                )),
+            }
+        }
+        Ok(body) => RedmanError::ApiError(format!(
+            "Error downloading torrent file ({}, {}): {}",
+            status,
+            content_type,
+            snippet(&body)
+        )),
+        Err(e) => RedmanError::ApiError(format!(
+            "Error downloading torrent file ({}): {}",
+            status, e
+        )),
+    }
+}
+
+async fn filter_freeload_torrents(
+    ts: &[Torrent],
+    api: &impl TrackerClient,
+    max_num: usize,
+    report: &mut WatchReport,
+) -> Result<Vec<Torrent>> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    let mut retries = 0;
+    let progress = progress_bar(ts.len() as u64, "Scanning for freeload");
+    while result.len() < max_num && i < ts.len() {
+        let t = &ts[i];
+        progress.set_position(i as u64);
+        progress.set_message(format!("{}/{} found", result.len(), max_num));
+        let info = api.torrent_info(t.id).await;
+        tokio::time::sleep(Duration::from_millis(150)).await; // Do not spam redacted API
+        let info = match info {
+            Ok(info) => info,
+            Err(e) => match e.action() {
+                ErrorAction::Retry if retries < MAX_RATE_LIMIT_RETRIES => {
+                    retries += 1;
+                    warn!(id = t.id, "Rate limited, waiting before retry");
+                    progress.set_message("waiting for rate limit");
+                    metrics::add(Counter::RateLimitWaits, 1);
+                    tokio::time::sleep(RATE_LIMIT_BACKOFF).await;
+                    continue;
+                }
+                ErrorAction::Skip => {
+                    warn!(id = t.id, "Skipping torrent: {}", e);
+                    report.skip(t, e);
+                    i += 1;
+                    continue;
+                }
+                _ => return Err(e),
+            },
+        };
+        retries = 0;
+        if info.is_freeload {
+            result.push(t.clone());
+            info!(id = t.id, "Freeload torrent added");
+        } else {
+            info!(id = t.id, "Skipping non-freeload torrent");
+            report.skip(t, "Not freeload");
+        }
+        i += 1;
+    }
+    progress.finish_and_clear();
+    Ok(result)
+}
+
+#[instrument(skip(api, torrent_dir))]
+async fn download_torrent(
+    api: &impl TrackerClient,
+    torrent_id: u32,
+    torrent_dir: &str,
+    use_fl: bool,
+) -> Result<(PathBuf, bool)> {
+    let response = api.download(torrent_id, use_fl).await?;
+
+    if is_torrent_file(&response) {
+        Ok((
+            write_torrent(torrent_dir, torrent_id, response).await?,
+            use_fl,
+        ))
+    } else {
+        // Only a refused token is worth a download without one; rate limits
+        // and account problems go to the caller to retry or abort on
+        match download_error(response).await {
+            RedmanError::NoFreeleechTokens(_) | RedmanError::ApiError(_) if use_fl => {}
+            e => return Err(e),
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await; // Do not spam redacted API
+        let response_no_fl = api.download(torrent_id, false).await?;
+        if is_torrent_file(&response_no_fl) {
+            Ok((
+                write_torrent(torrent_dir, torrent_id, response_no_fl).await?,
+                false,
+            ))
+        } else {
+            Err(download_error(response_no_fl).await)
+        }
+    }
+}
+
+/// Temporary file a torrent is written to before being moved into place, so an
+/// interrupted download never leaves a truncated .torrent behind.
+fn partial_path(torrent_dir: &str, torrent_id: u32) -> PathBuf {
+    PathBuf::from(torrent_dir).join(format!(".{}.torrent.part", torrent_id))
+}
+
+static FILENAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"filename="([^"]+)""#).expect("valid filename pattern"));
+
+async fn write_torrent(
+    torrent_dir: &str,
+    torrent_id: u32,
+    response: reqwest::Response,
+) -> Result<PathBuf> {
+    let content = response
+        .headers()
+        .get("Content-disposition")
+        .ok_or_else(|| {
+            RedmanError::ApiError("Headers does not contain Content-disposition".to_string())
+        })
+        .and_then(|c| {
+            String::from_utf8(c.as_bytes().to_vec())
+                .map_err(|e| RedmanError::ApiError(format!("Invalid header value: {}", e)))
+        })?;
+    let fname = FILENAME
+        .captures(&content)
+        .and_then(|caps| caps.get(1).map(|n| n.as_str().to_string()))
+        .ok_or_else(|| {
+            RedmanError::ApiError(format!(
+                "Could not parse default torrent file name for {}",
+                content
+            ))
+        })?;
+    let path = PathBuf::from(torrent_dir).join(fname);
+    let partial = partial_path(torrent_dir, torrent_id);
+    let mut file = File::create(&partial).map_err(RedmanError::file(&partial))?;
+    let bytes = response.bytes().await?;
+    let mut content = bytes.as_ref();
+    copy(&mut content, &mut file).map_err(RedmanError::file(&partial))?;
+    fs::rename(&partial, &path).map_err(RedmanError::file(&path))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::tests::Replay;
+
+    const TORRENT: &str = r#"{"status": "success", "response": {
+        "group": {"id": 5, "name": "Album", "year": 2001, "releaseType": 1,
+                  "musicInfo": {"artists": [{"name": "Artist"}]}},
+        "torrent": {"id": 7, "media": "CD", "format": "FLAC", "encoding": "Lossless",
+                    "fileCount": 10, "size": 1000, "isFreeload": false}}}"#;
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_rate_limited_lookups() {
+        let api = Replay::client(&[
+            (
+                200,
+                r#"{"status": "failure", "error": "Rate limit exceeded"}"#,
+            ),
+            (429, ""),
+            (200, TORRENT),
+        ]);
+        let db = Database::new(":memory:").unwrap();
+        let torrents = queued_torrents(&api, &db, &[7]).await.unwrap();
+        assert_eq!(torrents.len(), 1);
+        assert_eq!(torrents[0].id, 7);
+        assert_eq!(torrents[0].album_name, "Album");
+    }
+
+    #[tokio::test]
+    async fn downloads_without_token_only_when_refused() {
+        let api = Replay::client(&[
+            (429, ""),
+            (
+                200,
+                r#"{"status": "failure", "error": "No freeleech tokens left"}"#,
+            ),
+            (401, ""),
+        ]);
+        let dir = std::env::temp_dir();
+        let dir = dir.to_str().unwrap();
+        assert!(matches!(
+            download_torrent(&api, 7, dir, true).await,
+            Err(RedmanError::RateLimited)
+        ));
+        assert!(matches!(
+            download_torrent(&api, 7, dir, true).await,
+            Err(RedmanError::BadApiKey)
+        ));
+        let urls = api.transport().urls.lock().unwrap();
+        assert!(urls[0].ends_with("usetoken=1"));
+        assert!(urls[1].ends_with("usetoken=1"));
+        assert!(urls[2].ends_with("usetoken=0"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_the_retries() {
+        let limited = [(429, ""); MAX_RATE_LIMIT_RETRIES as usize + 1];
+        let api = Replay::client(&limited);
+        let db = Database::new(":memory:").unwrap();
+        assert!(matches!(
+            queued_torrents(&api, &db, &[7]).await,
+            Err(RedmanError::RateLimited)
+        ));
+    }
+}